@@ -0,0 +1,18 @@
+use std::path::PathBuf;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Path of the unix socket accepting control commands
+    pub socket: PathBuf,
+
+    /// User IDs permitted to send commands besides root and the user running the server
+    #[serde(default)]
+    pub uids: Vec<u32>,
+
+    /// Group ID owning the socket, whose members are permitted as well, otherwise only the owner can open the socket
+    pub gid: Option<u32>,
+
+    /// Directory backups are written to and restored from, the backup commands are refused if missing
+    pub backups: Option<PathBuf>,
+}
\ No newline at end of file
@@ -0,0 +1,250 @@
+use std::future::Future;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use futures::{SinkExt, StreamExt};
+use itertools::Itertools;
+use tokio::net::{UnixListener, UnixStream};
+use tokio_util::codec::{Framed, LinesCodec};
+use tracing::{debug, error, info, warn};
+
+use crate::database::SharedDatabase;
+use crate::metrics::Counters;
+
+pub use self::config::Config;
+pub use self::sessions::{SessionHandle, Sessions};
+
+mod config;
+mod sessions;
+
+/// Runtime state shared between the protocol servers and the admin interface
+#[derive(Debug)]
+pub struct Control {
+    maintenance: AtomicBool,
+
+    sessions: Sessions,
+
+    counters: Counters,
+
+    started: Instant,
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        return Self {
+            maintenance: AtomicBool::default(),
+            sessions: Sessions::default(),
+            counters: Counters::default(),
+            started: Instant::now(),
+        };
+    }
+}
+
+impl Control {
+    /// While in maintenance mode, new authentications are refused but existing connections are kept
+    pub fn maintenance(&self) -> bool {
+        return self.maintenance.load(Ordering::Relaxed);
+    }
+
+    pub fn set_maintenance(&self, maintenance: bool) {
+        self.maintenance.store(maintenance, Ordering::Relaxed);
+    }
+
+    /// Active LDAP client connections
+    pub fn sessions(&self) -> &Sessions {
+        return &self.sessions;
+    }
+
+    /// Event counters exported as metrics
+    pub fn counters(&self) -> &Counters {
+        return &self.counters;
+    }
+
+    /// Time since the server has been started
+    pub fn uptime(&self) -> Duration {
+        return self.started.elapsed();
+    }
+}
+
+/// Resolves a path given to the backup commands, which must stay within the backup directory
+fn backup_path(backups: Option<&Path>, name: &str) -> Result<PathBuf, String> {
+    let backups = backups.ok_or_else(|| "no backup directory configured".to_string())?;
+
+    let name = Path::new(name);
+    if !name.components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(format!("invalid backup name: {:?}", name));
+    }
+
+    return Ok(backups.join(name));
+}
+
+async fn execute(control: &Control, database: &SharedDatabase, backups: Option<&Path>, command: &str) -> Result<String, String> {
+    let mut args = command.split_whitespace();
+
+    return match (args.next(), args.next(), args.next()) {
+        (Some("status"), None, None) => Ok(format!("maintenance: {}", if control.maintenance() { "on" } else { "off" })),
+
+        (Some("report"), None, None) => Ok(database.snapshot().report().to_string()),
+
+        (Some("sessions"), None, None) => Ok(control.sessions().list().into_iter()
+            .map(|(id, session)| format!("{} {} {} operations={} idle={}s",
+                                         id,
+                                         session.peer,
+                                         session.bind_dn.as_deref().unwrap_or("-"),
+                                         session.operations,
+                                         session.last_activity.elapsed().as_secs()))
+            .join("\n")),
+
+        (Some("kill"), Some(id), None) => match id.parse() {
+            Ok(id) if control.sessions().kill(id) => {
+                warn!("Killing session {}", id);
+                Ok("ok".to_string())
+            }
+            Ok(id) => Err(format!("no such session: {}", id)),
+            Err(err) => Err(format!("invalid session: {}", err)),
+        },
+
+        (Some("backup"), None, None) => match backups {
+            Some(dir) => match database.snapshot().backup(dir).await {
+                Ok(path) => {
+                    info!("Written backup to {:?}", path);
+                    Ok(path.file_name().unwrap_or_default().to_string_lossy().into_owned())
+                }
+                Err(err) => Err(format!("{:#}", err)),
+            },
+            None => Err("no backup directory configured".to_string()),
+        },
+
+        (Some("restore"), Some(_), None) if database.is_read_only() => Err("database is read-only".to_string()),
+
+        (Some("restore"), Some(name), None) => match backup_path(backups, name) {
+            Ok(file) => match database.write().await.restore(&file).await {
+                Ok(changed) => {
+                    warn!("Restored backup from {:?}", file);
+                    Ok(format!("changed {} entries", changed))
+                }
+                Err(err) => Err(format!("{:#}", err)),
+            },
+            Err(err) => Err(err),
+        },
+
+        (Some("maintenance"), Some("on"), None) => {
+            warn!("Entering maintenance mode");
+            control.set_maintenance(true);
+            Ok("ok".to_string())
+        }
+
+        (Some("maintenance"), Some("off"), None) => {
+            warn!("Leaving maintenance mode");
+            control.set_maintenance(false);
+            Ok("ok".to_string())
+        }
+
+        _ => Err(format!("unknown command: {}", command)),
+    };
+}
+
+async fn serve_client(socket: UnixStream,
+                      control: Arc<Control>,
+                      database: Arc<SharedDatabase>,
+                      backups: Option<PathBuf>) -> Result<()> {
+    let mut lines = Framed::new(socket, LinesCodec::new());
+
+    while let Some(line) = lines.next().await {
+        let line = line.context("Invalid admin command")?;
+        debug!("Got admin command: {:?}", line);
+
+        let response = match execute(&control, &database, backups.as_deref(), &line).await {
+            Ok(response) => response,
+            Err(err) => format!("error: {}", err),
+        };
+
+        lines.send(response).await?;
+    }
+
+    return Ok(());
+}
+
+pub async fn serve(config: Option<Config>,
+                   control: Arc<Control>,
+                   database: Arc<SharedDatabase>,
+                   shutdown: impl Future) -> Result<()> {
+    let config = if let Some(config) = config { config } else {
+        return Ok(());
+    };
+
+    // Remove stale socket left over from previous runs
+    if config.socket.exists() {
+        tokio::fs::remove_file(&config.socket).await
+            .with_context(|| format!("Removing stale socket: {:?}", config.socket))?;
+    }
+
+    let listener = UnixListener::bind(&config.socket)
+        .with_context(|| format!("Listening on {:?}", config.socket))?;
+
+    // The socket grants full control over the server, so it is only opened to the owner and the configured group
+    if let Some(gid) = config.gid {
+        std::os::unix::fs::chown(&config.socket, None, Some(gid))
+            .with_context(|| format!("Changing group of socket: {:?}", config.socket))?;
+    }
+    let mode = if config.gid.is_some() { 0o660 } else { 0o600 };
+    tokio::fs::set_permissions(&config.socket, std::fs::Permissions::from_mode(mode)).await
+        .with_context(|| format!("Changing permissions of socket: {:?}", config.socket))?;
+
+    let owner = tokio::fs::metadata(&config.socket).await
+        .with_context(|| format!("Reading owner of socket: {:?}", config.socket))?
+        .uid();
+
+    let serve = async {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => match socket.peer_cred() {
+                    Ok(peer) if peer.uid() == 0
+                        || peer.uid() == owner
+                        || config.uids.contains(&peer.uid())
+                        || config.gid == Some(peer.gid()) => {
+                        tokio::spawn(serve_client(socket, control.clone(), database.clone(), config.backups.clone()));
+                    }
+                    Ok(peer) => warn!("Refusing admin connection from uid {}", peer.uid()),
+                    Err(err) => error!("Failed to identify admin client: {}", err),
+                },
+
+                Err(err) => {
+                    error!("Failed to accept admin connection: {}", err);
+                }
+            }
+        }
+    };
+
+    tokio::select! {
+         _ = shutdown => {
+            info!("Admin interface is shutting down");
+            return Ok(());
+        }
+
+        res = serve => {
+            return res;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backup_path() {
+        let backups = Path::new("/var/backups/concess");
+
+        assert_eq!(backup_path(Some(backups), "concess-20250131T120000Z.yaml"), Ok(backups.join("concess-20250131T120000Z.yaml")));
+
+        assert!(backup_path(Some(backups), "/etc/shadow").is_err());
+        assert!(backup_path(Some(backups), "../../../etc/shadow").is_err());
+        assert!(backup_path(Some(backups), "./concess.yaml").is_err());
+        assert!(backup_path(None, "concess-20250131T120000Z.yaml").is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use tokio_util::sync::CancellationToken;
+
+use crate::ldap::Peer;
+
+use super::Control;
+
+/// Information about an active client connection
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub peer: Peer,
+    pub bind_dn: Option<String>,
+    pub operations: u64,
+    pub last_activity: Instant,
+
+    kill: CancellationToken,
+}
+
+/// Registry of active client connections
+#[derive(Debug, Default)]
+pub struct Sessions {
+    next_id: AtomicU64,
+    sessions: Mutex<BTreeMap<u64, SessionInfo>>,
+}
+
+/// Registration of a session which is removed from the registry when dropped
+pub struct SessionHandle {
+    pub id: u64,
+    pub kill: CancellationToken,
+
+    control: Arc<Control>,
+}
+
+impl Sessions {
+    pub fn list(&self) -> Vec<(u64, SessionInfo)> {
+        return self.sessions.lock().unwrap()
+            .iter()
+            .map(|(id, info)| (*id, info.clone()))
+            .collect();
+    }
+
+    /// Number of sessions other than the given one which are bound as the given DN
+    pub fn bound_as(&self, bind_dn: &str, except: u64) -> usize {
+        return self.sessions.lock().unwrap()
+            .iter()
+            .filter(|(id, info)| **id != except && info.bind_dn.as_deref() == Some(bind_dn))
+            .count();
+    }
+
+    /// Requests the session to terminate, returns `false` if there is no such session
+    pub fn kill(&self, id: u64) -> bool {
+        return match self.sessions.lock().unwrap().get(&id) {
+            Some(info) => {
+                info.kill.cancel();
+                true
+            }
+            None => false,
+        };
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut SessionInfo)) {
+        if let Some(info) = self.sessions.lock().unwrap().get_mut(&id) {
+            f(info);
+        }
+    }
+}
+
+impl SessionHandle {
+    pub fn register(control: Arc<Control>, peer: Peer) -> Self {
+        let id = control.sessions.next_id.fetch_add(1, Ordering::Relaxed);
+        let kill = CancellationToken::new();
+
+        control.sessions.sessions.lock().unwrap().insert(id, SessionInfo {
+            peer,
+            bind_dn: None,
+            operations: 0,
+            last_activity: Instant::now(),
+            kill: kill.clone(),
+        });
+
+        return Self {
+            id,
+            kill,
+            control,
+        };
+    }
+
+    /// Records a processed operation
+    pub fn operation(&self) {
+        self.control.sessions.update(self.id, |info| {
+            info.operations += 1;
+            info.last_activity = Instant::now();
+        });
+    }
+
+    pub fn bound(&self, bind_dn: Option<String>) {
+        self.control.sessions.update(self.id, |info| info.bind_dn = bind_dn);
+    }
+}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        self.control.sessions.sessions.lock().unwrap().remove(&self.id);
+    }
+}
\ No newline at end of file
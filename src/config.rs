@@ -4,15 +4,32 @@ use anyhow::Context;
 use anyhow::Result;
 use serde::Deserialize;
 
+use crate::admin;
+use crate::database;
 use crate::ldap;
+use crate::messages;
+use crate::metrics;
 use crate::radius;
+use crate::replication;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub data: PathBuf,
 
+    #[serde(default)]
+    pub database: database::Config,
+
     pub ldap: ldap::Config,
     pub radius: radius::Config,
+
+    pub admin: Option<admin::Config>,
+
+    pub metrics: Option<metrics::Config>,
+
+    pub replication: Option<replication::Config>,
+
+    #[serde(default)]
+    pub messages: messages::Config,
 }
 
 impl Config {
@@ -0,0 +1,126 @@
+use std::fmt;
+
+use tracing::info;
+
+use super::{GroupEntity, UserEntity};
+
+/// Tracing target for changes made to the database
+pub const AUDIT: &str = "audit";
+
+/// Identity on whose behalf a change is made
+#[derive(Debug, Clone)]
+pub struct Actor {
+    pub protocol: &'static str,
+
+    /// Authenticated identity or `anonymous`
+    pub identity: String,
+
+    /// Address of the client
+    pub source: String,
+}
+
+impl fmt::Display for Actor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{} via {} from {}", self.identity, self.protocol, self.source);
+    }
+}
+
+/// Values of all audited attributes and whether the value is a secret
+fn attributes(user: &UserEntity) -> Vec<(&'static str, String, bool)> {
+    return vec![
+        ("password", user.password.to_string(), true),
+        ("first_name", user.first_name.clone(), false),
+        ("last_name", user.last_name.clone(), false),
+        ("mail", user.mail.join(", "), false),
+        ("mail_aliases", user.mail_aliases.join(", "), false),
+        ("groups", user.groups.join(","), false),
+        ("canary", user.canary.to_string(), false),
+        ("posix", user.posix.as_ref()
+            .map(|posix| format!("{}:{}:{}:{}", posix.uid_number, posix.gid_number, posix.home_directory, posix.login_shell.as_deref().unwrap_or_default()))
+            .unwrap_or_default(), false),
+        ("ssh_keys", user.ssh_keys.join(", "), false),
+        ("password_changed", user.password_changed.map(|date| date.to_string()).unwrap_or_default(), false),
+        ("password_max_age", user.password_max_age.map(|days| days.to_string()).unwrap_or_default(), false),
+        ("expires", user.expires.map(|date| date.to_string()).unwrap_or_default(), false),
+        ("disabled", user.disabled.to_string(), false),
+        ("locked_until", user.locked_until.map(|time| time.to_rfc3339()).unwrap_or_default(), false),
+        ("not_before", user.not_before.map(|time| time.to_rfc3339()).unwrap_or_default(), false),
+        ("not_after", user.not_after.map(|time| time.to_rfc3339()).unwrap_or_default(), false),
+        ("totp_secret", user.totp_secret.clone().unwrap_or_default(), true),
+        ("eap_password", user.eap_password.clone().unwrap_or_default(), true),
+        ("verifier", user.verifier.clone().unwrap_or_default(), false),
+    ];
+}
+
+/// Records all attributes of a user which differ between both states, secrets are only recorded as changed
+pub fn user_changed(actor: &Actor, name: &str, before: Option<&UserEntity>, after: Option<&UserEntity>) {
+    let before = before.map(attributes);
+    let after = after.map(attributes);
+
+    // Both states list the same attributes in the same order
+    let attributes = match (&before, &after) {
+        (Some(attributes), _) | (None, Some(attributes)) => attributes,
+        (None, None) => return,
+    };
+
+    for (i, &(attribute, _, secret)) in attributes.iter().enumerate() {
+        let old = before.as_ref().map(|before| &before[i].1);
+        let new = after.as_ref().map(|after| &after[i].1);
+
+        if old == new {
+            continue;
+        }
+
+        let redact = |value: Option<&String>| match value {
+            Some(_) if secret => "<redacted>".to_string(),
+            Some(value) => format!("{:?}", value),
+            None => "-".to_string(),
+        };
+
+        info!(target: AUDIT, actor = %actor, user = name, attribute, before = %redact(old), after = %redact(new), "Attribute changed");
+    }
+}
+
+/// Values of all audited attributes of a group
+fn group_attributes(group: &GroupEntity) -> Vec<(&'static str, String)> {
+    return vec![
+        ("members", group.members.join(",")),
+        ("description", group.description.clone().unwrap_or_default()),
+        ("owner", group.owner.clone().unwrap_or_default()),
+        ("mail", group.mail.join(", ")),
+        ("managers", group.managers.join(",")),
+        ("gid_number", group.gid_number.map(|gid| gid.to_string()).unwrap_or_default()),
+    ];
+}
+
+/// Records all attributes of a group file which differ between both states
+pub fn group_changed(actor: &Actor, name: &str, before: Option<&GroupEntity>, after: Option<&GroupEntity>) {
+    let before = before.map(group_attributes);
+    let after = after.map(group_attributes);
+
+    let attributes = match (&before, &after) {
+        (Some(attributes), _) | (None, Some(attributes)) => attributes,
+        (None, None) => return,
+    };
+
+    for (i, &(attribute, _)) in attributes.iter().enumerate() {
+        let old = before.as_ref().map(|before| &before[i].1);
+        let new = after.as_ref().map(|after| &after[i].1);
+
+        if old == new {
+            continue;
+        }
+
+        let format = |value: Option<&String>| value.map_or_else(|| "-".to_string(), |value| format!("{:?}", value));
+
+        info!(target: AUDIT, actor = %actor, group = name, attribute, before = %format(old), after = %format(new), "Attribute changed");
+    }
+}
+
+pub fn group_renamed(actor: &Actor, name: &str, new_name: &str) {
+    info!(target: AUDIT, actor = %actor, group = name, attribute = "name", before = %format!("{:?}", name), after = %format!("{:?}", new_name), "Attribute changed");
+}
+
+pub fn user_renamed(actor: &Actor, name: &str, new_name: &str) {
+    info!(target: AUDIT, actor = %actor, user = name, attribute = "name", before = %format!("{:?}", name), after = %format!("{:?}", new_name), "Attribute changed");
+}
\ No newline at end of file
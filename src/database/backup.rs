@@ -0,0 +1,78 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use super::{Actor, Database, Replica};
+use super::crypt::Crypt;
+
+/// Consistent copy of all entities in a single file
+#[derive(Deserialize, Serialize)]
+struct Backup {
+    created: DateTime<Utc>,
+
+    /// Sequence number of the snapshot the copy has been taken from
+    sequence: u64,
+
+    replica: Replica,
+}
+
+impl Database {
+    /// Writes all entities to a file named by the current time in the given directory and returns its path
+    ///
+    /// References to secrets are kept, while binary values are given inline. The file is encrypted if keys are
+    /// configured for the data directory.
+    pub async fn backup(&self, dir: &Path) -> Result<PathBuf> {
+        let created = Utc::now();
+
+        let data = serde_yaml::to_string(&Backup {
+            created,
+            sequence: self.sequence,
+            replica: self.replica(true),
+        }).context("Serializing backup")?.into_bytes();
+
+        let data = match &self.crypt {
+            Some(crypt) => crypt.encrypt(&data)
+                .context("Encrypting backup")?,
+            None => data,
+        };
+
+        let path = dir.join(format!("concess-{}.yaml", created.format("%Y%m%dT%H%M%SZ")));
+        let temp = path.with_extension("yaml.tmp");
+
+        let mut file = tokio::fs::File::create(&temp).await
+            .with_context(|| format!("Creating backup: {:?}", temp))?;
+        file.write_all(&data).await
+            .with_context(|| format!("Writing backup: {:?}", temp))?;
+        file.sync_all().await
+            .with_context(|| format!("Syncing backup: {:?}", temp))?;
+
+        tokio::fs::rename(&temp, &path).await
+            .with_context(|| format!("Replacing backup: {:?}", path))?;
+
+        return Ok(path);
+    }
+
+    /// Replaces all entities by the ones of a backup, persisting only the entities which have changed
+    ///
+    /// Returns the number of changed entities.
+    pub async fn restore(&mut self, path: &Path) -> Result<usize> {
+        let data = tokio::fs::read(path).await
+            .with_context(|| format!("Reading backup: {:?}", path))?;
+        let data = Crypt::open(self.crypt.as_deref(), data)
+            .with_context(|| format!("Decrypting backup: {:?}", path))?;
+
+        let backup: Backup = serde_yaml::from_slice(&data)
+            .with_context(|| format!("Parsing backup: {:?}", path))?;
+
+        let actor = Actor {
+            protocol: "backup",
+            identity: "restore".to_owned(),
+            source: path.display().to_string(),
+        };
+
+        return self.replicate(&actor, backup.replica).await;
+    }
+}
\ No newline at end of file
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use serde::{Deserialize, Deserializer};
+
+use super::verifier;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub normalize: Normalize,
+
+    /// Reload the database on changes in the data directory
+    pub reload: Option<Reload>,
+
+    /// Refuse to start with and to reload a database having skipped files, duplicates or problems
+    #[serde(default)]
+    pub strict: bool,
+
+    /// Rewrite files of older versions in the current version after loading them
+    #[serde(default)]
+    pub migrate: bool,
+
+    #[serde(default)]
+    pub hashing: Hashing,
+
+    /// Values of attributes computed from templates for users not setting the attribute themselves
+    #[serde(default)]
+    pub derived: BTreeMap<String, NameTemplate>,
+
+    #[serde(default)]
+    pub encryption: Encryption,
+
+    /// Verifier of the passwords of users not selecting one, the stored hash if unset
+    pub verifier: Option<String>,
+
+    /// Alternative verifiers of passwords by name
+    #[serde(default)]
+    pub verifiers: BTreeMap<String, verifier::Config>,
+}
+
+impl Config {
+    /// Name of the verifier used for a user selecting the given one if any
+    pub fn verifier<'a>(&'a self, selected: Option<&'a str>) -> &'a str {
+        return selected
+            .or(self.verifier.as_deref())
+            .unwrap_or(verifier::HASH);
+    }
+}
+
+/// Keys of entity files encrypted with age
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Encryption {
+    /// File holding the identity used to decrypt, taken from the `CONCESS_AGE_IDENTITY` environment variable if unset
+    pub identity: Option<PathBuf>,
+
+    /// Further recipients written files are encrypted for in addition to the identity, e.g. the keys of administrators
+    #[serde(default)]
+    pub recipients: Vec<String>,
+}
+
+/// Parameters of the Argon2 hashes made for new passwords
+#[derive(Debug, Clone, Deserialize)]
+pub struct Hashing {
+    /// Memory size in KiB
+    #[serde(default = "Hashing::default_memory")]
+    pub memory: u32,
+
+    /// Number of passes over the memory
+    #[serde(default = "Hashing::default_iterations")]
+    pub iterations: u32,
+
+    /// Degree of parallelism
+    #[serde(default = "Hashing::default_parallelism")]
+    pub parallelism: u32,
+
+    /// Re-hash passwords of users with weaker hashes using these parameters whenever they authenticate successfully
+    #[serde(default)]
+    pub upgrade: bool,
+}
+
+impl Hashing {
+    fn default_memory() -> u32 {
+        return Params::DEFAULT_M_COST;
+    }
+
+    fn default_iterations() -> u32 {
+        return Params::DEFAULT_T_COST;
+    }
+
+    fn default_parallelism() -> u32 {
+        return Params::DEFAULT_P_COST;
+    }
+
+    pub fn argon2(&self) -> argon2::Result<Argon2<'static>> {
+        let params = Params::new(self.memory, self.iterations, self.parallelism, None)?;
+        return Ok(Argon2::new(Algorithm::default(), Version::default(), params));
+    }
+}
+
+impl Default for Hashing {
+    fn default() -> Self {
+        return Self {
+            memory: Self::default_memory(),
+            iterations: Self::default_iterations(),
+            parallelism: Self::default_parallelism(),
+            upgrade: false,
+        };
+    }
+}
+
+/// Timings used for collapsing bursts of file events into a single reload
+#[derive(Debug, Clone, Deserialize)]
+pub struct Reload {
+    /// Time in milliseconds without file events before reloading
+    #[serde(default = "Reload::default_quiet_period")]
+    pub quiet_period: u64,
+
+    /// Upper bound in milliseconds of a random delay added to the quiet period
+    #[serde(default = "Reload::default_jitter")]
+    pub jitter: u64,
+
+    /// Time in milliseconds after which a reload happens even if file events keep coming in
+    #[serde(default = "Reload::default_max_delay")]
+    pub max_delay: u64,
+}
+
+impl Reload {
+    fn default_quiet_period() -> u64 {
+        return 500;
+    }
+
+    fn default_jitter() -> u64 {
+        return 250;
+    }
+
+    fn default_max_delay() -> u64 {
+        return 10000;
+    }
+}
+
+/// Rules applied to entity values while loading the database
+#[derive(Debug, Clone, Deserialize)]
+pub struct Normalize {
+    /// Strip leading and trailing whitespace from all values
+    #[serde(default = "enabled")]
+    pub trim: bool,
+
+    /// Convert mail addresses to lowercase
+    #[serde(default = "enabled")]
+    pub lowercase_mail: bool,
+
+    /// Convert group names to lowercase, which merges groups only differing in case
+    #[serde(default)]
+    pub lowercase_groups: bool,
+}
+
+impl Default for Normalize {
+    fn default() -> Self {
+        return Self {
+            trim: true,
+            lowercase_mail: true,
+            lowercase_groups: false,
+        };
+    }
+}
+
+fn enabled() -> bool {
+    return true;
+}
+
+/// Template of a name of a user referring to `{name}`, `{first_name}` and `{last_name}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate(String);
+
+impl NameTemplate {
+    const FIELDS: &'static [&'static str] = &["name", "first_name", "last_name"];
+
+    pub fn render(&self, name: &str, first_name: &str, last_name: &str) -> String {
+        return self.0
+            .replace("{name}", name)
+            .replace("{first_name}", first_name)
+            .replace("{last_name}", last_name)
+            .trim()
+            .to_owned();
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let end = start + rest[start..].find('}')
+                .ok_or_else(|| format!("Unterminated field in name template: {}", s))?;
+
+            let field = &rest[start + 1..end];
+            if !Self::FIELDS.contains(&field) {
+                return Err(format!("Unknown field in name template: {}", field));
+            }
+
+            rest = &rest[end + 1..];
+        }
+
+        return Ok(Self(s.to_owned()));
+    }
+}
+
+impl<'de> Deserialize<'de> for NameTemplate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de> {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        return Self::from_str(&s)
+            .map_err(serde::de::Error::custom);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_name_template() {
+        let template = NameTemplate::from_str("{last_name}, {first_name}").unwrap();
+        assert_eq!(template.render("brain", "Rob", "Paulsen"), "Paulsen, Rob");
+
+        assert!(NameTemplate::from_str("{first_name} {surname}").is_err());
+        assert!(NameTemplate::from_str("{first_name").is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,109 @@
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::x25519::{Identity, Recipient};
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::config::Encryption;
+
+/// Environment variable holding the identity if no file is configured
+const IDENTITY_VAR: &str = "CONCESS_AGE_IDENTITY";
+
+/// Leading bytes of age files in the binary and the armored format
+const MAGIC: &[&[u8]] = &[b"age-encryption.org/", b"-----BEGIN AGE ENCRYPTED FILE-----"];
+
+/// Keys used to decrypt entity files encrypted with age and to encrypt written files
+pub struct Crypt {
+    identity: Identity,
+    recipients: Vec<Recipient>,
+}
+
+impl Crypt {
+    /// Loads the configured identity, files are neither decrypted nor encrypted if there is none
+    pub async fn load(config: &Encryption) -> Result<Option<Self>> {
+        let identity = match &config.identity {
+            Some(path) => tokio::fs::read_to_string(path).await
+                .with_context(|| format!("Reading identity: {:?}", path))?,
+            None => match std::env::var(IDENTITY_VAR) {
+                Ok(identity) => identity,
+                Err(_) => return Ok(None),
+            },
+        };
+
+        // Identity files written by age-keygen contain comments
+        let identity = identity.lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .context("No identity found")?;
+        let identity = Identity::from_str(identity)
+            .map_err(|err| anyhow!("Invalid identity: {}", err))?;
+
+        let mut recipients = vec![identity.to_public()];
+        for recipient in config.recipients.iter() {
+            recipients.push(Recipient::from_str(recipient)
+                .map_err(|err| anyhow!("Invalid recipient {}: {}", recipient, err))?);
+        }
+
+        return Ok(Some(Self { identity, recipients }));
+    }
+
+    pub fn is_encrypted(data: &[u8]) -> bool {
+        return MAGIC.iter().any(|magic| data.starts_with(magic));
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let decryptor = age::Decryptor::new_buffered(ArmoredReader::new(data))?;
+
+        let mut plain = Vec::new();
+        decryptor.decrypt(std::iter::once(&self.identity as _))?
+            .read_to_end(&mut plain)?;
+
+        return Ok(plain);
+    }
+
+    /// Encrypts for all recipients using the armored format, which is friendlier to version control
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let encryptor = age::Encryptor::with_recipients(self.recipients.iter().map(|recipient| recipient as _))?;
+
+        let mut encrypted = Vec::new();
+        let mut writer = encryptor.wrap_output(ArmoredWriter::wrap_output(&mut encrypted, Format::AsciiArmor)?)?;
+        writer.write_all(data)?;
+        writer.finish()?.finish()?;
+
+        return Ok(encrypted);
+    }
+
+    /// Decrypts the data if encrypted, failing if no identity is given
+    pub fn open(crypt: Option<&Self>, data: Vec<u8>) -> Result<Vec<u8>> {
+        if !Self::is_encrypted(&data) {
+            return Ok(data);
+        }
+
+        return match crypt {
+            Some(crypt) => crypt.decrypt(&data),
+            None => bail!("Encrypted but no identity configured"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let crypt = Crypt {
+            identity: Identity::generate(),
+            recipients: vec![],
+        };
+        let crypt = Crypt { recipients: vec![crypt.identity.to_public()], ..crypt };
+
+        let encrypted = crypt.encrypt(b"first_name: 'Rob'").unwrap();
+        assert!(Crypt::is_encrypted(&encrypted));
+        assert_eq!(Crypt::open(Some(&crypt), encrypted.clone()).unwrap(), b"first_name: 'Rob'");
+        assert!(Crypt::open(None, encrypted).is_err());
+
+        assert_eq!(Crypt::open(None, b"first_name: 'Rob'".to_vec()).unwrap(), b"first_name: 'Rob'");
+    }
+}
\ No newline at end of file
@@ -1,24 +1,382 @@
-use password_hash::{Encoding, PasswordHashString};
-use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 
-#[derive(Debug, Clone, Deserialize)]
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, NaiveDate, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_with::{OneOrMany, serde_as};
+use uuid::Uuid;
+
+use super::config::Normalize;
+use super::password::PasswordHash;
+use super::store::Versioned;
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct UserEntity {
-    #[serde(deserialize_with = "deserialize_password")]
-    pub password: PasswordHashString,
+    pub password: Password,
 
     pub first_name: String,
     pub last_name: String,
 
-    pub mail: String,
+    /// Mail addresses of the user, given as a single or multiple values
+    #[serde_as(as = "OneOrMany<_>")]
+    #[serde(default)]
+    pub mail: Vec<String>,
+
+    /// Further addresses the user receives mail on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mail_aliases: Vec<String>,
 
     pub groups: Vec<String>,
+
+    /// Monitoring probe account which is excluded from statistics and logged separately
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub canary: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub posix: Option<PosixAccount>,
+
+    /// Public keys permitted to log in via SSH
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ssh_keys: Vec<String>,
+
+    /// Day of the last password change, maintained whenever the password is changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_changed: Option<NaiveDate>,
+
+    /// Number of days after the last change the password must be changed again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_max_age: Option<u32>,
+
+    /// Day on which the account expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<NaiveDate>,
+
+    /// Suspends the account, the user stays visible but is not able to authenticate
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled: bool,
+
+    /// Time until which the user is not able to authenticate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_until: Option<DateTime<Utc>>,
+
+    /// Time from which on the user is able to authenticate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<DateTime<Utc>>,
+
+    /// Time from which on the user is no longer able to authenticate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<DateTime<Utc>>,
+
+    /// Base32 encoded secret of the time-based one-time passwords appended to the password where required
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_secret: Option<String>,
+
+    /// Password in clear text for EAP-MD5 via RADIUS, which can not be verified against the hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eap_password: Option<String>,
+
+    /// Name of the verifier checking the password instead of the configured one, `hash` for the stored hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verifier: Option<String>,
+
+    /// Additional attributes exposed verbatim, each having a single or multiple values
+    #[serde_as(as = "BTreeMap<_, OneOrMany<_>>")]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, Vec<String>>,
+
+    /// Additional attributes with binary values like `jpegPhoto` or `userCertificate`
+    #[serde_as(as = "BTreeMap<_, OneOrMany<_>>")]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub binary_attributes: BTreeMap<String, Vec<BinaryValue>>,
+
+    /// Stable identifier of the user, derived from the name unless set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
+}
+
+/// Account information used by Linux hosts to resolve the user
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PosixAccount {
+    pub uid_number: u32,
+    pub gid_number: u32,
+
+    pub home_directory: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub login_shell: Option<String>,
+}
+
+/// Definition of a group whose members are listed here in addition to those declared by the users
+#[serde_as]
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct GroupEntity {
+    /// Users which are members of the group in addition to the users declaring the group
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// DN of the owner of the group relative to the base DN, exposed in addition to the managers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+
+    /// Mail addresses of the group, given as a single or multiple values
+    #[serde_as(as = "OneOrMany<_>")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mail: Vec<String>,
+
+    /// Users permitted to add and remove members of this group
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub managers: Vec<String>,
+
+    /// Numeric ID used by Linux hosts to resolve the group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid_number: Option<u32>,
+}
+
+/// Account of an application binding to the directory, kept apart from human users
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ServiceEntity {
+    pub password: Password,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// Permits the service to authenticate via RADIUS
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub radius: bool,
+}
+
+/// Additional name of a user or group placed in another subtree
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct AliasEntity {
+    /// DN of the entry containing the alias relative to the base DN
+    pub parent: String,
+
+    /// DN of the aliased entry relative to the base DN
+    pub target: String,
+}
+
+/// Password hash given inline or read from a secret outside of the data directory, e.g. mounted by a secrets manager
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "PasswordSource", into = "PasswordSource")]
+pub struct Password {
+    pub hash: PasswordHash,
+
+    /// Reference the hash has been read from, which is written instead of the hash
+    pub secret: Option<Secret>,
+}
+
+/// Reference to a secret holding a password hash
+#[derive(Debug, Clone, PartialEq)]
+pub enum Secret {
+    File(PathBuf),
+    Env(String),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum PasswordSource {
+    Hash(String),
+    Secret(SecretSource),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct SecretSource {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file: Option<PathBuf>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    env: Option<String>,
+}
+
+impl TryFrom<PasswordSource> for Password {
+    type Error = String;
+
+    fn try_from(source: PasswordSource) -> Result<Self, Self::Error> {
+        let (hash, secret) = match source {
+            PasswordSource::Hash(hash) => (hash, None),
+            PasswordSource::Secret(SecretSource { file: Some(path), env: None }) => {
+                let secret = Secret::File(path);
+                (secret.read()?, Some(secret))
+            }
+            PasswordSource::Secret(SecretSource { file: None, env: Some(name) }) => {
+                let secret = Secret::Env(name);
+                (secret.read()?, Some(secret))
+            }
+            PasswordSource::Secret(_) => return Err("Exactly one of file or env required".to_owned()),
+        };
+
+        return Ok(Self {
+            hash: PasswordHash::parse(hash.trim())?,
+            secret,
+        });
+    }
+}
+
+impl From<Password> for PasswordSource {
+    fn from(password: Password) -> Self {
+        return match password.secret {
+            Some(Secret::File(path)) => Self::Secret(SecretSource { file: Some(path), env: None }),
+            Some(Secret::Env(name)) => Self::Secret(SecretSource { file: None, env: Some(name) }),
+            None => Self::Hash(password.hash.to_string()),
+        };
+    }
+}
+
+impl From<PasswordHash> for Password {
+    fn from(hash: PasswordHash) -> Self {
+        return Self { hash, secret: None };
+    }
+}
+
+impl Deref for Password {
+    type Target = PasswordHash;
+
+    fn deref(&self) -> &Self::Target {
+        return &self.hash;
+    }
+}
+
+impl Secret {
+    /// Reads the secret while loading the database, so rotated secrets apply on the next reload
+    fn read(&self) -> Result<String, String> {
+        return match self {
+            Self::File(path) => std::fs::read_to_string(path)
+                .map_err(|err| format!("Reading secret {:?}: {}", path, err)),
+            Self::Env(name) => std::env::var(name)
+                .map_err(|err| format!("Reading secret ${}: {}", name, err)),
+        };
+    }
+}
+
+/// Binary attribute value given inline as base64 or by a file relative to the data directory
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "BinarySource", into = "BinarySource")]
+pub enum BinaryValue {
+    Inline(Vec<u8>),
+    File {
+        path: PathBuf,
+
+        /// Content of the file, read when loading the database
+        content: Arc<Vec<u8>>,
+    },
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct BinarySource {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    base64: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file: Option<PathBuf>,
+}
+
+impl TryFrom<BinarySource> for BinaryValue {
+    type Error = String;
+
+    fn try_from(source: BinarySource) -> Result<Self, Self::Error> {
+        return match source {
+            BinarySource { base64: Some(data), file: None } => BASE64.decode(data)
+                .map(Self::Inline)
+                .map_err(|err| format!("Invalid base64: {}", err)),
+            BinarySource { base64: None, file: Some(path) } => Ok(Self::File { path, content: Arc::default() }),
+            _ => Err("Exactly one of base64 or file required".to_owned()),
+        };
+    }
+}
+
+impl From<BinaryValue> for BinarySource {
+    fn from(value: BinaryValue) -> Self {
+        return match value {
+            BinaryValue::Inline(data) => Self { base64: Some(BASE64.encode(data)), file: None },
+            BinaryValue::File { path, .. } => Self { base64: None, file: Some(path) },
+        };
+    }
+}
+
+impl BinaryValue {
+    /// Reads the content of a referenced file, which must be located within the data directory
+    pub async fn load(&mut self, data: &Path) -> Result<()> {
+        if let Self::File { path, content } = self {
+            if !path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+                bail!("Binary value outside of the data directory: {:?}", path);
+            }
+
+            let path = data.join(&path);
+            *content = Arc::new(tokio::fs::read(&path).await
+                .with_context(|| format!("Reading binary value from {:?}", path))?);
+        }
+
+        return Ok(());
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        return match self {
+            Self::Inline(data) => data,
+            Self::File { content, .. } => content,
+        };
+    }
+}
+
+impl UserEntity {
+    pub fn normalize(&mut self, rules: &Normalize) {
+        if rules.trim {
+            for value in [&mut self.first_name, &mut self.last_name] {
+                *value = value.trim().to_owned();
+            }
+
+            for mail in self.mail.iter_mut().chain(self.mail_aliases.iter_mut()) {
+                *mail = mail.trim().to_owned();
+            }
+
+            for group in self.groups.iter_mut() {
+                *group = group.trim().to_owned();
+            }
+        }
+
+        if rules.lowercase_mail {
+            for mail in self.mail.iter_mut().chain(self.mail_aliases.iter_mut()) {
+                *mail = mail.to_lowercase();
+            }
+        }
+
+        if rules.lowercase_groups {
+            for group in self.groups.iter_mut() {
+                *group = group.to_lowercase();
+            }
+        }
+
+        // Canonicalized names may collapse into duplicates
+        self.groups = self.groups.drain(..).unique().collect();
+        self.mail = self.mail.drain(..).unique().collect();
+        self.mail_aliases = self.mail_aliases.drain(..).unique().collect();
+    }
+}
+
+/// Versions of the entity formats, which must be increased together with a migration whenever a format changes
+impl Versioned for UserEntity {
+    const VERSION: u32 = 1;
+}
+
+impl Versioned for GroupEntity {
+    const VERSION: u32 = 1;
+}
+
+impl Versioned for ServiceEntity {
+    const VERSION: u32 = 1;
 }
 
-fn deserialize_password<'de, D>(deserializer: D) -> Result<PasswordHashString, D::Error>
-    where
-        D: Deserializer<'de>,
-{
-    let s = Deserialize::deserialize(deserializer)?;
-    return PasswordHashString::parse(s, Encoding::default())
-        .map_err(serde::de::Error::custom);
+impl Versioned for AliasEntity {
+    const VERSION: u32 = 1;
 }
\ No newline at end of file
@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, HashMap};
+
+use itertools::Itertools;
+
+use super::config::NameTemplate;
+use super::data::{GroupEntity, UserEntity};
+use super::store::DirContainer;
+
+/// Lookup tables derived from users and groups to avoid scanning all of them for common queries
+#[derive(Clone, Default)]
+pub struct Index {
+    /// Names of users by their mail addresses in lowercase, the first user wins if an address is used multiple times
+    mails: HashMap<String, String>,
+
+    /// Names of groups listing a user as member by the name of the user
+    listed: HashMap<String, Vec<String>>,
+
+    /// Names of all members of a group, either declared by the user or listed by the group
+    members: HashMap<String, Vec<String>>,
+
+    /// Values of derived attributes by the name of the user, leaving out attributes set by the user
+    derived: HashMap<String, BTreeMap<String, String>>,
+}
+
+/// Derived attributes of users without any
+static NOT_DERIVED: BTreeMap<String, String> = BTreeMap::new();
+
+impl Index {
+    pub fn build(users: &DirContainer<UserEntity>, groups: &DirContainer<GroupEntity>, derived: &BTreeMap<String, NameTemplate>) -> Self {
+        let mut index = Self::default();
+
+        for user in users.iter() {
+            let derived = derived.iter()
+                .filter(|(attribute, _)| !user.attributes.keys().any(|name| name.eq_ignore_ascii_case(attribute)))
+                .map(|(attribute, template)| (attribute.clone(), template.render(&user.name, &user.first_name, &user.last_name)))
+                .collect::<BTreeMap<_, _>>();
+
+            // A derived address is used for users without any address of their own
+            let mails = user.mail.iter()
+                .chain(derived.get("mail").filter(|_| user.mail.is_empty()));
+
+            for mail in mails {
+                index.mails.entry(mail.to_ascii_lowercase())
+                    .or_insert_with(|| user.name.clone());
+            }
+
+            if !derived.is_empty() {
+                index.derived.insert(user.name.clone(), derived);
+            }
+        }
+
+        for group in groups.iter() {
+            for member in group.members.iter() {
+                index.listed.entry(member.clone())
+                    .or_default()
+                    .push(group.name.clone());
+            }
+        }
+
+        // Members are kept in the order of the users
+        for user in users.iter() {
+            let groups = user.groups.iter()
+                .chain(index.listed(&user.name))
+                .unique()
+                .cloned()
+                .collect::<Vec<_>>();
+
+            for group in groups {
+                index.members.entry(group)
+                    .or_default()
+                    .push(user.name.clone());
+            }
+        }
+
+        return index;
+    }
+
+    /// Name of the user having the given mail address, compared case-insensitive
+    pub fn mail(&self, mail: &str) -> Option<&str> {
+        return self.mails.get(&mail.to_ascii_lowercase())
+            .map(String::as_str);
+    }
+
+    /// Groups listing the user as member
+    pub fn listed(&self, user: &str) -> &[String] {
+        return self.listed.get(user)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+    }
+
+    /// Names of the members of the group
+    pub fn members(&self, group: &str) -> &[String] {
+        return self.members.get(group)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+    }
+
+    /// Values of the derived attributes of the user
+    pub fn derived(&self, user: &str) -> &BTreeMap<String, String> {
+        return self.derived.get(user)
+            .unwrap_or(&NOT_DERIVED);
+    }
+}
\ No newline at end of file
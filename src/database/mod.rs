@@ -1,53 +1,484 @@
+use std::collections::HashSet;
 use std::path::Path;
 use std::sync::Arc;
 
-use anyhow::Context;
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use itertools::Itertools;
-use tokio::sync::RwLock;
+use uuid::Uuid;
+use tracing::{info, warn};
 
-pub use model::{Group, User};
+pub use model::{Alias, derive_uuid, Group, Service, User};
 
-use crate::database::data::UserEntity;
+pub use self::audit::Actor;
+pub use self::config::{Config, NameTemplate};
+pub use self::watch::{reload, watch};
+pub use self::report::Report;
+pub use self::shared::SharedDatabase;
+use self::report::REPORT;
+pub use self::data::{AliasEntity, BinaryValue, GroupEntity, PosixAccount, ServiceEntity, UserEntity};
+pub use self::password::PasswordHash;
+pub use self::replica::Replica;
+pub use self::store::is_valid_name;
+pub use self::totp::hmac_sha1;
+
+use crate::database::crypt::Crypt;
+use crate::database::index::Index;
 use crate::database::store::DirContainer;
 
+mod audit;
+mod backup;
+mod config;
+mod crypt;
 mod model;
+mod password;
 mod store;
 mod data;
+mod index;
+mod replica;
+mod report;
+mod shared;
+mod totp;
+mod verifier;
+mod watch;
+
+/// Tracing target for authentication attempts of canary users
+pub const CANARY: &str = "canary";
 
+#[derive(Clone)]
 pub struct Database {
     users: DirContainer<UserEntity>,
+    groups: DirContainer<GroupEntity>,
+    aliases: DirContainer<AliasEntity>,
+    services: DirContainer<ServiceEntity>,
+
+    index: Index,
+
+    config: Config,
+
+    report: Report,
+
+    /// Keys used for encrypted files, which are applied to backups as well
+    crypt: Option<Arc<Crypt>>,
+
+    /// Whether the content has been changed since the copy has been made and must be published
+    changed: bool,
+
+    /// Number of changed snapshots published before this one
+    sequence: u64,
 }
 
 impl Database {
-    pub async fn load(path: impl AsRef<Path>) -> Result<Arc<RwLock<Self>>> {
+    pub async fn load(path: impl AsRef<Path>, config: &Config) -> Result<Arc<SharedDatabase>> {
+        let database = Self::read(path, config).await?;
+        database.report.log();
+
+        if config.strict && !database.report.is_clean() {
+            bail!("Database has findings which are not permitted in strict mode");
+        }
+
+        return Ok(Arc::new(SharedDatabase::new(database)));
+    }
+
+    /// Reads the complete database from the data directory
+    async fn read(path: impl AsRef<Path>, config: &Config) -> Result<Self> {
+        let crypt = Crypt::load(&config.encryption).await
+            .context("Loading encryption keys")?
+            .map(Arc::new);
+
         let users = path.as_ref().join("users");
-        let users = DirContainer::load(&users).await
+        let mut users = DirContainer::<UserEntity>::load(&users, true, crypt.clone()).await
             .with_context(|| format!("Loading users from {:?}", &users))?;
 
-        let database = Arc::new(RwLock::new(Self {
+        for user in users.iter_mut() {
+            user.normalize(&config.normalize);
+
+            // Users are still served if a referenced file can not be read, just without the value
+            let name = user.name.clone();
+            for value in user.binary_attributes.values_mut().flatten() {
+                if let Err(err) = value.load(path.as_ref()).await {
+                    warn!(target: REPORT, "Binary value of user {}: {:#}", name, err);
+                }
+            }
+        }
+
+        let groups = path.as_ref().join("groups");
+        let groups = DirContainer::<GroupEntity>::load_optional(&groups, false, crypt.clone()).await
+            .with_context(|| format!("Loading groups from {:?}", &groups))?;
+
+        let aliases = path.as_ref().join("aliases");
+        let aliases = DirContainer::<AliasEntity>::load_optional(&aliases, false, crypt.clone()).await
+            .with_context(|| format!("Loading aliases from {:?}", &aliases))?;
+
+        let services = path.as_ref().join("services");
+        let services = DirContainer::<ServiceEntity>::load_optional(&services, false, crypt.clone()).await
+            .with_context(|| format!("Loading services from {:?}", &services))?;
+
+        let index = Index::build(&users, &groups, &config.derived);
+
+        let mut database = Self {
             users,
-        }));
+            groups,
+            aliases,
+            services,
+            index,
+            config: config.clone(),
+            report: Report::default(),
+            crypt,
+            changed: false,
+            sequence: 0,
+        };
+
+        if config.migrate {
+            let migrated = database.users.migrate().await? + database.groups.migrate().await?
+                + database.aliases.migrate().await? + database.services.migrate().await?;
+            if migrated > 0 {
+                info!("Migrated {} files to the current version", migrated);
+            }
+        }
+
+        database.report = Report::collect(&database);
 
         return Ok(database);
     }
 
+    /// Replaces the content by a freshly read database
+    fn replace(&mut self, fresh: Self) {
+        let sequence = self.sequence;
+        *self = fresh;
+        self.sequence = sequence;
+        self.changed();
+    }
+
+    fn changed(&mut self) {
+        self.index = Index::build(&self.users, &self.groups, &self.config.derived);
+        self.changed = true;
+    }
+
+    /// Sequence number of the snapshot, counting up with every published change
+    pub fn sequence(&self) -> u64 {
+        return self.sequence;
+    }
+
+    /// Summary of the last load
+    pub fn report(&self) -> &Report {
+        return &self.report;
+    }
+
+    /// Hashes a plain text password using the configured parameters
+    pub fn hash_password(&self, password: &[u8]) -> Result<PasswordHash> {
+        return PasswordHash::new(password, &self.config.hashing)
+            .context("Hashing password");
+    }
+
+    /// Checks whether the hash is replaced once the password has been verified successfully
+    pub fn needs_upgrade(&self, password: &PasswordHash) -> bool {
+        return self.config.hashing.upgrade && password.is_weak(&self.config.hashing);
+    }
+
+    /// Replaces the verified hash of a user by a hash made with the configured parameters
+    ///
+    /// Nothing is changed if the hash has been replaced in the meantime, is read from a secret or is not used for
+    /// verification. The day of the last password change is kept, as the password itself stays the same.
+    pub async fn upgrade_password(&mut self, actor: &Actor, name: &str, verified: &PasswordHash, password: &[u8]) -> Result<()> {
+        let before = match self.user_entity(name) {
+            Some(user) if user.password.hash == *verified && user.password.secret.is_none()
+                && self.config.verifier(user.verifier.as_deref()) == verifier::HASH => user.clone(),
+            _ => return Ok(()),
+        };
+
+        let password = self.hash_password(password)?;
+        self.users.update(name, |user| user.password = password.into()).await?;
+        audit::user_changed(actor, name, Some(&before), self.user_entity(name));
+
+        self.changed();
+        return Ok(());
+    }
+
+    /// Creates a new user and persists it to the data directory, placed in the given organizational units
+    pub async fn add_user(&mut self, actor: &Actor, units: Vec<String>, name: String, mut user: UserEntity) -> Result<()> {
+        user.normalize(&self.config.normalize);
+        user.uuid.get_or_insert_with(Uuid::new_v4);
+        audit::user_changed(actor, &name, None, Some(&user));
+        self.users.insert(units, name, user).await?;
+
+        self.changed();
+        return Ok(());
+    }
+
+    /// Changes an existing user and persists it to the data directory
+    async fn modify_user(&mut self, actor: &Actor, name: &str, f: impl FnOnce(&mut UserEntity)) -> Result<()> {
+        let before = self.user_entity(name).cloned();
+
+        let normalize = &self.config.normalize;
+        self.users.update(name, |user| {
+            let password = user.password.clone();
+            f(user);
+            user.normalize(normalize);
+
+            if user.password != password {
+                user.password_changed = Some(chrono::Local::now().date_naive());
+            }
+        }).await?;
+
+        audit::user_changed(actor, name, before.as_ref(), self.user_entity(name));
+
+        self.changed();
+        return Ok(());
+    }
+
+    /// Replaces an existing user and persists it to the data directory
+    pub async fn update_user(&mut self, actor: &Actor, name: &str, user: UserEntity) -> Result<()> {
+        return self.modify_user(actor, name, |entity| *entity = user).await;
+    }
+
+    /// Renames a user and its file in the data directory
+    pub async fn rename_user(&mut self, actor: &Actor, name: &str, new_name: String) -> Result<()> {
+        // The identifier derived from the old name is persisted to keep it stable
+        self.users.update(name, |user| {
+            user.uuid.get_or_insert_with(|| derive_uuid("user", name));
+        }).await?;
+
+        audit::user_renamed(actor, name, &new_name);
+        self.users.rename(name, new_name.clone()).await?;
+
+        // Memberships listed by groups follow the user
+        let groups = self.groups.iter()
+            .filter(|group| group.members.iter().any(|member| member == name))
+            .map(|group| group.name.clone())
+            .collect::<Vec<_>>();
+
+        for group in groups {
+            self.groups.update(&group, |entity| {
+                for member in entity.members.iter_mut().filter(|member| *member == name) {
+                    *member = new_name.clone();
+                }
+            }).await?;
+        }
+
+        self.changed();
+        return Ok(());
+    }
+
+    /// Creates or replaces the file of a group in the data directory
+    pub async fn save_group(&mut self, actor: &Actor, name: &str, group: GroupEntity) -> Result<()> {
+        let before = self.group_entity(name).cloned();
+        audit::group_changed(actor, name, before.as_ref(), Some(&group));
+
+        if before.is_some() {
+            self.groups.update(name, |entity| *entity = group).await?;
+        } else {
+            self.groups.insert(Vec::new(), name.to_owned(), group).await?;
+        }
+
+        self.changed();
+        return Ok(());
+    }
+
+    /// Renames a group by updating all of its members and its file in the data directory
+    pub async fn rename_group(&mut self, actor: &Actor, name: &str, new_name: &str) -> Result<()> {
+        if self.group_entity(name).is_some() {
+            audit::group_renamed(actor, name, new_name);
+            self.groups.rename(name, new_name.to_owned()).await?;
+        }
+
+        let members = self.users.iter()
+            .filter(|user| user.groups.iter().any(|group| group == name))
+            .map(|user| user.name.clone())
+            .collect::<Vec<_>>();
+
+        for member in members {
+            self.modify_user(actor, &member, |user| {
+                for group in user.groups.iter_mut().filter(|group| *group == name) {
+                    *group = new_name.to_owned();
+                }
+            }).await?;
+        }
+
+        self.changed();
+        return Ok(());
+    }
+
+    /// Replaces all users and groups by the given ones, persisting only the entities which have changed
+    ///
+    /// Identifiers and organizational units of existing users are kept. Returns the number of changed entities.
+    pub async fn mirror(&mut self, actor: &Actor, users: Vec<(String, UserEntity)>, groups: Vec<(String, GroupEntity)>) -> Result<usize> {
+        let mut changed = 0;
+
+        let names = users.iter()
+            .map(|(name, _)| name.clone())
+            .collect::<HashSet<_>>();
+        let stale = self.users.iter()
+            .map(|user| user.name.clone())
+            .filter(|name| !names.contains(name))
+            .collect::<Vec<_>>();
+
+        for name in stale {
+            let before = self.user_entity(&name).cloned();
+            self.users.remove(&name).await?;
+            audit::user_changed(actor, &name, before.as_ref(), None);
+            changed += 1;
+        }
+
+        for (name, mut user) in users {
+            user.normalize(&self.config.normalize);
+
+            let before = match self.user_entity(&name) {
+                Some(before) => before.clone(),
+                None => {
+                    user.uuid.get_or_insert_with(Uuid::new_v4);
+                    audit::user_changed(actor, &name, None, Some(&user));
+                    self.users.insert(Vec::new(), name, user).await?;
+                    changed += 1;
+                    continue;
+                }
+            };
+
+            user.uuid = user.uuid.or(before.uuid);
+            if user == before {
+                continue;
+            }
+
+            // The day of the last password change is taken over as given instead of being maintained
+            self.users.update(&name, |entity| *entity = user).await?;
+            audit::user_changed(actor, &name, Some(&before), self.user_entity(&name));
+            changed += 1;
+        }
+
+        // Group names are normalized like the groups declared by users
+        let groups = groups.into_iter()
+            .map(|(name, group)| (match self.config.normalize.lowercase_groups {
+                true => name.trim().to_lowercase(),
+                false => name.trim().to_owned(),
+            }, group))
+            .collect::<Vec<_>>();
+
+        let names = groups.iter()
+            .map(|(name, _)| name.clone())
+            .collect::<HashSet<_>>();
+        let stale = self.groups.iter()
+            .map(|group| group.name.clone())
+            .filter(|name| !names.contains(name))
+            .collect::<Vec<_>>();
+
+        for name in stale {
+            let before = self.group_entity(&name).cloned();
+            self.groups.remove(&name).await?;
+            audit::group_changed(actor, &name, before.as_ref(), None);
+            changed += 1;
+        }
+
+        for (name, group) in groups {
+            let before = self.group_entity(&name).cloned();
+            if before.as_ref() == Some(&group) {
+                continue;
+            }
+
+            audit::group_changed(actor, &name, before.as_ref(), Some(&group));
+            match before {
+                Some(_) => self.groups.update(&name, |entity| *entity = group).await?,
+                None => self.groups.insert(Vec::new(), name, group).await?,
+            }
+            changed += 1;
+        }
+
+        if changed > 0 {
+            self.changed();
+        }
+
+        return Ok(changed);
+    }
+
+    /// The stored entity of a user, e.g. to prepare changes
+    pub fn user_entity(&self, name: &str) -> Option<&UserEntity> {
+        return self.users.get(name)
+            .map(|user| &**user);
+    }
+
+    /// Organizational units a user is placed in by the subdirectories holding its file
+    pub fn user_units(&self, name: &str) -> Option<&[String]> {
+        return self.users.get(name)
+            .map(|user| user.units.as_slice());
+    }
+
+    /// The stored settings of a group if there are any
+    pub fn group_entity(&self, name: &str) -> Option<&GroupEntity> {
+        return self.groups.get(name)
+            .map(|group| &**group);
+    }
+
+    /// Removes the given users from a group and persists the changed users and the changed group
+    pub async fn remove_group_members(&mut self, actor: &Actor, group: &str, members: &[String]) -> Result<()> {
+        for member in members {
+            if self.user_entity(member).map_or(false, |user| user.groups.iter().any(|g| g == group)) {
+                self.modify_user(actor, member, |user| {
+                    user.groups.retain(|g| g != group);
+                }).await?;
+            }
+        }
+
+        if let Some(before) = self.group_entity(group).cloned() {
+            if before.members.iter().any(|member| members.contains(member)) {
+                self.groups.update(group, |entity| {
+                    entity.members.retain(|member| !members.contains(member));
+                }).await?;
+
+                audit::group_changed(actor, group, Some(&before), self.group_entity(group));
+                self.changed();
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Adds the given users to a group and persists the changed users
+    pub async fn add_group_members(&mut self, actor: &Actor, group: &str, members: &[String]) -> Result<()> {
+        for member in members {
+            self.modify_user(actor, member, |user| {
+                user.groups.push(group.to_owned());
+            }).await?;
+        }
+
+        return Ok(());
+    }
+
     pub fn users(&self) -> impl Iterator<Item=User> {
         return self.users.iter()
-            .map(|user| User {
-                name: &user.name,
-                password: &user.password,
-                first_name: &user.first_name,
-                last_name: &user.last_name,
-                mail: &user.mail,
-                groups: &user.groups,
-                database: self,
-            });
+            .map(|user| User::new(user, self));
+    }
+
+    /// Looks up a user by its name
+    pub fn user(&self, name: &str) -> Option<User> {
+        return self.users.get(name)
+            .map(|user| User::new(user, self));
+    }
+
+    /// Looks up the user having the given mail address, compared case-insensitive
+    pub fn user_by_mail(&self, mail: &str) -> Option<User> {
+        return self.index.mail(mail)
+            .and_then(|name| self.user(name));
+    }
+
+    pub fn aliases(&self) -> impl Iterator<Item=Alias> {
+        return self.aliases.iter()
+            .map(Alias::new);
+    }
+
+    pub fn services(&self) -> impl Iterator<Item=Service> {
+        return self.services.iter()
+            .map(Service::new);
+    }
+
+    /// Looks up a service by its name
+    pub fn service(&self, name: &str) -> Option<Service> {
+        return self.services.get(name)
+            .map(Service::new);
     }
 
+    /// Groups declared by users and groups defined by files
     pub fn groups(&self) -> impl Iterator<Item=Group> {
         return self.users.iter()
             .flat_map(|user| user.groups.iter())
+            .chain(self.groups.iter().map(|group| &group.name))
             .unique()
             .map(|group| Group {
                 name: &group,
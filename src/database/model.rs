@@ -1,25 +1,120 @@
-use argon2::Argon2;
-use password_hash::{PasswordHashString, PasswordVerifier};
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use itertools::Itertools;
+use tracing::warn;
+use uuid::Uuid;
 
 use crate::Database;
 
+use super::data::{AliasEntity, BinaryValue, GroupEntity, PosixAccount, ServiceEntity, UserEntity};
+use super::password::PasswordHash;
+use super::store::Named;
+use super::totp;
+use super::verifier::{self, Verifier};
+
+/// Namespace of the identifiers derived from the names of entities
+const UUID_NAMESPACE: Uuid = Uuid::from_u128(0x5b0c1d2e_8f4a_4c67_9e31_a2d47f60c8b3);
+
+/// Derives a stable identifier from the kind and the name of an entity
+pub fn derive_uuid(kind: &str, name: &str) -> Uuid {
+    return Uuid::new_v5(&UUID_NAMESPACE, format!("{}/{}", kind, name).as_bytes());
+}
+
 #[derive(Clone)]
 pub struct User<'db, 'data> {
     pub name: &'data str,
 
-    pub password: &'data PasswordHashString,
+    /// Organizational units given by the subdirectories holding the user, outermost first
+    pub units: &'data [String],
+
+    pub password: &'data PasswordHash,
 
     pub first_name: &'data str,
     pub last_name: &'data str,
 
-    pub mail: &'data str,
+    pub mail: &'data Vec<String>,
+    pub mail_aliases: &'data Vec<String>,
+
+    /// Groups declared by the user and groups listing the user as member
+    pub groups: Vec<String>,
+
+    pub canary: bool,
+
+    pub posix: Option<&'data PosixAccount>,
+
+    pub ssh_keys: &'data Vec<String>,
+
+    pub password_changed: Option<NaiveDate>,
+    pub password_max_age: Option<u32>,
+    pub expires: Option<NaiveDate>,
 
-    pub groups: &'data Vec<String>,
+    pub disabled: bool,
+    pub locked_until: Option<DateTime<Utc>>,
+
+    pub not_before: Option<DateTime<Utc>>,
+    pub not_after: Option<DateTime<Utc>>,
+
+    pub totp_secret: Option<&'data str>,
+
+    pub eap_password: Option<&'data str>,
+
+    /// Verifier selected by the user itself, overriding the configured one
+    pub verifier: Option<&'data str>,
+
+    pub attributes: &'data BTreeMap<String, Vec<String>>,
+    pub binary_attributes: &'data BTreeMap<String, Vec<BinaryValue>>,
+
+    /// Values of derived attributes, used for attributes without any value of their own
+    pub derived: &'db BTreeMap<String, String>,
+
+    pub uuid: Uuid,
+
+    pub created: Option<SystemTime>,
+    pub modified: Option<SystemTime>,
 
     pub(super) database: &'db Database,
 }
 
 impl<'db, 'data> User<'db, 'data> {
+    pub(super) fn new(user: &'data Named<UserEntity>, database: &'db Database) -> Self {
+        return Self {
+            name: &user.name,
+            units: &user.units,
+            password: &user.password,
+            first_name: &user.first_name,
+            last_name: &user.last_name,
+            mail: &user.mail,
+            mail_aliases: &user.mail_aliases,
+            groups: user.groups.iter()
+                .chain(database.index.listed(&user.name))
+                .unique()
+                .cloned()
+                .collect(),
+            canary: user.canary,
+            posix: user.posix.as_ref(),
+            ssh_keys: &user.ssh_keys,
+            password_changed: user.password_changed,
+            password_max_age: user.password_max_age,
+            expires: user.expires,
+            disabled: user.disabled,
+            locked_until: user.locked_until,
+            not_before: user.not_before,
+            not_after: user.not_after,
+            totp_secret: user.totp_secret.as_deref(),
+            eap_password: user.eap_password.as_deref(),
+            verifier: user.verifier.as_deref(),
+            attributes: &user.attributes,
+            binary_attributes: &user.binary_attributes,
+            derived: database.index.derived(&user.name),
+            uuid: user.uuid.unwrap_or_else(|| derive_uuid("user", &user.name)),
+            created: user.created,
+            modified: user.modified,
+            database,
+        };
+    }
+
     pub fn groups(&'db self) -> impl Iterator<Item=Group<'db, '_>> + 'db {
         return self.groups.iter()
             .map(|group| Group {
@@ -28,10 +123,133 @@ impl<'db, 'data> User<'db, 'data> {
             });
     }
 
+    /// Name of the verifier checking the password of the user
+    pub fn verifier(&self) -> &str {
+        return self.database.config.verifier(self.verifier);
+    }
+
+    /// Verifies the password using the verifier of the user, failing if the verifier is unknown or unable to decide
+    pub async fn verify_password(&self, password: &[u8]) -> bool {
+        let verifier: &dyn Verifier = match self.verifier() {
+            verifier::HASH => &verifier::Hash,
+            name => match self.database.config.verifiers.get(name) {
+                Some(verifier) => verifier.verifier(),
+                None => {
+                    warn!("Unknown verifier {} of user {}", name, self.name);
+                    return false;
+                }
+            },
+        };
+
+        return match verifier.verify(self.name, self.password, password).await {
+            Ok(verified) => verified,
+            Err(err) => {
+                warn!("Verifying password of user {} failed: {:#}", self.name, err);
+                false
+            }
+        };
+    }
+
+    /// Verifies a password followed by the current one-time password if the user has a TOTP secret
+    pub async fn verify_password_with_otp(&self, password: &[u8]) -> bool {
+        let secret = if let Some(secret) = self.totp_secret { secret } else {
+            return self.verify_password(password).await;
+        };
+
+        if password.len() < totp::DIGITS {
+            return false;
+        }
+
+        // The password is always verified to not disclose whether the one-time password was correct by timing
+        let (password, code) = password.split_at(password.len() - totp::DIGITS);
+        let verified = self.verify_password(password).await;
+        return totp::verify(secret, code) && verified;
+    }
+
+    /// The password without the one-time password appended to it if the user has a TOTP secret
+    pub fn strip_otp<'p>(&self, password: &'p [u8]) -> &'p [u8] {
+        return match self.totp_secret {
+            Some(_) => &password[..password.len().saturating_sub(totp::DIGITS)],
+            None => password,
+        };
+    }
+
+    /// Time until which the user is locked if the lock is still in effect
+    pub fn locked(&self) -> Option<DateTime<Utc>> {
+        return self.locked_until
+            .filter(|until| *until > Utc::now());
+    }
+
+    /// Time the account becomes valid if it is not valid yet
+    pub fn pending(&self) -> Option<DateTime<Utc>> {
+        return self.not_before
+            .filter(|since| *since > Utc::now());
+    }
+
+    /// Time the account expired if it is no longer valid
+    pub fn expired(&self) -> Option<DateTime<Utc>> {
+        return self.not_after
+            .filter(|until| *until <= Utc::now());
+    }
+}
+
+#[derive(Clone)]
+pub struct Service<'data> {
+    pub name: &'data str,
+
+    pub password: &'data PasswordHash,
+
+    pub description: Option<&'data str>,
+
+    pub radius: bool,
+
+    pub uuid: Uuid,
+
+    pub created: Option<SystemTime>,
+    pub modified: Option<SystemTime>,
+}
+
+impl<'data> Service<'data> {
+    pub(super) fn new(service: &'data Named<ServiceEntity>) -> Self {
+        return Self {
+            name: &service.name,
+            password: &service.password,
+            description: service.description.as_deref(),
+            radius: service.radius,
+            uuid: derive_uuid("service", &service.name),
+            created: service.created,
+            modified: service.modified,
+        };
+    }
+
     pub fn verify_password(&self, password: &[u8]) -> bool {
-        return Argon2::default()
-            .verify_password(password, &self.password.password_hash())
-            .is_ok();
+        return self.password.verify(password);
+    }
+}
+
+#[derive(Clone)]
+pub struct Alias<'data> {
+    pub name: &'data str,
+
+    pub parent: &'data str,
+    pub target: &'data str,
+
+    pub uuid: Uuid,
+
+    pub created: Option<SystemTime>,
+    pub modified: Option<SystemTime>,
+}
+
+impl<'data> Alias<'data> {
+    pub(super) fn new(alias: &'data Named<AliasEntity>) -> Self {
+        return Self {
+            name: &alias.name,
+            parent: &alias.parent,
+            target: &alias.target,
+            uuid: derive_uuid("alias", &alias.name),
+            created: alias.created,
+            modified: alias.modified,
+        };
     }
 }
 
@@ -44,16 +262,62 @@ pub struct Group<'db, 'data> {
 
 impl<'db, 'data> Group<'db, 'data> {
     pub fn members(&'db self) -> impl Iterator<Item=User<'db, '_>> + 'db {
-        return self.database.users.iter()
-            .filter(|user| user.groups.iter().any(|group| group == self.name))
-            .map(|user| User {
-                name: &user.name,
-                password: &user.password,
-                first_name: &user.first_name,
-                last_name: &user.last_name,
-                mail: &user.mail,
-                groups: &user.groups,
-                database: self.database,
-            });
+        return self.database.index.members(self.name).iter()
+            .filter_map(|name| self.database.user(name));
+    }
+
+    /// Stable identifier of the group derived from its name
+    pub fn uuid(&self) -> Uuid {
+        return derive_uuid("group", self.name);
+    }
+
+    /// Creation time of the settings of the group if there are any
+    pub fn created(&self) -> Option<SystemTime> {
+        return self.settings().and_then(|group| group.created);
+    }
+
+    /// Time the settings of the group were last changed if there are any
+    pub fn modified(&self) -> Option<SystemTime> {
+        return self.settings().and_then(|group| group.modified);
+    }
+
+    fn settings(&self) -> Option<&'db Named<GroupEntity>> {
+        return self.database.groups.get(self.name);
+    }
+
+    /// Numeric ID of the group if it is exposed to Linux hosts
+    pub fn gid_number(&self) -> Option<u32> {
+        return self.database.group_entity(self.name)
+            .and_then(|group| group.gid_number);
+    }
+
+    pub fn description(&self) -> Option<&'db str> {
+        return self.database.group_entity(self.name)
+            .and_then(|group| group.description.as_deref());
+    }
+
+    /// DN of the owner relative to the base DN if there is one
+    pub fn owner(&self) -> Option<&'db str> {
+        return self.database.group_entity(self.name)
+            .and_then(|group| group.owner.as_deref());
+    }
+
+    /// Mail addresses of the group
+    pub fn mail(&self) -> &'db [String] {
+        return self.database.group_entity(self.name)
+            .map(|group| group.mail.as_slice())
+            .unwrap_or_default();
+    }
+
+    /// Users permitted to manage the members of this group
+    pub fn managers(&self) -> impl Iterator<Item=User<'db, 'db>> + 'db {
+        let managers = self.database.group_entity(self.name)
+            .map(|group| group.managers.as_slice())
+            .unwrap_or_default();
+
+        let database = self.database;
+        return database.users.iter()
+            .filter(move |user| managers.contains(&user.name))
+            .map(move |user| User::new(user, database));
     }
 }
\ No newline at end of file
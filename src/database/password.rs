@@ -0,0 +1,121 @@
+use std::fmt;
+
+use argon2::Argon2;
+use password_hash::{Encoding, PasswordHasher, PasswordHashString, PasswordVerifier, SaltString};
+use pbkdf2::Pbkdf2;
+use rand_core::OsRng;
+use scrypt::Scrypt;
+
+use super::config::Hashing;
+
+/// Prefixes of bcrypt hashes in the modular crypt format, naming the revision of the algorithm
+const BCRYPT_PREFIXES: &[&str] = &["$2a$", "$2b$", "$2x$", "$2y$"];
+
+/// Stored hash of a password
+///
+/// Argon2, scrypt and PBKDF2 hashes are given in the PHC string format, while bcrypt hashes keep the modular crypt
+/// format as they are usually found in other directories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordHash {
+    Phc(PasswordHashString),
+    Bcrypt(String),
+}
+
+impl PasswordHash {
+    /// Hashes a plain text password using Argon2 with the given parameters
+    pub fn new(password: &[u8], hashing: &Hashing) -> password_hash::Result<Self> {
+        let salt = SaltString::generate(&mut OsRng);
+        return Ok(Self::Phc(hashing.argon2()?.hash_password(password, &salt)?.serialize()));
+    }
+
+    /// Parses a hash dispatching on its identifier
+    pub fn parse(hash: &str) -> Result<Self, String> {
+        if BCRYPT_PREFIXES.iter().any(|prefix| hash.starts_with(prefix)) {
+            return hash.parse::<bcrypt::HashParts>()
+                .map(|_| Self::Bcrypt(hash.to_owned()))
+                .map_err(|err| err.to_string());
+        }
+
+        return PasswordHashString::parse(hash, Encoding::default())
+            .map(Self::Phc)
+            .map_err(|err| err.to_string());
+    }
+
+    /// Identifier of the algorithm used to create the hash
+    pub fn algorithm(&self) -> &str {
+        return match self {
+            Self::Phc(hash) => hash.algorithm().as_str(),
+            Self::Bcrypt(_) => "bcrypt",
+        };
+    }
+
+    pub fn as_str(&self) -> &str {
+        return match self {
+            Self::Phc(hash) => hash.as_str(),
+            Self::Bcrypt(hash) => hash,
+        };
+    }
+
+    /// Verifies the password using the algorithm the hash has been created with
+    pub fn verify(&self, password: &[u8]) -> bool {
+        return match self {
+            Self::Phc(hash) => hash.password_hash()
+                .verify_password(&[&Argon2::default() as &dyn PasswordVerifier, &Scrypt, &Pbkdf2], password)
+                .is_ok(),
+            Self::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+        };
+    }
+
+    /// Checks whether the hash is not made by Argon2 or weaker than the parameters used for new hashes
+    pub fn is_weak(&self, hashing: &Hashing) -> bool {
+        let hash = match self {
+            Self::Phc(hash) => hash.password_hash(),
+            Self::Bcrypt(_) => return true,
+        };
+
+        if !hash.algorithm.as_str().starts_with("argon2") {
+            return true;
+        }
+
+        let cost = |name: &str, minimum: u32| hash.params.get_decimal(name).map_or(true, |cost| cost < minimum);
+        return cost("m", hashing.memory) || cost("t", hashing.iterations);
+    }
+}
+
+impl fmt::Display for PasswordHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str(self.as_str());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use argon2::Params;
+
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let hashes = [
+            ("argon2id", "$argon2id$v=19$m=4096,t=3,p=1$LLGsuJNx7SiFhRygYkT4lg$SAWA/25qvtMjhE39wzPJfpiiMSIeTsgQEwG/30t2pzU"),
+            ("scrypt", "$scrypt$ln=4,r=8,p=1$c2FsdHNhbHQ$tHT7GLqp0uYbiB009MeIHnMuEMZ86kx0jgBpq5vxo84"),
+            ("pbkdf2-sha256", "$pbkdf2-sha256$i=1000,l=32$c2FsdHNhbHQ$hgR9HsqtKupWxpnv8y99TrPDajTT/9PcSTlNafpdLXQ"),
+            ("bcrypt", "$2b$04$nvfCqDo6Hb7BNJBIH6jPv.gPnh8kunRQJ7slLY7g0cYpB0sRdSbny"),
+        ];
+
+        for (algorithm, hash) in hashes {
+            let hash = PasswordHash::parse(hash).unwrap();
+            assert_eq!(hash.algorithm(), algorithm);
+            assert!(hash.verify(b"secret"), "{}", algorithm);
+            assert!(!hash.verify(b"wrong"), "{}", algorithm);
+        }
+
+        let hash = PasswordHash::new(b"secret", &Hashing::default()).unwrap();
+        assert!(hash.verify(b"secret"));
+        assert!(!hash.verify(b"wrong"));
+        assert!(!hash.is_weak(&Hashing::default()));
+        assert!(hash.is_weak(&Hashing { memory: Params::DEFAULT_M_COST * 2, ..Hashing::default() }));
+
+        assert!(PasswordHash::parse("$2b$04$invalid").is_err());
+    }
+}
\ No newline at end of file
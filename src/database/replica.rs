@@ -0,0 +1,160 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{audit, Actor, AliasEntity, BinaryValue, Database, GroupEntity, ServiceEntity, UserEntity};
+use super::store::{DirContainer, is_valid_name, Versioned};
+
+/// Entity of a replica together with its location in the data directory
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Entry<T> {
+    pub name: String,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub units: Vec<String>,
+
+    pub data: T,
+}
+
+/// Complete content of the data directory as transferred to secondary instances
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Replica {
+    pub users: Vec<Entry<UserEntity>>,
+    pub groups: Vec<Entry<GroupEntity>>,
+    pub services: Vec<Entry<ServiceEntity>>,
+    pub aliases: Vec<Entry<AliasEntity>>,
+}
+
+fn entries<T>(container: &DirContainer<T>, f: impl Fn(&T) -> T) -> Vec<Entry<T>>
+    where
+        T: Versioned + Send + 'static,
+{
+    return container.iter()
+        .map(|entity| Entry {
+            name: entity.name.clone(),
+            units: entity.units.clone(),
+            data: f(entity),
+        })
+        .collect();
+}
+
+fn unpack<T>(entries: Vec<Entry<T>>) -> Vec<(Vec<String>, String, T)> {
+    return entries.into_iter()
+        .map(|entry| (entry.units, entry.name, entry.data))
+        .collect();
+}
+
+/// Refuses entries whose name or units would escape the data directory
+fn validate<T>(entries: &[Entry<T>]) -> Result<()> {
+    for entry in entries {
+        if !is_valid_name(&entry.name) || !entry.units.iter().all(|unit| is_valid_name(unit)) {
+            bail!("Invalid entry: {:?} in {:?}", entry.name, entry.units);
+        }
+    }
+
+    return Ok(());
+}
+
+impl Database {
+    /// Takes a copy of all entities
+    ///
+    /// Binary values referenced by files are given inline, as the files are not part of the replica. Password hashes
+    /// read from secrets are given inline as well unless the references are kept.
+    pub fn replica(&self, keep_secrets: bool) -> Replica {
+        return Replica {
+            users: entries(&self.users, |user| {
+                let mut user = user.clone();
+                if !keep_secrets {
+                    user.password.secret = None;
+                }
+                for value in user.binary_attributes.values_mut().flatten() {
+                    if let BinaryValue::File { content, .. } = value {
+                        *value = BinaryValue::Inline(content.to_vec());
+                    }
+                }
+                return user;
+            }),
+            groups: entries(&self.groups, Clone::clone),
+            services: entries(&self.services, |service| {
+                let mut service = service.clone();
+                if !keep_secrets {
+                    service.password.secret = None;
+                }
+                return service;
+            }),
+            aliases: entries(&self.aliases, Clone::clone),
+        };
+    }
+
+    /// Replaces all entities by the ones of a replica, persisting only the entities which have changed
+    ///
+    /// Returns the number of changed entities.
+    pub async fn replicate(&mut self, actor: &Actor, replica: Replica) -> Result<usize> {
+        // Checked for all kinds of entities before replacing any of them
+        validate(&replica.users)?;
+        validate(&replica.groups)?;
+        validate(&replica.services)?;
+        validate(&replica.aliases)?;
+
+        let users = self.users.replace_all(unpack(replica.users)).await?;
+        for (name, before) in users.iter() {
+            audit::user_changed(actor, name, before.as_ref(), self.user_entity(name));
+        }
+
+        let groups = self.groups.replace_all(unpack(replica.groups)).await?;
+        for (name, before) in groups.iter() {
+            audit::group_changed(actor, name, before.as_ref(), self.group_entity(name));
+        }
+
+        let services = self.services.replace_all(unpack(replica.services)).await?;
+        let aliases = self.aliases.replace_all(unpack(replica.aliases)).await?;
+
+        let changed = users.len() + groups.len() + services.len() + aliases.len();
+        if changed > 0 {
+            self.changed();
+        }
+
+        return Ok(changed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::Config;
+
+    const USER: &str = "password: '$argon2id$v=19$m=4096,t=3,p=1$dL8VpIEd5VBf/y+Gy7HtYw$XJsET7CMeA4OmtKis355BFijnJObZbIlcMxORki9m0s'\nfirst_name: 'Rob'\nlast_name: 'Paulsen'\nmail: 'brain@example.com'\ngroups: []\n";
+
+    #[tokio::test]
+    async fn test_replicate() {
+        let dir = std::env::temp_dir().join(format!("concess-replica-{}", std::process::id()));
+        let (primary, secondary) = (dir.join("primary"), dir.join("secondary"));
+        tokio::fs::create_dir_all(primary.join("users/lab")).await.unwrap();
+        tokio::fs::create_dir_all(secondary.join("users")).await.unwrap();
+
+        tokio::fs::write(primary.join("users/brain.yaml"), USER).await.unwrap();
+        tokio::fs::write(primary.join("users/lab/pinky.yaml"), USER).await.unwrap();
+        tokio::fs::write(secondary.join("users/stale.yaml"), USER).await.unwrap();
+
+        let actor = Actor {
+            protocol: "replication",
+            identity: "primary".to_owned(),
+            source: "test".to_owned(),
+        };
+
+        let replica = Database::read(&primary, &Config::default()).await.unwrap().replica(false);
+        let mut database = Database::read(&secondary, &Config::default()).await.unwrap();
+
+        // Both users are added and the stale one is removed, nothing changes when applied again
+        assert_eq!(database.replicate(&actor, replica.clone()).await.unwrap(), 3);
+        assert_eq!(database.replicate(&actor, replica.clone()).await.unwrap(), 0);
+        assert!(secondary.join("users/lab/pinky.yaml").exists());
+        assert!(!secondary.join("users/stale.yaml").exists());
+
+        let mut escaping = replica.clone();
+        escaping.users[0].units = vec!["..".to_owned()];
+        assert!(database.replicate(&actor, escaping).await.is_err());
+        assert!(!secondary.join("brain.yaml").exists());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use itertools::Itertools;
+use tracing::{info, warn};
+
+use super::{totp, verifier, Database};
+
+/// Tracing target for the summary emitted after loading the database
+pub const REPORT: &str = "report";
+
+/// Summary of a database load
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub users: usize,
+    pub groups: usize,
+    pub services: usize,
+
+    /// Files which have not been loaded and the reason why
+    pub skipped: Vec<(PathBuf, String)>,
+
+    /// Values which are expected to be unique but are used multiple times
+    pub duplicates: Vec<String>,
+
+    /// Values which are most likely mistakes like references to unknown groups
+    pub problems: Vec<String>,
+
+    /// Number of users per password hash scheme
+    pub schemes: BTreeMap<String, usize>,
+}
+
+impl Report {
+    pub fn collect(database: &Database) -> Self {
+        let mut duplicates = Vec::new();
+
+        duplicates.extend(database.users()
+            .map(|user| user.name.to_lowercase())
+            .duplicates()
+            .map(|name| format!("name {}", name)));
+
+        duplicates.extend(database.users()
+            .flat_map(|user| user.mail.iter().chain(user.mail_aliases.iter()))
+            .map(|mail| mail.to_lowercase())
+            .duplicates()
+            .map(|mail| format!("mail {}", mail)));
+
+        duplicates.extend(database.users()
+            .map(|user| (user.password.as_str(), user.name))
+            .chain(database.services().map(|service| (service.password.as_str(), service.name)))
+            .into_group_map()
+            .into_values()
+            .filter(|names| names.len() > 1)
+            .map(|names| format!("password hash of {}", names.into_iter().sorted().join(", ")))
+            .sorted());
+
+        let mut problems = Vec::new();
+
+        // Groups are only known to exist if they are defined by files
+        if database.groups.iter().next().is_some() {
+            problems.extend(database.users.iter()
+                .flat_map(|user| user.groups.iter().map(move |group| (&user.name, group)))
+                .filter(|(_, group)| database.group_entity(group).is_none())
+                .map(|(name, group)| format!("unknown group {} of user {}", group, name)));
+        }
+
+        problems.extend(database.groups.iter()
+            .flat_map(|group| group.members.iter().chain(group.managers.iter()).map(move |member| (&group.name, member)))
+            .filter(|(_, member)| database.user_entity(member).is_none())
+            .map(|(name, member)| format!("unknown user {} in group {}", member, name)));
+
+        problems.extend(database.users()
+            .flat_map(|user| user.mail.iter().chain(user.mail_aliases.iter()).map(move |mail| (user.name, mail)))
+            .filter(|(_, mail)| !is_valid_mail(mail))
+            .map(|(name, mail)| format!("invalid mail {} of user {}", mail, name)));
+
+        problems.extend(database.users.iter()
+            .filter(|user| user.totp_secret.as_deref().map_or(false, |secret| totp::decode_secret(secret).is_none()))
+            .map(|user| format!("invalid TOTP secret of user {}", user.name)));
+
+        problems.extend(database.users()
+            .filter(|user| user.verifier() != verifier::HASH && !database.config.verifiers.contains_key(user.verifier()))
+            .map(|user| format!("unknown verifier {} of user {}", user.verifier(), user.name)));
+
+        // Hashes of users verified otherwise are not used
+        problems.extend(database.users()
+            .filter(|user| user.verifier() == verifier::HASH)
+            .map(|user| (user.password, user.name))
+            .chain(database.services().map(|service| (service.password, service.name)))
+            .filter(|(password, _)| password.is_weak(&database.config.hashing))
+            .map(|(_, name)| format!("weak password hash of {}", name)));
+
+        let schemes = database.users()
+            .map(|user| user.password.algorithm().to_string())
+            .counts()
+            .into_iter()
+            .collect();
+
+        return Self {
+            users: database.users().count(),
+            groups: database.groups().count(),
+            services: database.services().count(),
+            skipped: database.users.skipped().iter()
+                .chain(database.groups.skipped())
+                .chain(database.aliases.skipped())
+                .chain(database.services.skipped())
+                .cloned()
+                .collect(),
+            duplicates,
+            problems,
+            schemes,
+        };
+    }
+
+    /// Checks whether the load went without any findings, which is required in strict mode
+    pub fn is_clean(&self) -> bool {
+        return self.skipped.is_empty() && self.duplicates.is_empty() && self.problems.is_empty();
+    }
+
+    pub fn log(&self) {
+        info!(target: REPORT, users = self.users, groups = self.groups, services = self.services, skipped = self.skipped.len(), duplicates = self.duplicates.len(), problems = self.problems.len(), "Database loaded");
+
+        for (path, reason) in self.skipped.iter() {
+            warn!(target: REPORT, "Skipped {:?}: {}", path, reason);
+        }
+
+        for duplicate in self.duplicates.iter() {
+            warn!(target: REPORT, "Duplicate {}", duplicate);
+        }
+
+        for problem in self.problems.iter() {
+            warn!(target: REPORT, "Problem: {}", problem);
+        }
+
+        for (scheme, count) in self.schemes.iter() {
+            info!(target: REPORT, scheme = scheme.as_str(), count, "Password hash scheme in use");
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "users: {}", self.users)?;
+        writeln!(f, "groups: {}", self.groups)?;
+        writeln!(f, "services: {}", self.services)?;
+
+        for (path, reason) in self.skipped.iter() {
+            writeln!(f, "skipped: {:?} ({})", path, reason)?;
+        }
+
+        for duplicate in self.duplicates.iter() {
+            writeln!(f, "duplicate: {}", duplicate)?;
+        }
+
+        for problem in self.problems.iter() {
+            writeln!(f, "problem: {}", problem)?;
+        }
+
+        for (scheme, count) in self.schemes.iter() {
+            writeln!(f, "scheme: {} ({})", scheme, count)?;
+        }
+
+        return Ok(());
+    }
+}
+
+/// Checks the rough shape of a mail address, which is a local part and a domain containing a dot
+fn is_valid_mail(mail: &str) -> bool {
+    return match mail.split_once('@') {
+        Some((local, domain)) => !local.is_empty()
+            && domain.split('.').count() > 1
+            && domain.split('.').all(|label| !label.is_empty())
+            && !mail[local.len() + 1..].contains('@')
+            && !mail.chars().any(char::is_whitespace),
+        None => false,
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_valid_mail;
+
+    #[test]
+    fn test_valid_mail() {
+        assert!(is_valid_mail("brain@example.com"));
+        assert!(is_valid_mail("brain+lab@mail.example.com"));
+
+        assert!(!is_valid_mail("brain"));
+        assert!(!is_valid_mail("@example.com"));
+        assert!(!is_valid_mail("brain@localhost"));
+        assert!(!is_valid_mail("brain@example..com"));
+        assert!(!is_valid_mail("brain@lab@example.com"));
+        assert!(!is_valid_mail("brain @example.com"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,131 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use arc_swap::ArcSwap;
+use tokio::sync::{watch, Mutex, MutexGuard};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::{Actor, Database, PasswordHash};
+
+/// Database shared between all servers, published as immutable snapshots
+///
+/// Readers take the current snapshot without ever waiting, while writers are serialized and work on a copy which
+/// replaces the snapshot once the writer is done.
+pub struct SharedDatabase {
+    current: ArcSwap<Database>,
+
+    writer: Mutex<()>,
+
+    /// Identifies this instance of the database, as sequence numbers start over with every start
+    epoch: Uuid,
+
+    /// Notifies subscribers whenever a changed snapshot is published
+    changes: watch::Sender<()>,
+
+    /// Whether the content is mirrored from an upstream directory or replicated from a primary instance and must not
+    /// be changed by clients
+    read_only: AtomicBool,
+}
+
+impl SharedDatabase {
+    pub fn new(database: Database) -> Self {
+        return Self {
+            current: ArcSwap::from_pointee(database),
+            writer: Mutex::new(()),
+            epoch: Uuid::new_v4(),
+            changes: watch::channel(()).0,
+            read_only: AtomicBool::new(false),
+        };
+    }
+
+    /// Refuses changes made by clients from now on
+    pub fn set_read_only(&self) {
+        self.read_only.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        return self.read_only.load(Ordering::Relaxed);
+    }
+
+    /// Random identifier of the sequence the snapshots are numbered in
+    pub fn epoch(&self) -> Uuid {
+        return self.epoch;
+    }
+
+    /// The current content of the database, which is not affected by later changes
+    pub fn snapshot(&self) -> Arc<Database> {
+        return self.current.load_full();
+    }
+
+    /// Waits for other writers and returns a copy of the current content to change
+    pub async fn write(&self) -> WriteGuard<'_> {
+        let lock = self.writer.lock().await;
+
+        return WriteGuard {
+            shared: self,
+            database: Some(Database::clone(&self.current.load())),
+            _lock: lock,
+        };
+    }
+
+    /// Returns a receiver which is marked as changed whenever the content of the database changes
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        return self.changes.subscribe();
+    }
+
+    /// Replaces the weak hash of a user in the background if the password has been verified against it
+    ///
+    /// Authentication is not delayed by waiting for other writers and hashing the password again. Hashes of read-only
+    /// databases are kept, as they would be replaced by the upstream directory or the primary again.
+    pub fn upgrade_password(self: &Arc<Self>, actor: Actor, name: &str, verified: &PasswordHash, password: &[u8]) {
+        if self.is_read_only() || !self.snapshot().needs_upgrade(verified) {
+            return;
+        }
+
+        let shared = self.clone();
+        let name = name.to_owned();
+        let verified = verified.clone();
+        let password = password.to_vec();
+        tokio::spawn(async move {
+            info!("Upgrading {} password hash of user {}", verified.algorithm(), name);
+            if let Err(err) = shared.write().await.upgrade_password(&actor, &name, &verified, &password).await {
+                warn!("Failed to upgrade password hash of user {}: {:#}", name, err);
+            }
+        });
+    }
+}
+
+/// Exclusive access to a copy of the database which is published when dropped if anything has changed
+pub struct WriteGuard<'a> {
+    shared: &'a SharedDatabase,
+    database: Option<Database>,
+    _lock: MutexGuard<'a, ()>,
+}
+
+impl Deref for WriteGuard<'_> {
+    type Target = Database;
+
+    fn deref(&self) -> &Self::Target {
+        return self.database.as_ref().expect("Database taken");
+    }
+}
+
+impl DerefMut for WriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        return self.database.as_mut().expect("Database taken");
+    }
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(mut database) = self.database.take() {
+            if std::mem::take(&mut database.changed) {
+                database.sequence += 1;
+                self.shared.current.store(Arc::new(database));
+                self.shared.changes.send_replace(());
+            }
+        }
+    }
+}
\ No newline at end of file
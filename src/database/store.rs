@@ -1,13 +1,63 @@
-use std::ops::Deref;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
 use serde::de::DeserializeOwned;
-use tracing::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, info, warn};
 
+use super::crypt::Crypt;
+
+/// Number of entity files loaded concurrently
+const PARALLELISM: usize = 64;
+
+/// Number of loaded entities after which progress is reported
+const PROGRESS_INTERVAL: usize = 1000;
+
+/// Version of files not declaring a version, which predate versioning
+const UNVERSIONED: u32 = 1;
+
+/// Entities stored in files declaring the version of their format, which are upgraded from older versions on load
+pub trait Versioned: DeserializeOwned {
+    /// Version of the format of written files
+    const VERSION: u32;
+
+    /// Upgrades the content of a file from the given version to the next one
+    fn migrate(version: u32, _value: &mut serde_yaml::Mapping) -> Result<()> {
+        bail!("No migration from version {}", version);
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    version: Option<u32>,
+}
+
+/// Checks whether a name can be used as a file or directory name without escaping the directory of the container
+pub fn is_valid_name(name: &str) -> bool {
+    return !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\', '\0']);
+}
+
+#[derive(Clone)]
 pub struct Named<T> {
     pub name: String,
 
+    /// Subdirectories holding the file below the directory of the container, outermost first
+    pub units: Vec<String>,
+
+    /// Creation time of the file if supported by the filesystem
+    pub created: Option<SystemTime>,
+
+    /// Time the file was last written
+    pub modified: Option<SystemTime>,
+
     data: T,
 }
 
@@ -19,87 +69,510 @@ impl<T> Deref for Named<T> {
     }
 }
 
+impl<T> DerefMut for Named<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        return &mut self.data;
+    }
+}
+
+#[derive(Clone)]
 struct DirEntity<T>
     where
         T: DeserializeOwned,
 {
     path: PathBuf,
     data: Named<T>,
+
+    /// Version of the format the file has been written in
+    version: u32,
 }
 
 impl<T> DirEntity<T>
     where
-        T: DeserializeOwned,
+        T: Versioned,
 {
     pub async fn load(path: impl AsRef<Path>,
-                      name: String) -> Result<Self> {
+                      units: Vec<String>,
+                      name: String,
+                      crypt: Option<&Crypt>) -> Result<Self> {
         let path = path.as_ref();
 
         let data = tokio::fs::read(path).await
             .with_context(|| format!("Reading entity: {:?}", path))?;
+        let data = Crypt::open(crypt, data)
+            .with_context(|| format!("Decrypting entity: {:?}", path))?;
+
+        let version = serde_yaml::from_slice::<Header>(&data)
+            .with_context(|| format!("Parsing entity: {:?}", path))?
+            .version.unwrap_or(UNVERSIONED);
 
-        let data = serde_yaml::from_slice(&data)
-            .with_context(|| format!("Parsing entity: {:?}", path))?;
+        if version > T::VERSION {
+            bail!("Entity {:?} has version {} which is newer than the supported version {}", path, version, T::VERSION);
+        }
+
+        // Files in the current format are parsed directly to keep the locations of errors
+        let data = if version < T::VERSION {
+            let mut value: serde_yaml::Mapping = serde_yaml::from_slice(&data)
+                .with_context(|| format!("Parsing entity: {:?}", path))?;
+            for version in version..T::VERSION {
+                T::migrate(version, &mut value)
+                    .with_context(|| format!("Migrating entity {:?} from version {}", path, version))?;
+            }
+            value.remove("version");
+            serde_yaml::from_value(serde_yaml::Value::Mapping(value))
+        } else {
+            serde_yaml::from_slice(&data)
+        }.with_context(|| format!("Parsing entity: {:?}", path))?;
+
+        let metadata = tokio::fs::metadata(path).await
+            .with_context(|| format!("Reading metadata of entity: {:?}", path))?;
 
         return Ok(Self {
             path: path.to_owned(),
             data: Named {
                 name: name.to_owned(),
+                units,
+                created: metadata.created().ok(),
+                modified: metadata.modified().ok(),
                 data,
             },
+            version,
         });
     }
 }
 
+impl<T> DirEntity<T>
+    where
+        T: Versioned + Serialize,
+{
+    /// Writes the entity in the current version to a temporary file which replaces the original file afterwards
+    ///
+    /// Comments can not be kept in general as the file is serialized from scratch, but the comments heading the
+    /// original file are written again. The file is encrypted if keys are given.
+    pub async fn save(&mut self, crypt: Option<&Crypt>) -> Result<()> {
+        let data = match serde_yaml::to_value(&self.data.data) {
+            Ok(serde_yaml::Value::Mapping(data)) => std::iter::once(("version".into(), T::VERSION.into()))
+                .chain(data)
+                .collect::<serde_yaml::Mapping>(),
+            Ok(_) => bail!("Entity is not a mapping: {:?}", self.path),
+            Err(err) => return Err(err).with_context(|| format!("Serializing entity: {:?}", self.path)),
+        };
+        let data = serde_yaml::to_string(&data)
+            .with_context(|| format!("Serializing entity: {:?}", self.path))?;
+
+        let header = match tokio::fs::read(&self.path).await.map(|original| Crypt::open(crypt, original)) {
+            Ok(Ok(original)) => String::from_utf8_lossy(&original).lines()
+                .take_while(|line| line.starts_with('#'))
+                .map(|line| format!("{}\n", line))
+                .collect::<String>(),
+            _ => String::new(),
+        };
+
+        let data = format!("{}{}", header, data).into_bytes();
+        let data = match crypt {
+            Some(crypt) => crypt.encrypt(&data)
+                .with_context(|| format!("Encrypting entity: {:?}", self.path))?,
+            None => data,
+        };
+
+        let temp = self.path.with_extension("yaml.tmp");
+
+        let mut file = tokio::fs::File::create(&temp).await
+            .with_context(|| format!("Creating entity: {:?}", temp))?;
+        file.write_all(&data).await
+            .with_context(|| format!("Writing entity: {:?}", temp))?;
+
+        // The content must be on disk before replacing the original file to never leave a truncated file behind
+        file.sync_all().await
+            .with_context(|| format!("Syncing entity: {:?}", temp))?;
+
+        tokio::fs::rename(&temp, &self.path).await
+            .with_context(|| format!("Replacing entity: {:?}", self.path))?;
+
+        self.version = T::VERSION;
+        return Ok(());
+    }
+}
+
+#[derive(Clone)]
 pub struct DirContainer<T>
     where
         T: DeserializeOwned,
 {
     path: PathBuf,
     data: Vec<DirEntity<T>>,
+
+    /// Positions of the entities in data by their names
+    index: HashMap<String, usize>,
+
+    skipped: Vec<(PathBuf, String)>,
+
+    /// Keys for encrypted files, all written files are encrypted if given
+    crypt: Option<Arc<Crypt>>,
 }
 
 impl<T> DirContainer<T>
     where
         T: DeserializeOwned,
 {
-    pub async fn load(path: impl AsRef<Path>) -> Result<Self> {
+    /// Looks up an entity by its name
+    pub fn get(&self, name: &str) -> Option<&Named<T>> {
+        return self.index.get(name)
+            .map(|index| &self.data[*index].data);
+    }
+
+    fn reindex(&mut self) {
+        self.index = self.data.iter()
+            .enumerate()
+            .map(|(index, entity)| (entity.data.name.clone(), index))
+            .collect();
+    }
+}
+
+impl<T> DirContainer<T>
+    where
+        T: Versioned + Send + 'static,
+{
+    /// Loads all entities from the directory or starts empty if the directory does not exist
+    pub async fn load_optional(path: impl AsRef<Path>, nested: bool, crypt: Option<Arc<Crypt>>) -> Result<Self> {
         let path = path.as_ref();
 
-        let mut dir = tokio::fs::read_dir(path).await
-            .with_context(|| format!("Reading dir: {:?}", path))?;
+        if !path.exists() {
+            return Ok(Self {
+                path: path.to_owned(),
+                data: Vec::new(),
+                index: HashMap::new(),
+                skipped: Vec::new(),
+                crypt,
+            });
+        }
 
-        let mut data = Vec::new();
+        return Self::load(path, nested, crypt).await;
+    }
 
-        while let Some(entry) = dir.next_entry().await? {
-            let name = entry.file_name();
-            let name = if let Some(name) = name.to_str() { name } else {
-                warn!("Ignoring entity with invalid filename: {:?}", entry.path());
-                continue;
-            };
+    /// Loads all entities from the directory including its subdirectories if nested
+    ///
+    /// Names of entities must be unique across all subdirectories, all further entities of the same name are skipped.
+    pub async fn load(path: impl AsRef<Path>, nested: bool, crypt: Option<Arc<Crypt>>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let mut entries: Vec<(PathBuf, Vec<String>, String)> = Vec::new();
+        let mut skipped = Vec::new();
+
+        let mut dirs = vec![(path.to_owned(), Vec::new())];
+        while let Some((dir_path, units)) = dirs.pop() {
+            let mut dir = tokio::fs::read_dir(&dir_path).await
+                .with_context(|| format!("Reading dir: {:?}", dir_path))?;
 
-            if !name.ends_with(".yaml") {
-                warn!("Ignoring entity with wrong extension: {:?}", entry.path());
-                continue;
+            while let Some(entry) = dir.next_entry().await? {
+                let name = entry.file_name();
+                let name = if let Some(name) = name.to_str() { name } else {
+                    warn!("Ignoring entity with invalid filename: {:?}", entry.path());
+                    skipped.push((entry.path(), "invalid filename".to_string()));
+                    continue;
+                };
+
+                if nested && entry.file_type().await?.is_dir() {
+                    let mut units = units.clone();
+                    units.push(name.to_owned());
+                    dirs.push((entry.path(), units));
+                    continue;
+                }
+
+                if !name.ends_with(".yaml") {
+                    warn!("Ignoring entity with wrong extension: {:?}", entry.path());
+                    skipped.push((entry.path(), "wrong extension".to_string()));
+                    continue;
+                }
+
+                // Stripping .yaml file extension
+                let name = &name[..name.len() - 5];
+
+                if entries.iter().any(|(_, _, other)| other == name) {
+                    warn!("Ignoring entity with duplicate name: {:?}", entry.path());
+                    skipped.push((entry.path(), "duplicate name".to_string()));
+                    continue;
+                }
+
+                entries.push((entry.path(), units.clone(), name.to_owned()));
             }
+        }
 
-            // Stripping .yaml file extension
-            let name = &name[..name.len() - 5];
-            debug!("Loading entity: {:?} as {}", entry.path(), name);
+        // Entities are read and parsed concurrently on the runtime workers while keeping their order
+        let total = entries.len();
+        let mut loading = futures::stream::iter(entries)
+            .map(|(path, units, name)| {
+                let crypt = crypt.clone();
+                return tokio::spawn(async move {
+                    debug!("Loading entity: {:?} as {}", path, name);
+                    return DirEntity::load(&path, units, name, crypt.as_deref()).await
+                        .with_context(|| format!("Loading entity: {:?}", path));
+                });
+            })
+            .buffered(PARALLELISM);
 
-            let entity = DirEntity::load(entry.path(), name.to_owned()).await
-                .with_context(|| format!("Loading entity: {:?}", entry.path()))?;
+        let mut data = Vec::with_capacity(total);
+        while let Some(entity) = loading.next().await {
+            data.push(entity??);
 
-            data.push(entity);
+            if data.len() % PROGRESS_INTERVAL == 0 {
+                info!("Loaded {} of {} entities from {:?}", data.len(), total, path);
+            }
         }
 
-        return Ok(Self {
+        let mut container = Self {
             path: path.to_owned(),
             data,
-        });
+            index: HashMap::new(),
+            skipped,
+            crypt: crypt.clone(),
+        };
+        container.reindex();
+
+        return Ok(container);
     }
 
     pub fn iter(&self) -> impl Iterator<Item=&Named<T>> {
         return self.data.iter().map(|v| &v.data);
     }
+
+    /// Files in the directory which have not been loaded and the reason why
+    pub fn skipped(&self) -> &[(PathBuf, String)] {
+        return &self.skipped;
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item=&mut Named<T>> {
+        return self.data.iter_mut().map(|v| &mut v.data);
+    }
+}
+
+impl<T> DirContainer<T>
+    where
+        T: Versioned + Serialize,
+{
+    /// Creates a new entity and writes it to the directory or the given subdirectory
+    pub async fn insert(&mut self, units: Vec<String>, name: String, data: T) -> Result<()> {
+        if !is_valid_name(&name) {
+            bail!("Invalid entity name: {:?}", name);
+        }
+
+        if let Some(unit) = units.iter().find(|unit| !is_valid_name(unit)) {
+            bail!("Invalid unit name: {:?}", unit);
+        }
+
+        if self.index.contains_key(&name) {
+            bail!("Entity already exists: {}", name);
+        }
+
+        let dir = units.iter().fold(self.path.clone(), |dir, unit| dir.join(unit));
+        tokio::fs::create_dir_all(&dir).await
+            .with_context(|| format!("Creating dir: {:?}", dir))?;
+
+        let mut entity = DirEntity {
+            path: dir.join(format!("{}.yaml", name)),
+            data: Named {
+                name,
+                units,
+                created: Some(SystemTime::now()),
+                modified: Some(SystemTime::now()),
+                data,
+            },
+            version: T::VERSION,
+        };
+
+        entity.save(self.crypt.as_deref()).await?;
+        self.index.insert(entity.data.name.clone(), self.data.len());
+        self.data.push(entity);
+
+        return Ok(());
+    }
+
+    /// Writes all entities loaded from files of older versions in the current version and returns their number
+    pub async fn migrate(&mut self) -> Result<usize> {
+        let mut migrated = 0;
+        for entity in self.data.iter_mut().filter(|entity| entity.version < T::VERSION) {
+            entity.save(self.crypt.as_deref()).await?;
+            migrated += 1;
+        }
+
+        return Ok(migrated);
+    }
+
+    /// Applies changes to an existing entity and writes it to the directory
+    pub async fn update(&mut self, name: &str, f: impl FnOnce(&mut T)) -> Result<()> {
+        let index = *self.index.get(name)
+            .with_context(|| format!("No such entity: {}", name))?;
+        let entity = &mut self.data[index];
+
+        f(&mut entity.data.data);
+        entity.save(self.crypt.as_deref()).await?;
+        entity.data.modified = Some(SystemTime::now());
+
+        return Ok(());
+    }
+
+    /// Renames an existing entity including its file which is kept in its directory
+    pub async fn rename(&mut self, name: &str, new_name: String) -> Result<()> {
+        if !is_valid_name(&new_name) {
+            bail!("Invalid entity name: {:?}", new_name);
+        }
+
+        if self.index.contains_key(&new_name) {
+            bail!("Entity already exists: {}", new_name);
+        }
+
+        let index = *self.index.get(name)
+            .with_context(|| format!("No such entity: {}", name))?;
+        let entity = &mut self.data[index];
+
+        let path = entity.path.with_file_name(format!("{}.yaml", new_name));
+
+        tokio::fs::rename(&entity.path, &path).await
+            .with_context(|| format!("Renaming entity: {:?} to {:?}", entity.path, path))?;
+
+        entity.path = path;
+        entity.data.name = new_name.clone();
+
+        self.index.remove(name);
+        self.index.insert(new_name, index);
+
+        return Ok(());
+    }
+
+    /// Removes an existing entity including its file in the directory
+    pub async fn remove(&mut self, name: &str) -> Result<()> {
+        let index = *self.index.get(name)
+            .with_context(|| format!("No such entity: {}", name))?;
+
+        let path = &self.data[index].path;
+        tokio::fs::remove_file(path).await
+            .with_context(|| format!("Removing entity: {:?}", path))?;
+
+        self.data.remove(index);
+        self.reindex();
+
+        return Ok(());
+    }
+
+    /// Replaces all entities by the given ones, writing only the entities which have changed
+    ///
+    /// Entities placed in other subdirectories are moved. Returns the names of the changed entities with their former
+    /// data.
+    pub async fn replace_all(&mut self, entities: Vec<(Vec<String>, String, T)>) -> Result<Vec<(String, Option<T>)>>
+        where
+            T: Clone + PartialEq,
+    {
+        // Checked up front to not leave a partially replaced directory behind
+        for (units, name, _) in &entities {
+            if !is_valid_name(name) {
+                bail!("Invalid entity name: {:?}", name);
+            }
+
+            if let Some(unit) = units.iter().find(|unit| !is_valid_name(unit)) {
+                bail!("Invalid unit name: {:?}", unit);
+            }
+        }
+
+        let mut changed = Vec::new();
+
+        let names = entities.iter()
+            .map(|(_, name, _)| name.clone())
+            .collect::<HashSet<_>>();
+        let stale = self.data.iter()
+            .map(|entity| entity.data.name.clone())
+            .filter(|name| !names.contains(name))
+            .collect::<Vec<_>>();
+
+        for name in stale {
+            let before = self.get(&name).map(|entity| entity.data.clone());
+            self.remove(&name).await?;
+            changed.push((name, before));
+        }
+
+        for (units, name, data) in entities {
+            let before = match self.get(&name) {
+                Some(before) if before.units == units && before.data == data => continue,
+                Some(before) => Some((before.units.clone(), before.data.clone())),
+                None => None,
+            };
+
+            match before {
+                Some((before_units, before)) if before_units == units => {
+                    self.update(&name, |entity| *entity = data).await?;
+                    changed.push((name, Some(before)));
+                }
+                Some((_, before)) => {
+                    self.remove(&name).await?;
+                    self.insert(units, name.clone(), data).await?;
+                    changed.push((name, Some(before)));
+                }
+                None => {
+                    self.insert(units, name.clone(), data).await?;
+                    changed.push((name, None));
+                }
+            }
+        }
+
+        return Ok(changed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Entity {
+        display_name: String,
+    }
+
+    impl Versioned for Entity {
+        const VERSION: u32 = 2;
+
+        fn migrate(version: u32, value: &mut serde_yaml::Mapping) -> Result<()> {
+            match version {
+                1 => {
+                    let name = value.remove("name").context("Missing name")?;
+                    value.insert("display_name".into(), name);
+                }
+                _ => bail!("No migration from version {}", version),
+            }
+            return Ok(());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let dir = std::env::temp_dir().join(format!("concess-test-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        tokio::fs::write(dir.join("old.yaml"), "name: 'Old'\n").await.unwrap();
+        tokio::fs::write(dir.join("current.yaml"), "version: 2\ndisplay_name: 'Current'\n").await.unwrap();
+
+        let mut container = DirContainer::<Entity>::load(&dir, false, None).await.unwrap();
+        assert_eq!(container.get("old").unwrap().display_name, "Old");
+        assert_eq!(container.get("current").unwrap().display_name, "Current");
+
+        assert_eq!(container.migrate().await.unwrap(), 1);
+        assert_eq!(container.migrate().await.unwrap(), 0);
+        assert_eq!(tokio::fs::read_to_string(dir.join("old.yaml")).await.unwrap(), "version: 2\ndisplay_name: Old\n");
+
+        tokio::fs::write(dir.join("new.yaml"), "version: 3\ndisplay_name: 'New'\n").await.unwrap();
+        assert!(DirContainer::<Entity>::load(&dir, false, None).await.is_err());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn test_valid_name() {
+        assert!(is_valid_name("brain"));
+        assert!(is_valid_name("pinky.brain"));
+
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name(".."));
+        assert!(!is_valid_name(".hidden"));
+        assert!(!is_valid_name("../users/brain"));
+        assert!(!is_valid_name("lab\\brain"));
+        assert!(!is_valid_name("brain\0"));
+    }
 }
\ No newline at end of file
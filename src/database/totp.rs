@@ -0,0 +1,95 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use data_encoding::BASE32_NOPAD;
+use sha1::{Digest, Sha1};
+
+/// Number of digits of a one-time password
+pub const DIGITS: usize = 6;
+
+/// Seconds each one-time password is valid for
+const STEP: u64 = 30;
+
+/// Number of steps before and after the current one accepted to tolerate clock skew
+const WINDOW: u64 = 1;
+
+/// Decodes a base32 encoded secret as used by authenticator apps, ignoring case, padding and whitespace
+pub fn decode_secret(secret: &str) -> Option<Vec<u8>> {
+    let secret = secret.chars()
+        .filter(|c| !c.is_whitespace() && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect::<String>();
+
+    return BASE32_NOPAD.decode(secret.as_bytes()).ok()
+        .filter(|secret| !secret.is_empty());
+}
+
+/// Verifies a time-based one-time password (RFC 6238) against the current time
+pub fn verify(secret: &str, code: &[u8]) -> bool {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    return verify_at(secret, code, now);
+}
+
+fn verify_at(secret: &str, code: &[u8], time: u64) -> bool {
+    let secret = if let Some(secret) = decode_secret(secret) { secret } else {
+        return false;
+    };
+
+    let counter = time / STEP;
+    return (counter.saturating_sub(WINDOW)..=counter + WINDOW)
+        .any(|counter| format!("{:0width$}", hotp(&secret, counter), width = DIGITS).as_bytes() == code);
+}
+
+/// HMAC-based one-time password (RFC 4226)
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let hash = hmac_sha1(secret, &counter.to_be_bytes());
+
+    // Dynamic truncation selects four bytes by the low nibble of the last byte
+    let offset = (hash[19] & 0x0f) as usize;
+    let value = u32::from_be_bytes([hash[offset], hash[offset + 1], hash[offset + 2], hash[offset + 3]]) & 0x7fff_ffff;
+
+    return value % 10u32.pow(DIGITS as u32);
+}
+
+/// HMAC-SHA1 (RFC 2104)
+pub fn hmac_sha1(key: &[u8], data: &[u8]) -> [u8; 20] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..20].copy_from_slice(&Sha1::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner = Sha1::digest([&block.map(|b| b ^ 0x36)[..], data].concat());
+    let outer = Sha1::digest([&block.map(|b| b ^ 0x5c)[..], &inner[..]].concat());
+    return outer.into();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Test vectors from RFC 6238 using the ASCII secret "12345678901234567890" truncated to six digits
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn test_verify() {
+        assert!(verify_at(SECRET, b"287082", 59));
+        assert!(verify_at(SECRET, b"081804", 1111111109));
+        assert!(verify_at(SECRET, b"050471", 1111111111));
+        assert!(verify_at(SECRET, b"279037", 2000000000));
+
+        // Neighbouring steps are accepted, but not beyond
+        assert!(verify_at(SECRET, b"081804", 1111111109 + STEP));
+        assert!(!verify_at(SECRET, b"081804", 1111111109 + 2 * STEP));
+
+        assert!(!verify_at(SECRET, b"000000", 59));
+        assert!(!verify_at("not base32!", b"287082", 59));
+    }
+
+    #[test]
+    fn test_decode_secret() {
+        assert_eq!(decode_secret("gezd gnbv gy3t qojq"), Some(b"1234567890".to_vec()));
+        assert_eq!(decode_secret(""), None);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,152 @@
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+
+use super::password::PasswordHash;
+
+/// Name of the verifier checking the stored password hash, which is used unless configured otherwise
+pub const HASH: &str = "hash";
+
+/// Way of verifying the password of a user
+#[async_trait]
+pub trait Verifier: Send + Sync {
+    /// Checks the password of the named user having the given stored hash, failing if unable to decide
+    async fn verify(&self, name: &str, hash: &PasswordHash, password: &[u8]) -> Result<bool>;
+}
+
+/// Verifies the password against the hash stored for the user
+pub struct Hash;
+
+#[async_trait]
+impl Verifier for Hash {
+    async fn verify(&self, _name: &str, hash: &PasswordHash, password: &[u8]) -> Result<bool> {
+        return Ok(hash.verify(password));
+    }
+}
+
+/// Alternative verifier selectable by name in the config and by users
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Config {
+    Command(Command),
+    Ldap(Ldap),
+}
+
+impl Config {
+    pub fn verifier(&self) -> &dyn Verifier {
+        return match self {
+            Config::Command(command) => command,
+            Config::Ldap(ldap) => ldap,
+        };
+    }
+}
+
+/// Runs a program getting the name and the password as separate lines on stdin, accepting the password if it exits
+/// successfully
+///
+/// This is the interface of `pwauth`, which checks passwords using the PAM stack of the host.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Command {
+    /// Program followed by its arguments
+    pub command: Vec<String>,
+
+    /// Time in seconds the program has to decide
+    #[serde(default = "Command::default_timeout")]
+    pub timeout: u64,
+}
+
+impl Command {
+    fn default_timeout() -> u64 {
+        return 10;
+    }
+}
+
+#[async_trait]
+impl Verifier for Command {
+    async fn verify(&self, name: &str, _hash: &PasswordHash, password: &[u8]) -> Result<bool> {
+        // Line breaks would allow to smuggle further input to the program
+        if name.contains('\n') || password.contains(&b'\n') {
+            return Ok(false);
+        }
+
+        let (program, args) = self.command.split_first()
+            .context("No command given")?;
+
+        let mut child = tokio::process::Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Running {}", program))?;
+
+        let mut stdin = child.stdin.take().context("No stdin")?;
+        stdin.write_all(&[name.as_bytes(), b"\n", password, b"\n"].concat()).await
+            .context("Writing to stdin")?;
+        drop(stdin);
+
+        let status = tokio::time::timeout(Duration::from_secs(self.timeout), child.wait()).await
+            .with_context(|| format!("Timeout while running {}", program))??;
+
+        return Ok(status.success());
+    }
+}
+
+/// Binds to another LDAP server as the user, like an Active Directory the users actually live in
+#[derive(Debug, Clone, Deserialize)]
+pub struct Ldap {
+    /// LDAP URL of the server like `ldaps://dc.example.com`
+    pub url: String,
+
+    /// PEM encoded CA certificates used to verify the server, required for `ldaps`
+    pub ca: Option<PathBuf>,
+
+    /// DN to bind as, where `{name}` is replaced by the name of the user, e.g. `uid={name},ou=people,dc=example,dc=com`
+    pub bind_dn: String,
+
+    /// Time in seconds to wait for each response of the server
+    #[serde(default = "Ldap::default_timeout")]
+    pub timeout: u64,
+}
+
+impl Ldap {
+    fn default_timeout() -> u64 {
+        return 10;
+    }
+}
+
+#[async_trait]
+impl Verifier for Ldap {
+    async fn verify(&self, name: &str, _hash: &PasswordHash, password: &[u8]) -> Result<bool> {
+        let password = match std::str::from_utf8(password) {
+            Ok(password) => password,
+            Err(_) => return Ok(false),
+        };
+
+        return crate::ldap::verify_bind(&self.url, self.ca.as_deref(), self.timeout, &self.bind_dn, name, password).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_command() {
+        let command = Command {
+            command: vec!["sh".to_owned(), "-c".to_owned(), "read name && read password && [ \"$name:$password\" = 'brain:narf' ]".to_owned()],
+            timeout: 10,
+        };
+        let hash = PasswordHash::parse("$2b$04$nvfCqDo6Hb7BNJBIH6jPv.gPnh8kunRQJ7slLY7g0cYpB0sRdSbny").unwrap();
+
+        assert!(command.verify("brain", &hash, b"narf").await.unwrap());
+        assert!(!command.verify("brain", &hash, b"secret").await.unwrap());
+        assert!(!command.verify("brain", &hash, b"narf\nnarf").await.unwrap());
+        assert!(Hash.verify("brain", &hash, b"secret").await.unwrap());
+    }
+}
\ No newline at end of file
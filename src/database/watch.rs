@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use notify::{RecursiveMode, Watcher};
+use rand_core::{OsRng, RngCore};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use super::{Config, Database, SharedDatabase};
+use super::config::Reload;
+
+/// Waits for a burst of file events to settle down
+///
+/// Returns `false` if the event source has been closed.
+async fn settle(events: &mut mpsc::UnboundedReceiver<notify::Result<notify::Event>>, config: &Reload) -> bool {
+    // Wait for the first event of a burst
+    if events.recv().await.is_none() {
+        return false;
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(config.max_delay);
+
+    loop {
+        let jitter = OsRng.next_u64() % (config.jitter + 1);
+        let quiet = Instant::now() + Duration::from_millis(config.quiet_period + jitter);
+
+        match tokio::time::timeout_at(quiet.min(deadline), events.recv()).await {
+            Ok(Some(_)) if Instant::now() < deadline => continue,
+            Ok(Some(_)) => return true,
+            Ok(None) => return false,
+            Err(_) => return true,
+        }
+    }
+}
+
+/// Replaces the database with a fresh copy from the data directory
+///
+/// The fresh copy is rejected if files failed to load which have been loaded before, as this is most likely caused by
+/// files written while reading.
+pub async fn reload(path: &Path, config: &Config, database: &SharedDatabase) -> Result<()> {
+    let fresh = Database::read(path, config).await?;
+
+    let mut database = database.write().await;
+
+    let skipped = database.report.skipped.iter()
+        .map(|(path, _)| path)
+        .collect::<HashSet<&PathBuf>>();
+    if let Some((path, reason)) = fresh.report.skipped.iter().find(|(path, _)| !skipped.contains(path)) {
+        warn!("Keeping current database as {:?} failed to load: {}", path, reason);
+        return Ok(());
+    }
+
+    fresh.report.log();
+
+    if config.strict && !fresh.report.is_clean() {
+        warn!("Keeping current database as the fresh copy has findings not permitted in strict mode");
+        return Ok(());
+    }
+
+    database.replace(fresh);
+
+    info!("Database reloaded");
+    return Ok(());
+}
+
+/// Reloads the database whenever the data directory changes
+pub async fn watch(path: impl AsRef<Path>,
+                   config: &Config,
+                   database: Arc<SharedDatabase>,
+                   shutdown: impl Future) -> Result<()> {
+    let reload_config = if let Some(reload) = &config.reload { reload } else {
+        return Ok(());
+    };
+
+    let path = path.as_ref();
+
+    let (tx, mut events) = mpsc::unbounded_channel();
+
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    }).context("Creating file watcher")?;
+
+    watcher.watch(path, RecursiveMode::Recursive)
+        .with_context(|| format!("Watching {:?}", path))?;
+
+    let watch = async {
+        while settle(&mut events, reload_config).await {
+            debug!("Data directory changed");
+            if let Err(err) = reload(path, config, &database).await {
+                warn!("Failed to reload database: {:#}", err);
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = shutdown => {
+            info!("File watcher is shutting down");
+        }
+
+        _ = watch => {}
+    }
+
+    return Ok(());
+}
\ No newline at end of file
@@ -0,0 +1,113 @@
+use serde::Deserialize;
+
+use super::dn::DN;
+
+/// Level of access to entries where higher levels include the lower ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Access {
+    None,
+
+    /// Find entries by searching, read and compare their attributes
+    Read,
+
+    /// Modify, add and rename entries
+    Write,
+}
+
+/// Clients a rule applies to
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Subject {
+    Anyone,
+
+    /// Clients bound as any user
+    Authenticated,
+
+    /// The user bound as the entry itself
+    #[serde(rename = "self")]
+    Own,
+
+    /// Users which are members of the group
+    Group(String),
+}
+
+impl Subject {
+    fn matches(&self, entry: &DN, bound: Option<&DN>, groups: &[String]) -> bool {
+        return match self {
+            Subject::Anyone => true,
+            Subject::Authenticated => bound.is_some(),
+            Subject::Own => bound == Some(entry),
+            Subject::Group(group) => bound.is_some() && groups.contains(group),
+        };
+    }
+}
+
+/// Grants access to all entries in a subtree
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    pub subtree: DN,
+    pub who: Subject,
+    pub access: Access,
+}
+
+/// Access control list where the highest access granted by any matching rule applies
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Acl {
+    rules: Vec<Rule>,
+}
+
+impl Acl {
+    /// Returns the access to the entry for a client bound as the given DN being member of the given groups
+    ///
+    /// Access is not restricted at all as long as no rules are configured.
+    pub fn access(&self, entry: &DN, bound: Option<&DN>, groups: &[String]) -> Access {
+        if self.rules.is_empty() {
+            return Access::Write;
+        }
+
+        return self.rules.iter()
+            .filter(|rule| entry.is_descendant_of(&rule.subtree))
+            .filter(|rule| rule.who.matches(entry, bound, groups))
+            .map(|rule| rule.access)
+            .max()
+            .unwrap_or(Access::None);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_access() {
+        let acl: Acl = serde_yaml::from_str(r#"
+            - subtree: 'ou=users,dc=example,dc=com'
+              who: !group 'admin'
+              access: 'write'
+            - subtree: 'ou=users,dc=example,dc=com'
+              who: 'self'
+              access: 'read'
+            - subtree: 'ou=groups,dc=example,dc=com'
+              who: 'authenticated'
+              access: 'read'
+        "#).unwrap();
+
+        let brain = DN::from_str("cn=brain,ou=users,dc=example,dc=com").unwrap();
+        let pinky = DN::from_str("cn=pinky,ou=users,dc=example,dc=com").unwrap();
+        let group = DN::from_str("cn=member,ou=groups,dc=example,dc=com").unwrap();
+
+        let admin = ["admin".to_string()];
+
+        assert_eq!(acl.access(&pinky, Some(&brain), &admin), Access::Write);
+        assert_eq!(acl.access(&pinky, Some(&pinky), &[]), Access::Read);
+        assert_eq!(acl.access(&brain, Some(&pinky), &[]), Access::None);
+        assert_eq!(acl.access(&group, Some(&pinky), &[]), Access::Read);
+        assert_eq!(acl.access(&group, None, &[]), Access::None);
+
+        assert_eq!(Acl::default().access(&pinky, None, &[]), Access::Write);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,95 @@
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use uuid::Uuid;
+
+use crate::database::Alias;
+
+use super::dn::{AttributeName, DN, dn};
+use super::entities::{ATTR_CN, ATTR_CREATE_TIMESTAMP, ATTR_ENTRY_DN, ATTR_ENTRY_UUID, ATTR_MODIFY_TIMESTAMP, ATTR_OBJECT_CLASS, Entity, generalized_time};
+
+pub const ATTR_ALIASED_OBJECT_NAME: AttributeName = AttributeName::from("aliasedObjectName");
+
+/// Maximum number of aliases followed when dereferencing the search base
+pub const MAX_DEREF_HOPS: usize = 8;
+
+/// Alias entry with its DN and the DN of the aliased entry resolved against the base DN
+pub struct AliasEntry {
+    name: String,
+    dn: DN,
+    pub target: DN,
+
+    uuid: Uuid,
+    created: Option<SystemTime>,
+    modified: Option<SystemTime>,
+}
+
+impl AliasEntry {
+    /// Resolves the alias unless its parent or target are not valid DNs
+    pub fn resolve(alias: Alias, base_dn: &DN) -> Option<Self> {
+        let parent = DN::from_str(alias.parent).ok()?;
+        let target = DN::from_str(alias.target).ok()?;
+
+        return Some(Self {
+            name: alias.name.to_owned(),
+            dn: dn!["cn" => alias.name; base_dn.join(parent)],
+            target: base_dn.join(target),
+            uuid: alias.uuid,
+            created: alias.created,
+            modified: alias.modified,
+        });
+    }
+}
+
+impl Entity for AliasEntry {
+    const OBJECT_CLASSES: &'static [&'static str] = &[
+        "alias",
+        "extensibleObject",
+    ];
+
+    const ATTRIBUTES: &'static [AttributeName] = &[
+        ATTR_OBJECT_CLASS,
+        ATTR_ENTRY_DN,
+        ATTR_ENTRY_UUID,
+        ATTR_CREATE_TIMESTAMP,
+        ATTR_MODIFY_TIMESTAMP,
+        ATTR_CN,
+        ATTR_ALIASED_OBJECT_NAME,
+    ];
+
+    fn dn(&self) -> DN {
+        return self.dn.clone();
+    }
+
+    fn get(attribute: &AttributeName) -> Option<for<'a> fn(&'a Self) -> Vec<String>> {
+        if attribute == &ATTR_OBJECT_CLASS {
+            return Some(|_| Self::object_classes());
+        }
+
+        if attribute == &ATTR_ENTRY_DN {
+            return Some(|e| vec![e.dn().to_string()]);
+        }
+
+        if attribute == &ATTR_ENTRY_UUID {
+            return Some(|e| vec![e.uuid.to_string()]);
+        }
+
+        if attribute == &ATTR_CREATE_TIMESTAMP {
+            return Some(|e| e.created.map(generalized_time).into_iter().collect());
+        }
+
+        if attribute == &ATTR_MODIFY_TIMESTAMP {
+            return Some(|e| e.modified.map(generalized_time).into_iter().collect());
+        }
+
+        if attribute == &ATTR_CN {
+            return Some(|e| vec![e.name.clone()]);
+        }
+
+        if attribute == &ATTR_ALIASED_OBJECT_NAME {
+            return Some(|e| vec![e.target.to_string()]);
+        }
+
+        return None;
+    }
+}
\ No newline at end of file
@@ -0,0 +1,115 @@
+use serde::Deserialize;
+
+/// Algorithm used to evaluate approximate match filters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApproxMatch {
+    /// Values match if they are equal after folding case and diacritics
+    #[default]
+    Fold,
+
+    /// Values match if they sound alike according to the Soundex algorithm
+    Soundex,
+}
+
+impl ApproxMatch {
+    pub fn matches(&self, value: &str, assertion: &str) -> bool {
+        return match self {
+            ApproxMatch::Fold => fold(value) == fold(assertion),
+            ApproxMatch::Soundex => match (soundex(value), soundex(assertion)) {
+                (Some(value), Some(assertion)) => value == assertion,
+                _ => fold(value) == fold(assertion),
+            },
+        };
+    }
+}
+
+/// Lowercases the value and replaces latin letters with diacritics by their base letters
+fn fold(value: &str) -> String {
+    return value.trim().to_lowercase().chars()
+        .map(|c| match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+            'ç' | 'ć' | 'ĉ' | 'č' => "c",
+            'ď' | 'đ' => "d",
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+            'ğ' | 'ĝ' | 'ġ' | 'ģ' => "g",
+            'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' | 'ı' => "i",
+            'ł' | 'ľ' | 'ĺ' | 'ļ' => "l",
+            'ñ' | 'ń' | 'ň' | 'ņ' => "n",
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+            'ŕ' | 'ř' => "r",
+            'ś' | 'ŝ' | 'ş' | 'š' => "s",
+            'ß' => "ss",
+            'ţ' | 'ť' => "t",
+            'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' | 'ų' => "u",
+            'ý' | 'ÿ' => "y",
+            'ź' | 'ż' | 'ž' => "z",
+            'æ' => "ae",
+            'œ' => "oe",
+            c => return c.to_string(),
+        }.to_string())
+        .collect();
+}
+
+/// Soundex code of the first word of the value or `None` if it does not start with a letter
+fn soundex(value: &str) -> Option<String> {
+    fn code(c: char) -> Option<char> {
+        return match c {
+            'b' | 'f' | 'p' | 'v' => Some('1'),
+            'c' | 'g' | 'j' | 'k' | 'q' | 's' | 'x' | 'z' => Some('2'),
+            'd' | 't' => Some('3'),
+            'l' => Some('4'),
+            'm' | 'n' => Some('5'),
+            'r' => Some('6'),
+            _ => None,
+        };
+    }
+
+    let folded = fold(value);
+    let mut letters = folded.chars()
+        .take_while(|c| c.is_ascii_alphabetic());
+
+    let first = letters.next()?;
+
+    let mut result = first.to_ascii_uppercase().to_string();
+    let mut last = code(first);
+    for c in letters {
+        let current = code(c);
+        if current.is_some() && current != last {
+            result.extend(current);
+        }
+
+        // Letters with the same code separated by 'h' or 'w' are coded once
+        if c != 'h' && c != 'w' {
+            last = current;
+        }
+
+        if result.len() == 4 {
+            break;
+        }
+    }
+
+    return Some(format!("{:0<4}", result));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fold() {
+        assert!(ApproxMatch::Fold.matches("Müller", "muller"));
+        assert!(ApproxMatch::Fold.matches("Straße", "STRASSE"));
+        assert!(!ApproxMatch::Fold.matches("Müller", "Miller"));
+    }
+
+    #[test]
+    fn test_soundex() {
+        assert_eq!(soundex("Robert").as_deref(), Some("R163"));
+        assert_eq!(soundex("Rupert").as_deref(), Some("R163"));
+        assert_eq!(soundex("Ashcraft").as_deref(), Some("A261"));
+        assert_eq!(soundex("Tymczak").as_deref(), Some("T522"));
+        assert_eq!(soundex("Pfister").as_deref(), Some("P236"));
+        assert!(ApproxMatch::Soundex.matches("Müller", "Miller"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,96 @@
+use lber::common::TagClass;
+use lber::parse::parse_tag;
+use lber::structure::{PL, StructureTag};
+use lber::universal::Types;
+use ldap3_proto::LdapResultCode;
+use ldap3_proto::proto::{LdapExtendedResponse, LdapMsg, LdapOp, LdapResult};
+
+use super::response;
+
+/// Cancel extended operation (RFC 3909)
+pub const OID: &str = "1.3.6.1.1.8";
+
+const START_TLS_OID: &str = "1.3.6.1.4.1.1466.20037";
+
+// Result codes defined by RFC 3909 which are unknown to the protocol library
+pub const CANCELED: u8 = 118;
+pub const NO_SUCH_OPERATION: u8 = 119;
+pub const TOO_LATE: u8 = 120;
+
+pub fn is_request(req: &LdapMsg) -> bool {
+    return matches!(&req.op, LdapOp::ExtendedRequest(ext) if ext.name == OID);
+}
+
+/// Parses the message ID of the operation to cancel from the request value
+pub fn target(req: &LdapMsg) -> Option<i32> {
+    let value = match &req.op {
+        LdapOp::ExtendedRequest(ext) => ext.value.as_deref()?,
+        _ => return None,
+    };
+
+    let (_, tag) = parse_tag(value).ok()?;
+    let id = tag
+        .match_class(TagClass::Universal)
+        .and_then(|tag| tag.match_id(Types::Sequence as u64))
+        .and_then(|tag| tag.expect_constructed())
+        .and_then(|tags| tags.into_iter().next())
+        .and_then(|tag| tag.match_class(TagClass::Universal))
+        .and_then(|tag| tag.match_id(Types::Integer as u64))
+        .and_then(|tag| tag.expect_primitive())?;
+
+    if id.is_empty() || id.len() > 4 {
+        return None;
+    }
+
+    // Big-endian two's complement with sign extension of the first byte
+    return Some(id.iter()
+        .fold(if id[0] & 0x80 != 0 { -1 } else { 0 }, |acc, b| (acc << 8) | *b as i32));
+}
+
+/// Returns the result operation for requests which can be abandoned or canceled
+pub fn result_op(op: &LdapOp) -> Option<fn(LdapResult) -> LdapOp> {
+    return match op {
+        LdapOp::SearchRequest(_) => Some(LdapOp::SearchResultDone),
+        LdapOp::ModifyRequest(_) => Some(LdapOp::ModifyResponse),
+        LdapOp::AddRequest(_) => Some(LdapOp::AddResponse),
+        LdapOp::DelRequest(_) => Some(LdapOp::DelResponse),
+        LdapOp::ModifyDNRequest(_) => Some(LdapOp::ModifyDNResponse),
+        LdapOp::CompareRequest(_) => Some(LdapOp::CompareResult),
+        LdapOp::ExtendedRequest(ext) if ext.name != OID && ext.name != START_TLS_OID => Some(|res| LdapOp::ExtendedResponse(LdapExtendedResponse {
+            res,
+            name: None,
+            value: None,
+        })),
+        _ => None,
+    };
+}
+
+/// Encodes a result message using a result code not known to the protocol library
+pub fn with_result_code(msg: LdapMsg, code: u8) -> StructureTag {
+    let mut tag = StructureTag::from(msg);
+
+    if let PL::C(message) = &mut tag.payload {
+        if let Some(PL::C(result)) = message.get_mut(1).map(|op| &mut op.payload) {
+            if let Some(result_code) = result.first_mut() {
+                // All codes below 128 are encoded as a single byte
+                result_code.payload = PL::P(vec![code]);
+            }
+        }
+    }
+
+    return tag;
+}
+
+/// Final result of a canceled operation
+pub fn canceled(msgid: i32, op: fn(LdapResult) -> LdapOp) -> StructureTag {
+    return with_result_code(response(msgid, op, LdapResultCode::Success, ""), CANCELED);
+}
+
+/// Response to the cancel request itself
+pub fn cancel_response(msgid: i32, code: u8) -> StructureTag {
+    return with_result_code(response(msgid, |res| LdapOp::ExtendedResponse(LdapExtendedResponse {
+        res,
+        name: None,
+        value: None,
+    }), LdapResultCode::Success, ""), code);
+}
\ No newline at end of file
@@ -0,0 +1,157 @@
+use std::fmt::{Debug, Write as _};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use ldap3_proto::proto::{LdapBindCred, LdapMsg, LdapOp};
+use tracing::{trace, warn};
+
+use super::config::CaptureConfig;
+use super::Peer;
+
+/// Tracing target for captured protocol data units
+pub const CAPTURE: &str = "capture";
+
+const PASSWORD_MODIFY_OID: &str = "1.3.6.1.4.1.4203.1.11.1";
+
+const ATTR_USER_PASSWORD: &str = "userpassword";
+
+/// Records the protocol data units exchanged with a single client for debugging purposes
+pub struct Capture {
+    peer: Peer,
+    file: Option<Mutex<File>>,
+}
+
+impl Capture {
+    pub fn open(config: &CaptureConfig, peer: Peer, id: u64) -> Result<Self> {
+        let file = match config {
+            CaptureConfig::Log => None,
+            CaptureConfig::Directory(path) => {
+                let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                let path = Path::new(path).join(format!("session-{}-{}.log", started, id));
+
+                let mut file = OpenOptions::new().create(true).append(true).open(&path)
+                    .with_context(|| format!("Failed to open capture file {:?}", path))?;
+                writeln!(file, "# Session {} from {}", id, peer)?;
+
+                Some(Mutex::new(file))
+            }
+        };
+
+        return Ok(Self { peer, file });
+    }
+
+    /// Records a request with all secrets it carries replaced
+    pub fn request(&self, raw: &[u8], msg: &LdapMsg) {
+        let (msg, secrets) = redact(msg);
+
+        let mut raw = raw.to_vec();
+        for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+            let mut offset = 0;
+            while let Some(position) = raw[offset..].windows(secret.len()).position(|window| window == secret.as_slice()) {
+                let start = offset + position;
+                raw[start..start + secret.len()].fill(b'*');
+                offset = start + secret.len();
+            }
+        }
+
+        self.record("request", &raw, &msg);
+    }
+
+    pub fn response(&self, raw: &[u8], msg: &dyn Debug) {
+        self.record("response", raw, msg);
+    }
+
+    fn record(&self, direction: &str, raw: &[u8], decoded: &dyn Debug) {
+        let dump = hexdump(raw);
+
+        match &self.file {
+            Some(file) => {
+                let mut file = file.lock().unwrap();
+                if let Err(err) = writeln!(file, "{} {} bytes\n{:?}\n{}", direction, raw.len(), decoded, dump) {
+                    warn!("Failed to write capture of {}: {}", self.peer, err);
+                }
+            }
+            None => trace!(target: CAPTURE, "{} {} from {}: {:?}\n{}", direction, raw.len(), self.peer, decoded, dump),
+        }
+    }
+}
+
+/// Replaces passwords in the request and returns the original values
+fn redact(msg: &LdapMsg) -> (LdapMsg, Vec<Vec<u8>>) {
+    let mut msg = msg.clone();
+    let mut secrets = Vec::new();
+
+    let mut replace = |value: &mut Vec<u8>| {
+        secrets.push(std::mem::replace(value, vec![b'*'; value.len()]));
+    };
+
+    match &mut msg.op {
+        LdapOp::BindRequest(req) => match &mut req.cred {
+            LdapBindCred::Simple(pw) => {
+                secrets.push(pw.as_bytes().to_vec());
+                *pw = "*".repeat(pw.len());
+            }
+            LdapBindCred::SASL(sasl) => replace(&mut sasl.credentials),
+        },
+
+        LdapOp::ExtendedRequest(req) if req.name == PASSWORD_MODIFY_OID => {
+            if let Some(value) = &mut req.value {
+                replace(value);
+            }
+        }
+
+        LdapOp::AddRequest(req) => req.attributes.iter_mut()
+            .filter(|attribute| attribute.atype.to_lowercase() == ATTR_USER_PASSWORD)
+            .flat_map(|attribute| attribute.vals.iter_mut())
+            .for_each(replace),
+
+        LdapOp::ModifyRequest(req) => req.changes.iter_mut()
+            .map(|change| &mut change.modification)
+            .filter(|attribute| attribute.atype.to_lowercase() == ATTR_USER_PASSWORD)
+            .flat_map(|attribute| attribute.vals.iter_mut())
+            .for_each(replace),
+
+        LdapOp::CompareRequest(req) if req.atype.to_lowercase() == ATTR_USER_PASSWORD => replace(&mut req.val),
+
+        _ => {}
+    }
+
+    return (msg, secrets);
+}
+
+fn hexdump(raw: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in raw.chunks(16).enumerate() {
+        let hex = chunk.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+        let text = chunk.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }).collect::<String>();
+        let _ = writeln!(dump, "{:08x}  {:<47}  |{}|", line * 16, hex, text);
+    }
+    return dump;
+}
+
+#[cfg(test)]
+mod test {
+    use ldap3_proto::proto::LdapBindRequest;
+
+    use super::*;
+
+    #[test]
+    fn test_redact_bind() {
+        let msg = LdapMsg {
+            msgid: 1,
+            op: LdapOp::BindRequest(LdapBindRequest {
+                dn: "cn=brain".to_string(),
+                cred: LdapBindCred::Simple("secret".to_string()),
+            }),
+            ctrl: vec![],
+        };
+
+        let (redacted, secrets) = redact(&msg);
+        assert_eq!(secrets, vec![b"secret".to_vec()]);
+        assert!(matches!(redacted.op, LdapOp::BindRequest(LdapBindRequest { cred: LdapBindCred::Simple(ref pw), .. }) if pw == "******"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,187 @@
+use std::io;
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use lber::common::TagClass;
+use lber::parse::parse_tag;
+use lber::structure::{PL, StructureTag};
+use lber::universal::Types;
+use ldap3_proto::LdapCodec;
+use ldap3_proto::control::LdapControl;
+use ldap3_proto::proto::LdapMsg;
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::capture::Capture;
+use super::psearch;
+
+/// Controls handled by the server itself which are never passed to the protocol library
+const OWN_CONTROLS: &[&str] = &[psearch::OID];
+
+/// Maximum size of a single encoded request
+const MAX_REQUEST_SIZE: usize = 8 * 1024 * 1024;
+
+/// Request control which is not known to the protocol library
+#[derive(Debug, Clone)]
+pub struct Control {
+    pub oid: String,
+    pub critical: bool,
+    pub value: Option<Vec<u8>>,
+}
+
+/// Decoded request along with the controls the protocol library does not understand
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub msg: LdapMsg,
+    pub controls: Vec<Control>,
+}
+
+impl Request {
+    pub fn control(&self, oid: &str) -> Option<&Control> {
+        return self.controls.iter().find(|control| control.oid == oid);
+    }
+}
+
+/// Codec which decodes requests carrying controls unknown to the protocol library instead of failing
+#[derive(Default)]
+pub struct Codec {
+    inner: LdapCodec,
+
+    capture: Option<Arc<Capture>>,
+}
+
+impl Codec {
+    pub fn new(capture: Option<Arc<Capture>>) -> Self {
+        return Self {
+            inner: LdapCodec::default(),
+            capture,
+        };
+    }
+
+    pub fn capture(&self) -> Option<&Capture> {
+        return self.capture.as_deref();
+    }
+}
+
+impl Decoder for Codec {
+    type Item = Request;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let (rest, tag) = match parse_tag(buf) {
+            Ok(parsed) => parsed,
+            Err(nom::Err::Incomplete(_)) => return Ok(None),
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid BER encoding: {:?}", err))),
+        };
+
+        let size = buf.len() - rest.len();
+        if size > MAX_REQUEST_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Request too large: {} bytes", size)));
+        }
+
+        let raw = buf.split_to(size);
+
+        let (tag, controls) = extract_controls(tag);
+        let msg = LdapMsg::try_from(tag)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid message: {:?}", err)))?;
+
+        if let Some(capture) = &self.capture {
+            capture.request(&raw, &msg);
+        }
+
+        return Ok(Some(Request { msg, controls }));
+    }
+}
+
+impl Encoder<LdapMsg> for Codec {
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: LdapMsg, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        let capture = self.capture.as_ref().map(|capture| (capture, buf.len(), format!("{:?}", msg)));
+
+        self.inner.encode(msg, buf)?;
+
+        if let Some((capture, start, decoded)) = capture {
+            capture.response(&buf[start..], &format_args!("{}", decoded));
+        }
+
+        return Ok(());
+    }
+}
+
+/// Removes the controls unknown to the protocol library from the encoded message
+fn extract_controls(mut tag: StructureTag) -> (StructureTag, Vec<Control>) {
+    let mut unknown = Vec::new();
+
+    // Controls are the optional third element of the message sequence tagged as [0]
+    if let PL::C(message) = &mut tag.payload {
+        if let Some(PL::C(controls)) = message.get_mut(2)
+            .filter(|tag| tag.class == TagClass::Context && tag.id == 0)
+            .map(|tag| &mut tag.payload) {
+            controls.retain(|control| {
+                let parsed = parse_control(control.clone());
+
+                let own = parsed.as_ref().map_or(false, |parsed| OWN_CONTROLS.contains(&parsed.oid.as_str()));
+                if !own && LdapControl::try_from(control.clone()).is_ok() {
+                    return true;
+                }
+
+                unknown.extend(parsed);
+                return false;
+            });
+        }
+    }
+
+    return (tag, unknown);
+}
+
+fn parse_control(tag: StructureTag) -> Option<Control> {
+    let mut elements = tag
+        .match_class(TagClass::Universal)
+        .and_then(|tag| tag.match_id(Types::Sequence as u64))
+        .and_then(|tag| tag.expect_constructed())?
+        .into_iter();
+
+    let oid = elements.next()
+        .and_then(|tag| tag.match_id(Types::OctetString as u64))
+        .and_then(|tag| tag.expect_primitive())
+        .and_then(|oid| String::from_utf8(oid).ok())?;
+
+    let mut critical = false;
+    let mut value = None;
+    for element in elements {
+        match element.id {
+            id if id == Types::Boolean as u64 => critical = element.expect_primitive()?.first().map_or(false, |b| *b != 0),
+            id if id == Types::OctetString as u64 => value = Some(element.expect_primitive()?),
+            _ => return None,
+        }
+    }
+
+    return Some(Control { oid, critical, value });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_own_control() {
+        // Search request with a critical persistent search control
+        let mut buf = BytesMut::from(&[
+            0x30, 0x5a, 0x02, 0x01, 0x02, 0x63, 0x28, 0x04, 0x11, 0x64, 0x63, 0x3d, 0x65, 0x78, 0x61, 0x6d,
+            0x70, 0x6c, 0x65, 0x2c, 0x64, 0x63, 0x3d, 0x63, 0x6f, 0x6d, 0x0a, 0x01, 0x02, 0x0a, 0x01, 0x00,
+            0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x01, 0x01, 0x00, 0x87, 0x02, 0x63, 0x6e, 0x30, 0x00, 0xa0,
+            0x2b, 0x30, 0x29, 0x04, 0x17, 0x32, 0x2e, 0x31, 0x36, 0x2e, 0x38, 0x34, 0x30, 0x2e, 0x31, 0x2e,
+            0x31, 0x31, 0x33, 0x37, 0x33, 0x30, 0x2e, 0x33, 0x2e, 0x34, 0x2e, 0x33, 0x01, 0x01, 0xff, 0x04,
+            0x0b, 0x30, 0x09, 0x02, 0x01, 0x0f, 0x01, 0x01, 0x00, 0x01, 0x01, 0xff,
+        ][..]);
+
+        let request = Codec::default().decode(&mut buf).unwrap().unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(request.msg.msgid, 2);
+        assert!(request.msg.ctrl.is_empty());
+
+        let control = request.control(psearch::OID).unwrap();
+        assert!(control.critical);
+        assert_eq!(control.value.as_deref(), Some(&[0x30, 0x09, 0x02, 0x01, 0x0f, 0x01, 0x01, 0x00, 0x01, 0x01, 0xff][..]));
+    }
+}
\ No newline at end of file
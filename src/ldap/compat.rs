@@ -0,0 +1,168 @@
+use itertools::Itertools;
+use serde::Deserialize;
+
+use super::container::ATTR_DC;
+use super::dn::{AttributeName, DN, dn};
+use super::entities::{ATTR_MEMBER, ATTR_OBJECT_CLASS, ATTR_UNIQUE_MEMBERS, Entity, object_class_hierarchy};
+
+pub const ATTR_SAM_ACCOUNT_NAME: AttributeName = AttributeName::from("sAMAccountName");
+pub const ATTR_USER_PRINCIPAL_NAME: AttributeName = AttributeName::from("userPrincipalName");
+pub const ATTR_OBJECT_CATEGORY: AttributeName = AttributeName::from("objectCategory");
+
+const USER_ATTRIBUTES: &[AttributeName] = &[
+    ATTR_SAM_ACCOUNT_NAME,
+    ATTR_USER_PRINCIPAL_NAME,
+    ATTR_OBJECT_CATEGORY,
+];
+
+const GROUP_ATTRIBUTES: &[AttributeName] = &[
+    ATTR_SAM_ACCOUNT_NAME,
+    ATTR_OBJECT_CATEGORY,
+    ATTR_MEMBER,
+];
+
+/// Attributes of Active Directory exposed for applications only able to talk to it
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ActiveDirectory {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Domain of the user principal names, derived from the base DN if not set
+    pub upn_suffix: Option<String>,
+}
+
+impl ActiveDirectory {
+    pub fn user<'c, E: Entity>(&'c self, base_dn: &'c DN, entity: E) -> Compat<'c, E> {
+        return Compat { entity, config: self, base_dn, kind: Kind::User };
+    }
+
+    pub fn group<'c, E: Entity>(&'c self, base_dn: &'c DN, entity: E) -> Compat<'c, E> {
+        return Compat { entity, config: self, base_dn, kind: Kind::Group };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    User,
+    Group,
+}
+
+/// Entity extended by the attributes of Active Directory if enabled
+pub struct Compat<'c, E> {
+    entity: E,
+    config: &'c ActiveDirectory,
+    base_dn: &'c DN,
+    kind: Kind,
+}
+
+impl<E: Entity> Compat<'_, E> {
+    /// The name of the entity as held by its RDN
+    fn name(&self) -> Option<String> {
+        return self.entity.dn().iter()
+            .next()
+            .and_then(|rdn| rdn.iter().next())
+            .map(|attribute| attribute.value().to_owned());
+    }
+
+    /// Domain of the user principal names, by default the domain components of the base DN
+    fn upn_suffix(&self) -> Option<String> {
+        if let Some(suffix) = &self.config.upn_suffix {
+            return Some(suffix.clone());
+        }
+
+        let domain = self.base_dn.iter()
+            .flat_map(|rdn| rdn.iter())
+            .filter(|attribute| attribute.name() == &ATTR_DC)
+            .map(|attribute| attribute.value())
+            .join(".");
+
+        return Some(domain).filter(|domain| !domain.is_empty());
+    }
+
+    fn attributes(&self) -> &'static [AttributeName] {
+        if !self.config.enabled {
+            return &[];
+        }
+
+        return match self.kind {
+            Kind::User => USER_ATTRIBUTES,
+            Kind::Group => GROUP_ATTRIBUTES,
+        };
+    }
+}
+
+impl<E: Entity> Entity for Compat<'_, E> {
+    const OBJECT_CLASSES: &'static [&'static str] = E::OBJECT_CLASSES;
+
+    const ATTRIBUTES: &'static [AttributeName] = E::ATTRIBUTES;
+
+    fn dn(&self) -> DN {
+        return self.entity.dn();
+    }
+
+    // All values are looked up by the instance as the attributes depend on the config
+    fn get(_: &AttributeName) -> Option<for<'a> fn(&'a Self) -> Vec<String>> {
+        return None;
+    }
+
+    fn extra_attributes(&self) -> Vec<AttributeName> {
+        return self.entity.extra_attributes().into_iter()
+            .chain(self.attributes().iter().cloned())
+            .unique()
+            .collect();
+    }
+
+    fn values(&self, attribute: &AttributeName) -> Option<Vec<String>> {
+        if !self.attributes().contains(attribute) {
+            let values = self.entity.values(attribute)?;
+
+            if self.config.enabled && attribute == &ATTR_OBJECT_CLASS {
+                let class = match self.kind {
+                    Kind::User => "user",
+                    Kind::Group => "group",
+                };
+                return Some(values.into_iter()
+                    .chain(object_class_hierarchy(&[class]))
+                    .unique()
+                    .collect());
+            }
+
+            return Some(values);
+        }
+
+        if attribute == &ATTR_SAM_ACCOUNT_NAME {
+            return Some(self.name().into_iter().collect());
+        }
+
+        if attribute == &ATTR_USER_PRINCIPAL_NAME {
+            return Some(self.name().zip(self.upn_suffix())
+                .map(|(name, suffix)| format!("{}@{}", name, suffix))
+                .into_iter()
+                .collect());
+        }
+
+        if attribute == &ATTR_OBJECT_CATEGORY {
+            let category = match self.kind {
+                Kind::User => "Person",
+                Kind::Group => "Group",
+            };
+            return Some(vec![dn!["cn" => category, "cn" => "Schema", "cn" => "Configuration"; self.base_dn].to_string()]);
+        }
+
+        // Members are listed as plain DNs like the unique members
+        if attribute == &ATTR_MEMBER {
+            return self.entity.values(&ATTR_UNIQUE_MEMBERS);
+        }
+
+        return None;
+    }
+
+    fn raw_values(&self, attribute: &AttributeName) -> Option<Vec<Vec<u8>>> {
+        if self.attributes().contains(attribute) || attribute == &ATTR_OBJECT_CLASS {
+            return self.values(attribute)
+                .map(|values| values.into_iter().map(String::into_bytes).collect());
+        }
+
+        return self.entity.raw_values(attribute);
+    }
+}
\ No newline at end of file
@@ -1,6 +1,17 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use serde::Deserialize;
-use crate::ldap::dn::DN;
+use std::path::PathBuf;
+use std::str::FromStr;
+use ldap3_proto::{LdapFilter, parse_ldap_filter_str};
+use serde::{Deserialize, Deserializer};
+use crate::database::NameTemplate;
+use crate::ldap::acl::Acl;
+use crate::ldap::dn::{AttributeName, DN, dn, RDN};
+use crate::ldap::compat::ActiveDirectory;
+use crate::ldap::container::ATTR_OU;
+use crate::ldap::entities::{ATTR_CN, ATTR_UID};
+use crate::ldap::mapping::Mapping;
+use crate::ldap::schema::Schema;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
@@ -8,5 +19,445 @@ pub struct Config {
 
     pub listen: SocketAddr,
 
-    // TODO: Support some kind of DN-pattern for users and groups?
+    /// Additional naming contexts served from data directories of their own
+    #[serde(default)]
+    pub tenants: Vec<Tenant>,
+
+    pub tls: Option<TlsConfig>,
+
+    pub ldapi: Option<LdapiConfig>,
+
+    /// LDAP URL of a server to refer to for search bases outside of the base DN
+    pub referral: Option<String>,
+
+    /// LDAP URLs of servers to refer to for search bases within the given subtrees, preferred over `referral`
+    #[serde(default)]
+    pub referrals: HashMap<DN, String>,
+
+    /// Group whose members are permitted to bind, all users may bind if unset
+    pub bind_group: Option<String>,
+
+    /// Requires users having a TOTP secret to append the current one-time password to their password when binding
+    #[serde(default)]
+    pub totp: bool,
+
+    /// Hides users from searches once their account has expired
+    #[serde(default)]
+    pub hide_expired: bool,
+
+    /// Attributes used to resolve bind names which are not the DN of a user, in order of preference
+    #[serde(default)]
+    pub bind_lookup: Vec<BindLookup>,
+
+    /// Access granted to clients which are not bound as a user
+    #[serde(default)]
+    pub anonymous: AnonymousAccess,
+
+    /// Groups whose members are permitted to modify the directory
+    #[serde(default)]
+    pub writers: Vec<String>,
+
+    /// Rules granting access to subtrees, access is only restricted by the other settings if empty
+    #[serde(default)]
+    pub acl: Acl,
+
+    /// Attributes only released to binds of members of the given groups
+    #[serde(default)]
+    pub attribute_release: HashMap<AttributeName, Vec<String>>,
+
+    /// Maximum time in seconds spent on evaluating a single search operation
+    pub search_timeout: Option<u64>,
+
+    /// Maximum number of entries returned by a single search operation
+    pub size_limit: Option<usize>,
+
+    /// Maximum number of concurrent client connections, further connections are closed right after accepting them
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of concurrent sessions bound as the same DN, further binds are refused as busy
+    pub max_sessions_per_dn: Option<usize>,
+
+    /// Rejects DNs supplied by clients unless strictly following RFC 4514
+    #[serde(default)]
+    pub strict_dn: bool,
+
+    /// Records the protocol data units exchanged with clients for debugging, with passwords redacted
+    pub capture: Option<CaptureConfig>,
+
+    /// Naming of users, groups and services within the naming contexts
+    #[serde(default)]
+    pub layout: Layout,
+
+    /// Exposure of groups having a GID number as POSIX groups
+    #[serde(default)]
+    pub posix_groups: PosixGroups,
+
+    /// Site-specific schema elements published in the subschema entry
+    #[serde(default)]
+    pub schema: Schema,
+
+    /// Attributes and object classes of the entries adjusted without changing the built-in ones
+    #[serde(default)]
+    pub mapping: Mapping,
+
+    /// Additionally exposes users and groups with the attributes of Active Directory
+    #[serde(default)]
+    pub active_directory: ActiveDirectory,
+
+    /// Directory whose users and groups are pulled periodically into the primary naming context, which is read-only then
+    pub upstream: Option<Upstream>,
+}
+
+impl Config {
+    /// Selects the server to refer to for a base outside of the base DN by the most specific subtree
+    pub fn referral(&self, base: &DN) -> Option<&str> {
+        return self.referrals.iter()
+            .filter(|(subtree, _)| base.is_descendant_of(subtree))
+            .max_by_key(|(subtree, _)| subtree.iter().count())
+            .map(|(_, server)| server.as_str())
+            .or(self.referral.as_deref());
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnonymousAccess {
+    /// Anonymous clients may search and compare
+    #[default]
+    Read,
+
+    /// Anonymous binds are accepted but searching and comparing requires binding as a user
+    BindOnly,
+
+    /// Anonymous binds are refused and searching and comparing requires binding as a user
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BindLookup {
+    /// The bind name is the plain name of the user
+    Name,
+
+    /// The bind name is the mail address of the user
+    Mail,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Layout {
+    /// Attribute holding the name in the RDN of users
+    #[serde(default)]
+    pub user_rdn: RdnAttribute,
+
+    /// Attribute holding the name in the RDN of groups
+    #[serde(default)]
+    pub group_rdn: RdnAttribute,
+
+    /// Pattern of the DN of users like `uid={name},ou=people,{base}`, replacing the RDN attribute
+    pub users: Option<DnPattern>,
+
+    /// Pattern of the DN of groups like `cn={name},ou=teams,{base}`, replacing the RDN attribute
+    pub groups: Option<DnPattern>,
+
+    /// Pattern of the DN of services like `cn={name},ou=applications,{base}`
+    pub services: Option<DnPattern>,
+
+    /// Template of the display name of users like `{first_name} {last_name}`, the name of the user if unset
+    pub display_name: Option<NameTemplate>,
+
+    /// Template of the common name of users, kept in addition to the name if used as RDN
+    pub common_name: Option<NameTemplate>,
+}
+
+impl Layout {
+    /// Pattern of the DN of entries in the given container
+    pub fn pattern(&self, container: &str) -> DnPattern {
+        let (pattern, rdn) = match container {
+            "users" => (&self.users, self.user_rdn),
+            "groups" => (&self.groups, self.group_rdn),
+            "services" => (&self.services, RdnAttribute::Cn),
+            _ => (&None, RdnAttribute::Cn),
+        };
+
+        return pattern.clone().unwrap_or_else(|| DnPattern {
+            rdn,
+            parent: dn!["ou" => container],
+        });
+    }
+}
+
+/// Template for the DN of an entry given as `ATTRIBUTE={name},PARENT,{base}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnPattern {
+    /// Attribute holding the name in the RDN
+    pub rdn: RdnAttribute,
+
+    /// DN of the entry holding the entries relative to the base DN
+    pub parent: DN,
+}
+
+impl DnPattern {
+    /// The DN of the entry with the given name
+    pub fn dn(&self, base_dn: &DN, name: &str) -> DN {
+        return self.nested_dn(base_dn, &[], name);
+    }
+
+    /// The DN of the entry with the given name placed in nested organizational units, outermost first
+    pub fn nested_dn(&self, base_dn: &DN, units: &[String], name: &str) -> DN {
+        return dn![self.rdn.attribute() => name; self.unit_dn(base_dn, units)];
+    }
+
+    /// The DN of the nested organizational units, outermost first, or of the container itself if there are none
+    pub fn unit_dn(&self, base_dn: &DN, units: &[String]) -> DN {
+        return units.iter()
+            .fold(base_dn.join(self.parent.clone()), |parent, unit| dn!["ou" => unit.as_str(); parent]);
+    }
+
+    /// Resolves the name from the DN of an entry if it follows the pattern
+    pub fn name(&self, base_dn: &DN, dn: &DN) -> Option<String> {
+        return match self.nested_name(base_dn, dn)? {
+            (units, name) if units.is_empty() => Some(name),
+            _ => None,
+        };
+    }
+
+    /// Resolves the organizational units and the name from the DN of an entry placed in nested units
+    pub fn nested_name(&self, base_dn: &DN, dn: &DN) -> Option<(Vec<String>, String)> {
+        let relative = dn.relative_to(&base_dn.join(self.parent.clone()))?;
+
+        let single = |rdn: &RDN, name: &AttributeName| {
+            let mut attributes = rdn.iter();
+            return match (attributes.next(), attributes.next()) {
+                (Some(attribute), None) if attribute.name() == name => Some(attribute.value().to_owned()),
+                _ => None,
+            };
+        };
+
+        let mut rdns = relative.iter();
+        let name = single(rdns.next()?, &self.rdn.attribute())?;
+        let units = rdns
+            .map(|rdn| single(rdn, &ATTR_OU))
+            .collect::<Option<Vec<_>>>()?;
+
+        return Some((units.into_iter().rev().collect(), name));
+    }
+}
+
+impl FromStr for DnPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_suffix("{base}")
+            .ok_or_else(|| format!("DN pattern must end with {{base}}: {}", s))?;
+
+        let (rdn, parent) = s.split_once(',')
+            .ok_or_else(|| format!("DN pattern must start with ATTRIBUTE={{name}}: {}", s))?;
+
+        let rdn = match rdn.strip_suffix("={name}") {
+            Some(attribute) if attribute.eq_ignore_ascii_case("cn") => RdnAttribute::Cn,
+            Some(attribute) if attribute.eq_ignore_ascii_case("uid") => RdnAttribute::Uid,
+            _ => return Err(format!("DN pattern must start with cn={{name}} or uid={{name}}: {}", rdn)),
+        };
+
+        let parent = match parent.strip_suffix(',') {
+            Some(parent) => DN::from_str(parent)
+                .map_err(|err| format!("Invalid DN in pattern: {}", err))?,
+            None if parent.is_empty() => DN::ROOT,
+            None => return Err(format!("DN pattern must separate {{base}} by a comma: {}", parent)),
+        };
+
+        return Ok(Self { rdn, parent });
+    }
+}
+
+impl<'de> Deserialize<'de> for DnPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de> {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        return Self::from_str(&s)
+            .map_err(serde::de::Error::custom);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RdnAttribute {
+    #[default]
+    Cn,
+    Uid,
+}
+
+impl RdnAttribute {
+    pub fn attribute(&self) -> AttributeName {
+        return match self {
+            RdnAttribute::Cn => ATTR_CN,
+            RdnAttribute::Uid => ATTR_UID,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PosixGroups {
+    /// Groups are only exposed as groups of unique names
+    #[default]
+    Disabled,
+
+    /// Groups are additionally exposed as POSIX groups
+    Additional,
+
+    /// Groups are exposed as POSIX groups instead of groups of unique names
+    Exclusive,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaptureConfig {
+    /// Hexdumps are emitted as trace logs
+    Log,
+
+    /// Hexdumps are written to a file per connection in the given directory
+    Directory(PathBuf),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Tenant {
+    pub base_dn: DN,
+
+    /// Data directory holding the users and groups of the tenant
+    pub data: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LdapiConfig {
+    /// Path of the unix socket accepting local clients
+    pub socket: PathBuf,
+
+    /// Users which local processes are bound as on connect by their user ID
+    #[serde(default)]
+    pub peers: HashMap<u32, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsConfig {
+    pub listen: SocketAddr,
+
+    /// PEM encoded certificate chain
+    pub certificate: PathBuf,
+
+    /// PEM encoded private key
+    pub key: PathBuf,
+
+    /// PEM encoded CA certificates used to verify optional client certificates
+    pub client_ca: Option<PathBuf>,
+}
+
+/// Directory mirrored by pulling its users and groups
+#[derive(Debug, Clone, Deserialize)]
+pub struct Upstream {
+    /// LDAP URL of the server like `ldaps://dc.example.com`
+    pub url: String,
+
+    /// PEM encoded CA certificates used to verify the server, required for `ldaps`
+    pub ca: Option<PathBuf>,
+
+    /// DN to bind as, binding anonymously if unset
+    pub bind_dn: Option<String>,
+
+    #[serde(default)]
+    pub password: String,
+
+    /// DN of the subtree holding the users and groups
+    pub base_dn: DN,
+
+    /// Filter selecting the entries pulled as users
+    #[serde(default = "Upstream::default_users", deserialize_with = "deserialize_filter")]
+    pub users: LdapFilter,
+
+    /// Filter selecting the entries pulled as groups
+    #[serde(default = "Upstream::default_groups", deserialize_with = "deserialize_filter")]
+    pub groups: LdapFilter,
+
+    /// Attributes taken from other attributes of the upstream entries, like `uid: 'sAMAccountName'`
+    #[serde(default)]
+    pub attributes: HashMap<AttributeName, AttributeName>,
+
+    /// Time in seconds between two pulls
+    #[serde(default = "Upstream::default_interval")]
+    pub interval: u64,
+
+    /// Time in seconds to wait for each response of the server
+    #[serde(default = "Upstream::default_timeout")]
+    pub timeout: u64,
+
+    /// Number of entries requested per page of the search results
+    #[serde(default = "Upstream::default_page_size")]
+    pub page_size: i64,
+}
+
+impl Upstream {
+    fn default_users() -> LdapFilter {
+        return parse_ldap_filter_str("(|(objectClass=person)(objectClass=posixAccount))")
+            .expect("Valid filter");
+    }
+
+    fn default_groups() -> LdapFilter {
+        return parse_ldap_filter_str("(|(objectClass=groupOfNames)(objectClass=groupOfUniqueNames)(objectClass=posixGroup)(objectClass=group))")
+            .expect("Valid filter");
+    }
+
+    fn default_interval() -> u64 {
+        return 300;
+    }
+
+    fn default_timeout() -> u64 {
+        return 30;
+    }
+
+    fn default_page_size() -> i64 {
+        return 500;
+    }
+}
+
+fn deserialize_filter<'de, D>(deserializer: D) -> Result<LdapFilter, D::Error>
+    where
+        D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    return parse_ldap_filter_str(&s)
+        .map_err(|err| serde::de::Error::custom(format!("Invalid filter {}: {}", s, err)));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dn_pattern() {
+        let base_dn = DN::from_str("dc=example,dc=com").unwrap();
+
+        let pattern = DnPattern::from_str("uid={name},ou=people,ou=org,{base}").unwrap();
+        assert_eq!(pattern.rdn, RdnAttribute::Uid);
+
+        let dn = pattern.dn(&base_dn, "brain");
+        assert_eq!(dn, DN::from_str("uid=brain,ou=people,ou=org,dc=example,dc=com").unwrap());
+        assert_eq!(pattern.name(&base_dn, &dn), Some("brain".to_owned()));
+        assert_eq!(pattern.name(&base_dn, &DN::from_str("cn=brain,ou=people,ou=org,dc=example,dc=com").unwrap()), None);
+        assert_eq!(pattern.name(&base_dn, &DN::from_str("uid=brain,ou=people,dc=example,dc=com").unwrap()), None);
+
+        let units = vec!["lab".to_owned(), "night".to_owned()];
+        let dn = pattern.nested_dn(&base_dn, &units, "brain");
+        assert_eq!(dn, DN::from_str("uid=brain,ou=night,ou=lab,ou=people,ou=org,dc=example,dc=com").unwrap());
+        assert_eq!(pattern.nested_name(&base_dn, &dn), Some((units, "brain".to_owned())));
+        assert_eq!(pattern.name(&base_dn, &dn), None);
+        assert_eq!(pattern.nested_name(&base_dn, &DN::from_str("uid=brain,cn=lab,ou=people,ou=org,dc=example,dc=com").unwrap()), None);
+
+        let pattern = DnPattern::from_str("cn={name},{base}").unwrap();
+        assert_eq!(pattern.dn(&base_dn, "member"), DN::from_str("cn=member,dc=example,dc=com").unwrap());
+
+        assert!(DnPattern::from_str("cn={name},ou=people").is_err());
+        assert!(DnPattern::from_str("mail={name},{base}").is_err());
+        assert!(DnPattern::from_str("cn={name}{base}").is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,116 @@
+use itertools::Itertools;
+
+use crate::Database;
+use crate::database::derive_uuid;
+
+use super::config::Layout;
+use super::dn::{AttributeName, DN};
+use super::entities::{ATTR_ENTRY_DN, ATTR_ENTRY_UUID, ATTR_OBJECT_CLASS, Entity, object_class_hierarchy};
+
+pub const ATTR_DC: AttributeName = AttributeName::from("dc");
+pub const ATTR_O: AttributeName = AttributeName::from("o");
+pub const ATTR_OU: AttributeName = AttributeName::from("ou");
+
+/// Intermediate entry of a naming context, either its base or a container of users, groups or services
+pub struct ContainerEntry {
+    dn: DN,
+}
+
+impl ContainerEntry {
+    /// Collects the base and the containers of users, groups and services including all intermediate entries
+    ///
+    /// The organizational units users are placed in by subdirectories are collected as well.
+    pub fn collect(base_dn: &DN, layout: &Layout, database: &Database) -> Vec<Self> {
+        let containers = ["users", "groups", "services"].into_iter()
+            .map(|container| layout.pattern(container).parent)
+            .flat_map(|parent| (0..parent.iter().count())
+                .map(move |skip| DN::from_iter(parent.iter().skip(skip).cloned())))
+            .map(|parent| base_dn.join(parent));
+
+        let users = layout.pattern("users");
+        let units = database.users()
+            .flat_map(|user| (1..=user.units.len())
+                .map(|depth| users.unit_dn(base_dn, &user.units[..depth]))
+                .collect::<Vec<_>>());
+
+        return std::iter::once(base_dn.clone())
+            .chain(containers)
+            .chain(units)
+            .unique()
+            .map(|dn| Self { dn })
+            .collect();
+    }
+
+    /// Values of the given attribute in the RDN of the entry
+    fn naming(&self, attribute: &AttributeName) -> Vec<String> {
+        return self.dn.iter()
+            .next()
+            .into_iter()
+            .flat_map(|rdn| rdn.iter())
+            .filter(|value| value.name() == attribute)
+            .map(|value| value.value().to_string())
+            .collect();
+    }
+}
+
+impl Entity for ContainerEntry {
+    const OBJECT_CLASSES: &'static [&'static str] = &[
+        "organizationalUnit",
+    ];
+
+    const ATTRIBUTES: &'static [AttributeName] = &[
+        ATTR_OBJECT_CLASS,
+        ATTR_ENTRY_DN,
+        ATTR_ENTRY_UUID,
+        ATTR_DC,
+        ATTR_O,
+        ATTR_OU,
+    ];
+
+    fn dn(&self) -> DN {
+        return self.dn.clone();
+    }
+
+    fn get(attribute: &AttributeName) -> Option<for<'a> fn(&'a Self) -> Vec<String>> {
+        if attribute == &ATTR_OBJECT_CLASS {
+            // The structural class follows the naming attribute as the base DN can be named arbitrarily
+            return Some(|e| {
+                if !e.naming(&ATTR_DC).is_empty() {
+                    return object_class_hierarchy(&["domain", "dcObject"]);
+                }
+
+                if !e.naming(&ATTR_O).is_empty() {
+                    return object_class_hierarchy(&["organization"]);
+                }
+
+                if !e.naming(&ATTR_OU).is_empty() {
+                    return Self::object_classes();
+                }
+
+                return object_class_hierarchy(&["extensibleObject"]);
+            });
+        }
+
+        if attribute == &ATTR_ENTRY_DN {
+            return Some(|e| vec![e.dn().to_string()]);
+        }
+
+        if attribute == &ATTR_ENTRY_UUID {
+            return Some(|e| vec![derive_uuid("container", &e.dn.to_string().to_lowercase()).to_string()]);
+        }
+
+        if attribute == &ATTR_DC {
+            return Some(|e| e.naming(&ATTR_DC));
+        }
+
+        if attribute == &ATTR_O {
+            return Some(|e| e.naming(&ATTR_O));
+        }
+
+        if attribute == &ATTR_OU {
+            return Some(|e| e.naming(&ATTR_OU));
+        }
+
+        return None;
+    }
+}
\ No newline at end of file
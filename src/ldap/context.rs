@@ -0,0 +1,61 @@
+use std::sync::Arc;
+
+
+use crate::database::{Database, Service, SharedDatabase, User};
+
+use super::config::Layout;
+use super::dn::DN;
+use super::entities::{Entity, WithBaseDN};
+
+/// Subtree of the directory served from a database of its own
+pub struct NamingContext {
+    pub base_dn: DN,
+    pub layout: Layout,
+    pub database: Arc<SharedDatabase>,
+}
+
+impl NamingContext {
+    /// Checks whether the subtree below the given base contains entries of this naming context
+    pub fn overlaps(&self, base: &DN) -> bool {
+        return base.is_descendant_of(&self.base_dn) || base.is_ancestor_of(&self.base_dn);
+    }
+
+    /// Looks up the user with the given DN in a snapshot of the database of this naming context
+    pub fn user<'a>(&'a self, database: &'a Database, dn: &DN) -> Option<WithBaseDN<'a, User<'a, 'a>>> {
+        let (_, name) = self.layout.pattern("users").nested_name(&self.base_dn, dn)?;
+        return database.user(&name)
+            .map(|user| user.with_base_dn(&self.base_dn, &self.layout))
+            .filter(|user| &user.dn() == dn);
+    }
+
+    /// Looks up the service with the given DN in a snapshot of the database of this naming context
+    pub fn service<'a>(&'a self, database: &'a Database, dn: &DN) -> Option<WithBaseDN<'a, Service<'a>>> {
+        let name = self.layout.pattern("services").name(&self.base_dn, dn)?;
+        return database.service(&name)
+            .map(|service| service.with_base_dn(&self.base_dn, &self.layout));
+    }
+}
+
+/// Naming contexts served by the same listener with the primary one first
+pub struct NamingContexts(Vec<NamingContext>);
+
+impl NamingContexts {
+    pub fn new(primary: NamingContext, tenants: impl IntoIterator<Item=NamingContext>) -> Self {
+        return Self(std::iter::once(primary).chain(tenants).collect());
+    }
+
+    pub fn primary(&self) -> &NamingContext {
+        return &self.0[0];
+    }
+
+    /// The naming context holding the entry, choosing the longest matching base DN
+    pub fn find(&self, dn: &DN) -> Option<&NamingContext> {
+        return self.0.iter()
+            .filter(|context| dn.is_descendant_of(&context.base_dn))
+            .max_by_key(|context| context.base_dn.iter().count());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&NamingContext> {
+        return self.0.iter();
+    }
+}
\ No newline at end of file
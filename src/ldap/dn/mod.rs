@@ -5,17 +5,60 @@ use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 
 use itertools::{Itertools, Position};
+use nom::combinator::all_consuming;
 use nom::Finish;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 mod parser;
 
+/// Maximum length of a DN supplied by a client
+pub const MAX_LENGTH: usize = 4096;
+
+/// Maximum number of RDNs in a DN supplied by a client
+pub const MAX_COMPONENTS: usize = 32;
+
+/// Maximum number of attributes in a single RDN supplied by a client
+pub const MAX_ATTRIBUTES: usize = 8;
+
+/// Builds a DN from RDNs written in order, like `dn!["cn" => name, "ou" => "users"; base]` below the DN `base`
+macro_rules! dn {
+    [$($name:expr => $value:expr),+ $(,)?] => {
+        $crate::ldap::dn::DN::from_iter([$($crate::ldap::dn::RDN::from(($name, $value))),+])
+    };
+    [$($name:expr => $value:expr),+ ; $base:expr] => {
+        ($base).join($crate::ldap::dn::dn![$($name => $value),+])
+    };
+}
+
+pub(crate) use dn;
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    TooLong(usize),
+    TooManyComponents(usize),
+    TooManyAttributes(usize),
+    Syntax(nom::error::Error<String>),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            ParseError::TooLong(length) => write!(f, "DN too long: {} > {}", length, MAX_LENGTH),
+            ParseError::TooManyComponents(count) => write!(f, "Too many RDNs: {} > {}", count, MAX_COMPONENTS),
+            ParseError::TooManyAttributes(count) => write!(f, "Too many attributes in RDN: {} > {}", count, MAX_ATTRIBUTES),
+            ParseError::Syntax(err) => write!(f, "Invalid DN syntax: {}", err),
+        };
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct DN {
     components: Vec<RDN>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone)]
 pub struct RDN {
     attributes: Vec<Attribute>,
 }
@@ -29,7 +72,7 @@ pub struct Attribute {
 #[derive(Debug, Clone, Eq)]
 pub enum AttributeName {
     String(Cow<'static, str>),
-    OID(Vec<u16>),
+    OID(Vec<u128>),
 }
 
 impl PartialEq for AttributeName {
@@ -42,6 +85,21 @@ impl PartialEq for AttributeName {
     }
 }
 
+// The attributes of multi-valued RDNs are unordered
+impl PartialEq for RDN {
+    fn eq(&self, other: &Self) -> bool {
+        return self.key() == other.key();
+    }
+}
+
+impl Eq for RDN {}
+
+impl Hash for RDN {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
 impl Hash for AttributeName {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
@@ -77,6 +135,46 @@ fn iter_after<'a, T, I, J>(mut iter: I, mut prefix: J) -> Option<I>
 impl DN {
     pub const ROOT: Self = Self { components: Vec::new() };
 
+    /// Parses a DN supplied by a client while enforcing limits on its size, optionally following RFC 4514 strictly
+    pub fn parse_limited(s: &str, strict: bool) -> Result<Self, ParseError> {
+        if s.len() > MAX_LENGTH {
+            return Err(ParseError::TooLong(s.len()));
+        }
+
+        let dn = match strict {
+            true => Self::parse_strict(s),
+            false => Self::from_str(s),
+        }.map_err(ParseError::Syntax)?;
+
+        if dn.components.len() > MAX_COMPONENTS {
+            return Err(ParseError::TooManyComponents(dn.components.len()));
+        }
+
+        if let Some(rdn) = dn.components.iter().find(|rdn| rdn.attributes.len() > MAX_ATTRIBUTES) {
+            return Err(ParseError::TooManyAttributes(rdn.attributes.len()));
+        }
+
+        return Ok(dn);
+    }
+
+    /// Parses a DN following RFC 4514 without accepting quoted values, semicolons or surrounding whitespace
+    pub fn parse_strict(s: &str) -> Result<Self, nom::error::Error<String>> {
+        return match all_consuming(parser::strict_dn)(s).finish() {
+            Ok((_, dn)) => Ok(dn),
+            Err(nom::error::Error { input, code }) => Err(nom::error::Error {
+                input: input.to_owned(),
+                code,
+            }),
+        };
+    }
+
+    /// Canonical string form with lowercased attribute types, minimal escaping and sorted multi-valued RDNs
+    pub fn normalize(&self) -> String {
+        return self.components.iter()
+            .map(RDN::normalize)
+            .join(",");
+    }
+
     pub fn iter(&self) -> impl Iterator<Item=&RDN> {
         return self.components.iter();
     }
@@ -105,12 +203,40 @@ impl DN {
             components: self.components.iter().skip(1).cloned().collect(),
         };
     }
+
+    /// Compares two DNs structurally while ignoring the case of attribute values and the order within RDNs
+    pub fn matches(&self, other: &DN) -> bool {
+        return self.components.len() == other.components.len()
+            && self.components.iter().zip(other.components.iter())
+            .all(|(rdn, other)| rdn.matches(other));
+    }
 }
 
 impl RDN {
     pub fn iter(&self) -> impl Iterator<Item=&Attribute> {
         return self.attributes.iter();
     }
+
+    /// The attributes in an order independent of the order given
+    fn key(&self) -> Vec<(String, &str)> {
+        return self.attributes.iter()
+            .map(|attribute| (attribute.name.to_string().to_ascii_lowercase(), attribute.value.as_str()))
+            .sorted()
+            .collect();
+    }
+
+    fn normalize(&self) -> String {
+        return self.attributes.iter()
+            .map(Attribute::normalize)
+            .sorted()
+            .join("+");
+    }
+
+    fn matches(&self, other: &RDN) -> bool {
+        return self.attributes.len() == other.attributes.len()
+            && self.attributes.iter()
+            .all(|attribute| other.attributes.iter().any(|other| attribute.matches(other)));
+    }
 }
 
 impl Attribute {
@@ -121,6 +247,33 @@ impl Attribute {
     pub fn value(&self) -> &str {
         return &self.value;
     }
+
+    /// Escapes only the characters required by RFC 4514
+    fn normalize(&self) -> String {
+        let mut normalized = format!("{}=", self.name.to_string().to_ascii_lowercase());
+
+        for (i, c) in self.value.char_indices() {
+            let first = i == 0;
+            let last = i + c.len_utf8() == self.value.len();
+
+            match c {
+                '"' | '+' | ',' | ';' | '<' | '>' | '\\' => {
+                    normalized.push('\\');
+                    normalized.push(c);
+                }
+                '#' if first => normalized.push_str("\\#"),
+                ' ' if first || last => normalized.push_str("\\ "),
+                '\0' => normalized.push_str("\\00"),
+                c => normalized.push(c),
+            }
+        }
+
+        return normalized;
+    }
+
+    fn matches(&self, other: &Attribute) -> bool {
+        return self.name == other.name && self.value.to_lowercase() == other.value.to_lowercase();
+    }
 }
 
 impl From<RDN> for DN {
@@ -196,8 +349,8 @@ impl From<String> for AttributeName {
     }
 }
 
-impl<const N: usize> From<[u16; N]> for AttributeName {
-    fn from(value: [u16; N]) -> Self {
+impl<const N: usize> From<[u128; N]> for AttributeName {
+    fn from(value: [u128; N]) -> Self {
         return Self::OID(value.to_vec());
     }
 }
@@ -206,7 +359,7 @@ impl FromStr for DN {
     type Err = nom::error::Error<String>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        return match parser::dn(s).finish() {
+        return match all_consuming(parser::dn)(s).finish() {
             Ok((_, dn)) => Ok(dn),
             Err(nom::error::Error { input, code }) => Err(nom::error::Error {
                 input: input.to_owned(),
@@ -307,8 +460,11 @@ impl fmt::Display for Attribute {
                 Position::Last(c) |
                 Position::Middle(c) |
                 Position::Only(c) => {
-                    if c < ' ' {
-                        write!(f, "\\{:02x}", c as u8)?;
+                    // Non-printable characters are written as hex pairs of their UTF-8 encoding
+                    if c.is_control() {
+                        for b in c.encode_utf8(&mut [0; 4]).bytes() {
+                            write!(f, "\\{:02x}", b)?;
+                        }
                     } else if ESCAPED.contains(&c) {
                         write!(f, "\\{}", c)?;
                     } else {
@@ -330,7 +486,7 @@ impl fmt::Display for AttributeName {
             }
             AttributeName::OID(oid) => {
                 write!(f, "{}", oid.iter()
-                    .format_with(",", |elt, f| (f(elt))))?;
+                    .format_with(".", |elt, f| (f(elt))))?;
             }
         });
     }
@@ -350,23 +506,127 @@ impl<'de> Deserialize<'de> for DN {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
         where
             D: Deserializer<'de> {
-        let s = Deserialize::deserialize(deserializer)?;
-        return Self::from_str(s)
+        let s: String = Deserialize::deserialize(deserializer)?;
+        return Self::from_str(&s)
+            .map_err(serde::de::Error::custom);
+    }
+}
+
+impl<'de> Deserialize<'de> for RDN {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de> {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        return Self::from_str(&s)
             .map_err(serde::de::Error::custom);
     }
 }
 
+impl<'de> Deserialize<'de> for Attribute {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de> {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        return Self::from_str(&s)
+            .map_err(serde::de::Error::custom);
+    }
+}
+
+impl<'de> Deserialize<'de> for AttributeName {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de> {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        return Self::from_str(&s)
+            .map_err(serde::de::Error::custom);
+    }
+}
+
+impl Serialize for DN {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer {
+        return serializer.collect_str(self);
+    }
+}
+
+impl Serialize for RDN {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer {
+        return serializer.collect_str(self);
+    }
+}
+
+impl Serialize for Attribute {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer {
+        return serializer.collect_str(self);
+    }
+}
+
+impl Serialize for AttributeName {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer {
+        return serializer.collect_str(self);
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use proptest::prelude::*;
+
     use super::*;
     use super::parser::*;
 
+    fn attribute_name() -> impl Strategy<Value=AttributeName> {
+        return prop_oneof![
+            "[a-zA-Z][a-zA-Z0-9-]{0,8}".prop_map(AttributeName::from),
+            prop::collection::vec(any::<u128>(), 2..6).prop_map(AttributeName::OID),
+        ];
+    }
+
+    fn dn() -> impl Strategy<Value=DN> {
+        let value = prop::collection::vec(any::<char>(), 0..16).prop_map(String::from_iter);
+        let attribute = (attribute_name(), value).prop_map(Attribute::from);
+        let rdn = prop::collection::vec(attribute, 1..4).prop_map(RDN::from_iter);
+        return prop::collection::vec(rdn, 0..6).prop_map(DN::from_iter);
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(dn in dn()) {
+            prop_assert_eq!(DN::from_str(&dn.to_string()), Ok(dn.clone()));
+            prop_assert_eq!(DN::parse_strict(&dn.normalize()).map(|dn| dn.normalize()), Ok(dn.normalize()));
+
+            let yaml = serde_yaml::to_string(&dn).unwrap();
+            prop_assert_eq!(serde_yaml::from_str::<DN>(&yaml).unwrap(), dn);
+        }
+    }
+
     #[test]
     fn test_join() {
         assert_eq!(DN::from_iter([("dc", "example"), ("dc", "com")]).join(("ou", "test")),
                    DN::from_iter([("ou", "test"), ("dc", "example"), ("dc", "com")]));
     }
 
+    #[test]
+    fn test_display() {
+        assert_eq!(DN::from_iter([("cn", "Müller 🦀"), ("ou", "#a\u{85}\n")]).to_string(), "cn=Müller 🦀,ou=\\#a\\c2\\85\\0a");
+        assert_eq!(DN::from_str("cn=Müller 🦀,ou=\\#a\\c2\\85\\0a"), Ok(DN::from_iter([("cn", "Müller 🦀"), ("ou", "#a\u{85}\n")])));
+    }
+
+    #[test]
+    fn test_dn_macro() {
+        let base = DN::from_str("dc=example,dc=com").unwrap();
+        let name = String::from("brain");
+
+        assert_eq!(dn!["cn" => name.clone(), "ou" => "users"; base], DN::from_str("cn=brain,ou=users,dc=example,dc=com").unwrap());
+        assert_eq!(dn![AttributeName::from("uid") => &name, "ou" => "users"], DN::from_str("uid=brain,ou=users").unwrap());
+    }
+
     #[test]
     fn test_descendant() {
         assert!(DN::from_iter([("ou", "test"), ("dc", "example"), ("dc", "com")]).is_descendant_of(&DN::from_iter([("ou", "test"), ("dc", "example"), ("dc", "com")])));
@@ -379,6 +639,33 @@ mod test {
         assert!(!DN::from_iter([("ou", "test"), ("dc", "example"), ("dc", "com")]).is_descendant_of(&DN::from_iter([("dc", "com"), ("ex", "suffix")])));
     }
 
+    #[test]
+    fn test_matches() {
+        let dn = DN::from_iter([("cn", "admins"), ("ou", "groups"), ("dc", "example"), ("dc", "com")]);
+
+        assert!(dn.matches(&DN::from_str("CN=Admins, OU=Groups, DC=example, DC=com").unwrap()));
+        assert!(!dn.matches(&DN::from_str("cn=users,ou=groups,dc=example,dc=com").unwrap()));
+        assert!(!dn.matches(&DN::from_str("ou=groups,dc=example,dc=com").unwrap()));
+    }
+
+    #[test]
+    fn test_rdn_order() {
+        let dn = DN::from_str("cn=a+sn=b,ou=users,dc=example,dc=com").unwrap();
+        let reordered = DN::from_str("SN=b+cn=a,ou=users,dc=example,dc=com").unwrap();
+
+        assert_eq!(dn, reordered);
+        assert!(DN::from_str("uid=x,sn=b+cn=a,ou=users,dc=example,dc=com").unwrap().is_descendant_of(&dn));
+        assert_ne!(dn, DN::from_str("cn=a+sn=b+sn=c,ou=users,dc=example,dc=com").unwrap());
+        assert_ne!(dn, DN::from_str("cn=b+sn=a,ou=users,dc=example,dc=com").unwrap());
+
+        let hash = |dn: &DN| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            dn.hash(&mut hasher);
+            return hasher.finish();
+        };
+        assert_eq!(hash(&dn), hash(&reordered));
+    }
+
     #[test]
     fn test_ancestor() {
         assert!(DN::from_iter([("ou", "test"), ("dc", "example"), ("dc", "com")]).is_ancestor_of(&DN::from_iter([("ou", "test"), ("dc", "example"), ("dc", "com")])));
@@ -400,6 +687,58 @@ mod test {
                    None);
     }
 
+    #[test]
+    fn test_oid() {
+        assert_eq!(AttributeName::from_str("1.3.6.1.4.1.99999.1"),
+                   Ok(AttributeName::from([1, 3, 6, 1, 4, 1, 99999, 1])));
+        assert_eq!(AttributeName::from([1, 3, 6, 1, 4, 1, 99999, 1]).to_string(), "1.3.6.1.4.1.99999.1");
+
+        // Arcs below 2.25 are UUIDs taken as 128 bit integers
+        let oid = "2.25.329800735698586629295641978511506172918";
+        assert_eq!(AttributeName::from_str(oid), Ok(AttributeName::from([2, 25, 329800735698586629295641978511506172918])));
+        assert_eq!(AttributeName::from_str(oid).unwrap().to_string(), oid);
+    }
+
+    #[test]
+    fn test_parse_limited() {
+        assert_eq!(DN::parse_limited("cn=foo,dc=example,dc=com", false),
+                   Ok(DN::from_iter([("cn", "foo"), ("dc", "example"), ("dc", "com")])));
+
+        assert!(matches!(DN::parse_limited(&"x".repeat(MAX_LENGTH + 1), false), Err(ParseError::TooLong(_))));
+        assert!(matches!(DN::parse_limited(&vec!["dc=x"; MAX_COMPONENTS + 1].join(","), false), Err(ParseError::TooManyComponents(_))));
+        assert!(matches!(DN::parse_limited(&vec!["dc=x"; MAX_ATTRIBUTES + 1].join("+"), false), Err(ParseError::TooManyAttributes(_))));
+        assert!(matches!(DN::parse_limited("garbage", false), Err(ParseError::Syntax(_))));
+        assert!(matches!(DN::parse_limited("cn=foo; dc=com", true), Err(ParseError::Syntax(_))));
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        assert_eq!(DN::parse_strict("cn=foo\\,bar+uid=x,dc=example,dc=com"),
+                   Ok(DN::from_iter([RDN::from_iter([("cn", "foo,bar"), ("uid", "x")]), RDN::from(("dc", "example")), RDN::from(("dc", "com"))])));
+        assert_eq!(DN::parse_strict("cn=\\20spaced\\ ,cn=caf\\C3\\A9,cn=a=b"),
+                   Ok(DN::from_iter([("cn", " spaced "), ("cn", "café"), ("cn", "a=b")])));
+        assert_eq!(DN::parse_strict(""), Ok(DN::ROOT));
+
+        assert!(DN::parse_strict("cn=foo;dc=com").is_err());
+        assert!(DN::parse_strict("cn=foo, dc=com").is_err());
+        assert!(DN::parse_strict("cn=\"foo\"").is_err());
+        assert!(DN::parse_strict("cn= foo").is_err());
+        assert!(DN::parse_strict("cn=foo ").is_err());
+        assert!(DN::parse_strict("cn=#foo").is_err());
+        assert!(DN::parse_strict("cn=foo,").is_err());
+        assert!(DN::parse_strict("01.2=foo").is_err());
+    }
+
+    #[test]
+    fn test_normalize() {
+        let dn = DN::from_str("UID=x+CN=Foo\\,Bar , OU=#41;dc=com").unwrap();
+        assert_eq!(dn.normalize(), "cn=Foo\\,Bar+uid=x,ou=A,dc=com");
+        assert_eq!(DN::from_iter([("cn", " a=b#"), ("cn", "#x ")]).normalize(), "cn=\\ a=b#,cn=\\#x\\ ");
+
+        let dn = DN::from_iter([("cn", " a=b;c<d> "), ("dc", "com")]);
+        assert_eq!(DN::parse_strict(&dn.normalize()), Ok(dn));
+    }
+
     #[test]
     fn test_parent() {
         assert_eq!(DN::from_iter([("cn", "myself"), ("ou", "test"), ("dc", "example"), ("dc", "com")]).parent(),
@@ -1,10 +1,10 @@
 use nom::{IResult, Parser};
 use nom::branch::alt;
 use nom::bytes::complete::tag;
-use nom::character::complete::{alpha1, alphanumeric1, char, digit1, multispace0, none_of, one_of};
-use nom::combinator::{map, map_res, recognize};
+use nom::character::complete::{alpha1, alphanumeric1, char, digit0, digit1, multispace0, none_of, one_of};
+use nom::combinator::{consumed, map, map_res, recognize, verify};
 use nom::error::{Error, ParseError};
-use nom::multi::{count, fold_many0, many0, separated_list0, separated_list1};
+use nom::multi::{count, fold_many0, many0, many1, separated_list0, separated_list1};
 use nom::sequence::{delimited, pair, preceded, separated_pair};
 
 use crate::ldap::dn::AttributeName;
@@ -38,30 +38,40 @@ pub fn attribute_name(i: &str) -> IResult<&str, AttributeName, Error<&str>> {
     ))(i);
 }
 
+fn hexpair(i: &str) -> IResult<&str, u8, Error<&str>> {
+    return map_res(recognize(count(one_of("0123456789abcdefABCDEF"), 2)), |s| u8::from_str_radix(s, 16))(i);
+}
+
 pub fn attribute_value(i: &str) -> IResult<&str, String, Error<&str>> {
-    fn escaped(i: &str) -> IResult<&str, core::primitive::char, Error<&str>> {
-        return preceded(char('\\'), one_of("\\\",=\r+<>#; "))(i);
+    fn escaped(i: &str) -> IResult<&str, Vec<u8>, Error<&str>> {
+        return preceded(char('\\'), alt((
+            map(hexpair, |b| vec![b]),
+            map(one_of("\\\",=\r+<>#; "), |c| c.to_string().into_bytes()),
+        )))(i);
     }
 
-    let simple = fold_many0(alt((
-        none_of(",=+<>#;\\\""),
-        escaped
-    )), String::new, |mut acc, c| {
+    // Surrounding whitespace is dropped unless escaped while a leading sharp starts a hex string instead
+    let simple = map_res(fold_many0(alt((
+        map(none_of(",=+<>;\\\""), |c| (c.to_string().into_bytes(), !" \t\r\n".contains(c))),
+        map(escaped, |bytes| (bytes, true)),
+    )), Vec::new, |mut acc, c| {
         acc.push(c);
         return acc;
-    }).map(|s| s.trim().to_owned());
+    }), |chars| {
+        let start = chars.iter().position(|(_, significant)| *significant).unwrap_or(chars.len());
+        let end = chars.iter().rposition(|(_, significant)| *significant).map_or(start, |end| end + 1);
+        return String::from_utf8(chars[start..end].iter().flat_map(|(bytes, _)| bytes.iter().copied()).collect());
+    });
 
-    let quoted = delimited(char('"'), fold_many0(alt((
-        none_of("\\\""),
+    let quoted = map_res(delimited(char('"'), fold_many0(alt((
+        map(none_of("\\\""), |c| c.to_string().into_bytes()),
         escaped
-    )), String::new, |mut acc, c| {
-        acc.push(c);
+    )), Vec::new, |mut acc, bytes| {
+        acc.extend(bytes);
         return acc;
-    }), char('"'));
+    }), char('"')), String::from_utf8);
 
-    let hexstr = map_res(preceded(char('#'), many0(
-        map_res(recognize(count(one_of("0123456789abcdefABCDEF"), 2)), |s| u8::from_str_radix(s, 16)),
-    )), String::from_utf8);
+    let hexstr = map_res(preceded(char('#'), many0(hexpair)), String::from_utf8);
 
     return alt((hexstr, quoted, simple))(i);
 }
@@ -75,7 +85,7 @@ pub fn attribute(i: &str) -> IResult<&str, Attribute, Error<&str>> {
 }
 
 pub fn rdn(i: &str) -> IResult<&str, RDN, Error<&str>> {
-    return map(separated_list0(char('+'), attribute),
+    return map(separated_list1(char('+'), attribute),
                |attributes| RDN { attributes })(i);
 }
 
@@ -84,6 +94,78 @@ pub fn dn(i: &str) -> IResult<&str, DN, Error<&str>> {
                |components| DN { components })(i);
 }
 
+pub fn strict_attribute_name(i: &str) -> IResult<&str, AttributeName, Error<&str>> {
+    let descr = map(recognize::<&str, _, _, _>(
+        pair(
+            alpha1,
+            many0(alt((alphanumeric1, tag("-")))),
+        )), |s| AttributeName::from(s.to_lowercase()),
+    );
+
+    // Numbers must not have leading zeros and OIDs must have at least two of them
+    let number = map_res(alt((tag("0"), recognize(pair(one_of("123456789"), digit0)))), str::parse::<u128>);
+    let oid = map(verify(separated_list1(char('.'), number),
+                         |parts: &Vec<u128>| parts.len() > 1),
+                  AttributeName::OID);
+
+    return alt((
+        descr,
+        oid
+    ))(i);
+}
+
+pub fn strict_attribute_value(i: &str) -> IResult<&str, String, Error<&str>> {
+    fn escaped(i: &str) -> IResult<&str, Vec<u8>, Error<&str>> {
+        return preceded(char('\\'), alt((
+            map(hexpair, |b| vec![b]),
+            map(one_of("\\\"+,;<> #="), |c| vec![c as u8]),
+        )))(i);
+    }
+
+    // Leading spaces and sharps as well as trailing spaces must be escaped
+    fn escaped_bounds(raw: &str) -> bool {
+        if raw.starts_with(' ') || raw.starts_with('#') {
+            return false;
+        }
+
+        return match raw.strip_suffix(' ') {
+            Some(rest) => rest.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1,
+            None => true,
+        };
+    }
+
+    let string = map_res(verify(consumed(fold_many0(alt((
+        map(none_of("\0\"+,;<>\\"), |c| c.to_string().into_bytes()),
+        escaped,
+    )), Vec::new, |mut acc, bytes| {
+        acc.extend(bytes);
+        return acc;
+    })), |(raw, _): &(&str, Vec<u8>)| escaped_bounds(raw)), |(_, bytes)| String::from_utf8(bytes));
+
+    // The encoded value is taken as UTF-8 like by the lenient parser
+    let hexstr = map_res(preceded(char('#'), many1(hexpair)), String::from_utf8);
+
+    return alt((hexstr, string))(i);
+}
+
+pub fn strict_attribute(i: &str) -> IResult<&str, Attribute, Error<&str>> {
+    return map(separated_pair(strict_attribute_name, char('='), strict_attribute_value),
+               |(name, value)| Attribute {
+                   name,
+                   value,
+               })(i);
+}
+
+pub fn strict_rdn(i: &str) -> IResult<&str, RDN, Error<&str>> {
+    return map(separated_list1(char('+'), strict_attribute),
+               |attributes| RDN { attributes })(i);
+}
+
+pub fn strict_dn(i: &str) -> IResult<&str, DN, Error<&str>> {
+    return map(separated_list0(char(','), strict_rdn),
+               |components| DN { components })(i);
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -102,6 +184,9 @@ mod test {
         assert_eq!(attribute_value("42"), Ok(("", String::from("42"))));
         assert_eq!(attribute_value("some spaces  in  between"), Ok(("", String::from("some spaces  in  between"))));
         assert_eq!(attribute_value("test\\+name@example.com"), Ok(("", String::from("test+name@example.com"))));
+        assert_eq!(attribute_value("caf\\C3\\A9 \\F0\\9F\\A6\\80"), Ok(("", String::from("café 🦀"))));
+        assert_eq!(attribute_value("no#1"), Ok(("", String::from("no#1"))));
+        assert_eq!(attribute_value("\\ spaced\\ "), Ok(("", String::from(" spaced "))));
         assert_eq!(attribute_value(""), Ok(("", String::from(""))));
 
         assert_eq!(attribute_value("\"bar\""), Ok(("", String::from("bar"))));
@@ -1,11 +1,51 @@
 use std::ops::Deref;
+use std::time::SystemTime;
 
-use crate::database::{Group, User};
+use chrono::{DateTime, NaiveDate, Utc};
+use itertools::Itertools;
 
+use crate::database::{Group, NameTemplate, Service, User};
+
+use super::config::{Layout, PosixGroups, RdnAttribute};
 use super::dn::{AttributeName, DN};
 
+/// Superclass of each object class used by the entities
+const SUPERCLASSES: &[(&str, &str)] = &[
+    ("inetOrgPerson", "organizationalPerson"),
+    ("organizationalPerson", "person"),
+    ("person", "top"),
+    ("posixAccount", "top"),
+    ("shadowAccount", "top"),
+    ("ldapPublicKey", "top"),
+    ("groupOfUniqueNames", "top"),
+    ("groupOfNames", "top"),
+    ("user", "organizationalPerson"),
+    ("group", "top"),
+    ("posixGroup", "top"),
+    ("applicationProcess", "top"),
+    ("alias", "top"),
+    ("domain", "top"),
+    ("dcObject", "top"),
+    ("organization", "top"),
+    ("organizationalUnit", "top"),
+    ("extensibleObject", "top"),
+    ("monitorServer", "top"),
+    ("monitorCounterObject", "top"),
+];
+
+/// Expands object classes to themselves followed by all of their superclasses
+pub fn object_class_hierarchy(classes: &[&str]) -> Vec<String> {
+    return classes.iter()
+        .flat_map(|class| std::iter::successors(Some(*class), |class| SUPERCLASSES.iter()
+            .find(|(subclass, _)| subclass.eq_ignore_ascii_case(class))
+            .map(|(_, superclass)| *superclass)))
+        .unique()
+        .map(ToString::to_string)
+        .collect();
+}
+
 pub trait Entity {
-    /// The object classes of this entity
+    /// The most derived object classes of this entity, superclasses are added according to the hierarchy
     const OBJECT_CLASSES: &'static [&'static str];
 
     /// Return all exposed attribute names
@@ -17,24 +57,83 @@ pub trait Entity {
     /// Get the values of the given attribute
     fn get(attribute: &AttributeName) -> Option<for<'a> fn(&'a Self) -> Vec<String>>;
 
-    /// Checks whether the attribute is present or not
-    fn has(attribute: &AttributeName) -> bool {
-        return Self::get(attribute).is_some();
+    /// The object classes of this entity including all superclasses
+    fn object_classes() -> Vec<String> {
+        return object_class_hierarchy(Self::OBJECT_CLASSES);
+    }
+
+    /// Attributes of this entity in addition to the exposed ones
+    fn extra_attributes(&self) -> Vec<AttributeName> {
+        return vec![];
     }
+
+    /// Get the values of the given exposed or additional attribute
+    fn values(&self, attribute: &AttributeName) -> Option<Vec<String>> {
+        return Self::get(attribute).map(|getter| getter(self));
+    }
+
+    /// Get the values of the given attribute as sent to clients, including binary ones
+    fn raw_values(&self, attribute: &AttributeName) -> Option<Vec<Vec<u8>>> {
+        return self.values(attribute)
+            .map(|values| values.into_iter().map(String::into_bytes).collect());
+    }
+
+    /// Checks whether the entity has any value for the attribute
+    fn has(&self, attribute: &AttributeName) -> bool {
+        return self.raw_values(attribute).map_or(false, |values| !values.is_empty());
+    }
+}
+
+pub const ATTR_OBJECT_CLASS: AttributeName = AttributeName::from("objectClass");
+pub const ATTR_ENTRY_DN: AttributeName = AttributeName::from("entryDN");
+pub const ATTR_ENTRY_UUID: AttributeName = AttributeName::from("entryUUID");
+pub const ATTR_CREATE_TIMESTAMP: AttributeName = AttributeName::from("createTimestamp");
+pub const ATTR_MODIFY_TIMESTAMP: AttributeName = AttributeName::from("modifyTimestamp");
+pub const ATTR_CN: AttributeName = AttributeName::from("cn");
+pub const ATTR_DISPLAY_NAME: AttributeName = AttributeName::from("displayName");
+pub const ATTR_GIVEN_NAME: AttributeName = AttributeName::from("givenName");
+pub const ATTR_SN: AttributeName = AttributeName::from("sn");
+pub const ATTR_MAIL: AttributeName = AttributeName::from("mail");
+pub const ATTR_MAIL_ALIAS: AttributeName = AttributeName::from("mailAlias");
+pub const ATTR_UID: AttributeName = AttributeName::from("uid");
+pub const ATTR_UID_NUMBER: AttributeName = AttributeName::from("uidNumber");
+pub const ATTR_GID_NUMBER: AttributeName = AttributeName::from("gidNumber");
+pub const ATTR_HOME_DIRECTORY: AttributeName = AttributeName::from("homeDirectory");
+pub const ATTR_LOGIN_SHELL: AttributeName = AttributeName::from("loginShell");
+pub const ATTR_MEMBER_UID: AttributeName = AttributeName::from("memberUid");
+pub const ATTR_SHADOW_LAST_CHANGE: AttributeName = AttributeName::from("shadowLastChange");
+pub const ATTR_SHADOW_MAX: AttributeName = AttributeName::from("shadowMax");
+pub const ATTR_SHADOW_EXPIRE: AttributeName = AttributeName::from("shadowExpire");
+pub const ATTR_DESCRIPTION: AttributeName = AttributeName::from("description");
+pub const ATTR_SSH_PUBLIC_KEY: AttributeName = AttributeName::from("sshPublicKey");
+
+/// Formats a point in time as generalized time in UTC
+pub fn generalized_time(time: SystemTime) -> String {
+    return DateTime::<Utc>::from(time).format("%Y%m%d%H%M%SZ").to_string();
+}
+
+/// Number of days since the epoch as used by shadow attributes
+fn epoch_days(date: NaiveDate) -> i64 {
+    return (date - NaiveDate::from_ymd_opt(1970, 1, 1).expect("Valid epoch")).num_days();
 }
+pub const ATTR_MEMBER_OF: AttributeName = AttributeName::from("memberOf");
+pub const ATTR_UNIQUE_MEMBERS: AttributeName = AttributeName::from("uniqueMembers");
 
-const ATTR_OBJECT_CLASS: AttributeName = AttributeName::from("objectClass");
-const ATTR_ENTRY_DN: AttributeName = AttributeName::from("entryDN");
-const ATTR_CN: AttributeName = AttributeName::from("cn");
-const ATTR_DISPLAY_NAME: AttributeName = AttributeName::from("displayName");
-const ATTR_GIVEN_NAME: AttributeName = AttributeName::from("givenName");
-const ATTR_SN: AttributeName = AttributeName::from("sn");
-const ATTR_MAIL: AttributeName = AttributeName::from("mail");
-const ATTR_MEMBER_OF: AttributeName = AttributeName::from("memberOf");
-const ATTR_UNIQUE_MEMBERS: AttributeName = AttributeName::from("uniqueMembers");
+/// Member attribute of `groupOfNames`, exposed by renaming the unique members or for Active Directory
+pub const ATTR_MEMBER: AttributeName = AttributeName::from("member");
+pub const ATTR_OWNER: AttributeName = AttributeName::from("owner");
+
+/// Attributes maintained by the server which are only returned if requested explicitly or by `+`
+pub const OPERATIONAL_ATTRIBUTES: &[AttributeName] = &[
+    ATTR_ENTRY_DN,
+    ATTR_ENTRY_UUID,
+    ATTR_CREATE_TIMESTAMP,
+    ATTR_MODIFY_TIMESTAMP,
+];
 
 pub struct WithBaseDN<'dn, T> {
     base_dn: &'dn DN,
+    layout: &'dn Layout,
     entity: T,
 }
 
@@ -42,6 +141,10 @@ impl<'dn, T> WithBaseDN<'dn, T> {
     pub fn base_dn(&self) -> &'dn DN {
         return self.base_dn;
     }
+
+    pub fn layout(&self) -> &'dn Layout {
+        return self.layout;
+    }
 }
 
 impl<'dn, T> Deref for WithBaseDN<'dn, T> {
@@ -53,54 +156,101 @@ impl<'dn, T> Deref for WithBaseDN<'dn, T> {
 }
 
 impl User<'_, '_> {
-    pub fn with_base_dn(self, base_dn: &DN) -> WithBaseDN<Self> {
+    pub fn with_base_dn<'dn>(self, base_dn: &'dn DN, layout: &'dn Layout) -> WithBaseDN<'dn, Self> {
         return WithBaseDN {
             base_dn,
+            layout,
             entity: self,
         };
     }
 }
 
+impl WithBaseDN<'_, User<'_, '_>> {
+    fn render_name(&self, template: &NameTemplate) -> String {
+        return template.render(self.name, &self.first_name, &self.last_name);
+    }
+}
+
 impl Entity for WithBaseDN<'_, User<'_, '_>> {
     const OBJECT_CLASSES: &'static [&'static str] = &[
         "inetOrgPerson",
-        "organizationalPerson",
-        "person",
-        "top",
     ];
 
     const ATTRIBUTES: &'static [AttributeName] = &[
         ATTR_OBJECT_CLASS,
         ATTR_ENTRY_DN,
+        ATTR_ENTRY_UUID,
+        ATTR_CREATE_TIMESTAMP,
+        ATTR_MODIFY_TIMESTAMP,
         ATTR_CN,
         ATTR_DISPLAY_NAME,
         ATTR_GIVEN_NAME,
         ATTR_SN,
         ATTR_MAIL,
+        ATTR_MAIL_ALIAS,
         ATTR_MEMBER_OF,
+        ATTR_UID,
+        ATTR_UID_NUMBER,
+        ATTR_GID_NUMBER,
+        ATTR_HOME_DIRECTORY,
+        ATTR_LOGIN_SHELL,
+        ATTR_SHADOW_LAST_CHANGE,
+        ATTR_SHADOW_MAX,
+        ATTR_SHADOW_EXPIRE,
+        ATTR_SSH_PUBLIC_KEY,
     ];
 
     fn dn(&self) -> DN {
-        return self.base_dn()
-            .join(("ou", "users"))
-            .join(("cn", self.name));
+        return self.layout.pattern("users").nested_dn(self.base_dn(), self.units, self.name);
     }
 
     fn get(attribute: &AttributeName) -> Option<for<'a> fn(&'a Self) -> Vec<String>> {
         if attribute == &ATTR_OBJECT_CLASS {
-            return Some(|_| Self::OBJECT_CLASSES.iter().map(ToString::to_string).collect());
+            return Some(|e| {
+                let mut classes = Self::OBJECT_CLASSES.to_vec();
+                if e.posix.is_some() {
+                    classes.push("posixAccount");
+                }
+                if e.password_changed.is_some() || e.password_max_age.is_some() || e.expires.is_some() {
+                    classes.push("shadowAccount");
+                }
+                if !e.ssh_keys.is_empty() {
+                    classes.push("ldapPublicKey");
+                }
+                return object_class_hierarchy(&classes);
+            });
         }
 
         if attribute == &ATTR_ENTRY_DN {
             return Some(|e| vec![e.dn().to_string()]);
         }
 
+        if attribute == &ATTR_ENTRY_UUID {
+            return Some(|e| vec![e.uuid.to_string()]);
+        }
+
+        if attribute == &ATTR_CREATE_TIMESTAMP {
+            return Some(|e| e.created.map(generalized_time).into_iter().collect());
+        }
+
+        if attribute == &ATTR_MODIFY_TIMESTAMP {
+            return Some(|e| e.modified.map(generalized_time).into_iter().collect());
+        }
+
+        // The name is kept as common name if used as RDN
         if attribute == &ATTR_CN {
-            return Some(|e| vec![e.name.to_string()]);
+            return Some(|e| match (&e.layout().common_name, e.layout().pattern("users").rdn) {
+                (Some(template), RdnAttribute::Cn) => [e.render_name(template), e.name.to_string()].into_iter().unique().collect(),
+                (Some(template), RdnAttribute::Uid) => vec![e.render_name(template)],
+                (None, _) => vec![e.name.to_string()],
+            });
         }
 
         if attribute == &ATTR_DISPLAY_NAME {
-            return Some(|e| vec![e.name.to_string()]);
+            return Some(|e| match &e.layout().display_name {
+                Some(template) => vec![e.render_name(template)],
+                None => vec![e.name.to_string()],
+            });
         }
 
         if attribute == &ATTR_GIVEN_NAME {
@@ -112,68 +262,330 @@ impl Entity for WithBaseDN<'_, User<'_, '_>> {
         }
 
         if attribute == &ATTR_MAIL {
-            return Some(|e| vec![e.mail.to_string()]);
+            return Some(|e| e.mail.clone());
+        }
+
+        if attribute == &ATTR_MAIL_ALIAS {
+            return Some(|e| e.mail_aliases.clone());
         }
 
         if attribute == &ATTR_MEMBER_OF {
             return Some(|e| e.groups()
-                .map(|group| group.with_base_dn(e.base_dn()))
+                .map(|group| group.with_base_dn(e.base_dn(), e.layout()))
                 .map(|group| group.dn().to_string())
                 .collect());
         }
 
+        if attribute == &ATTR_UID {
+            return Some(|e| vec![e.name.to_string()]);
+        }
+
+        if attribute == &ATTR_UID_NUMBER {
+            return Some(|e| e.posix.iter().map(|posix| posix.uid_number.to_string()).collect());
+        }
+
+        if attribute == &ATTR_GID_NUMBER {
+            return Some(|e| e.posix.iter().map(|posix| posix.gid_number.to_string()).collect());
+        }
+
+        if attribute == &ATTR_HOME_DIRECTORY {
+            return Some(|e| e.posix.iter().map(|posix| posix.home_directory.to_string()).collect());
+        }
+
+        if attribute == &ATTR_LOGIN_SHELL {
+            return Some(|e| e.posix.iter().filter_map(|posix| posix.login_shell.clone()).collect());
+        }
+
+        if attribute == &ATTR_SHADOW_LAST_CHANGE {
+            return Some(|e| e.password_changed.map(epoch_days).iter().map(ToString::to_string).collect());
+        }
+
+        if attribute == &ATTR_SHADOW_MAX {
+            return Some(|e| e.password_max_age.iter().map(ToString::to_string).collect());
+        }
+
+        if attribute == &ATTR_SHADOW_EXPIRE {
+            return Some(|e| e.expires.map(epoch_days).iter().map(ToString::to_string).collect());
+        }
+
+        if attribute == &ATTR_SSH_PUBLIC_KEY {
+            return Some(|e| e.ssh_keys.clone());
+        }
+
         return None;
     }
+
+    /// Additional and derived attributes of the user unless shadowing an exposed attribute
+    fn extra_attributes(&self) -> Vec<AttributeName> {
+        return self.attributes.keys()
+            .chain(self.binary_attributes.keys())
+            .chain(self.derived.keys())
+            .filter(|name| name.parse::<AttributeName>().map_or(false, |attribute| Self::get(&attribute).is_none()))
+            .map(|name| AttributeName::from(name.clone()))
+            .unique()
+            .collect();
+    }
+
+    fn values(&self, attribute: &AttributeName) -> Option<Vec<String>> {
+        let derived = || self.derived.iter()
+            .find(|(name, _)| name.parse::<AttributeName>().map_or(false, |name| &name == attribute))
+            .map(|(_, value)| vec![value.clone()]);
+
+        // Derived values fill in exposed attributes without any value
+        if let Some(getter) = Self::get(attribute) {
+            let values = getter(self);
+            if values.is_empty() {
+                return Some(derived().unwrap_or(values));
+            }
+            return Some(values);
+        }
+
+        return self.attributes.iter()
+            .find(|(name, _)| name.parse::<AttributeName>().map_or(false, |name| &name == attribute))
+            .map(|(_, values)| values.clone())
+            .or_else(derived);
+    }
+
+    fn raw_values(&self, attribute: &AttributeName) -> Option<Vec<Vec<u8>>> {
+        if let Some(values) = self.values(attribute) {
+            return Some(values.into_iter().map(String::into_bytes).collect());
+        }
+
+        // Values of files which could not be read are empty and left out
+        return self.binary_attributes.iter()
+            .find(|(name, _)| name.parse::<AttributeName>().map_or(false, |name| &name == attribute))
+            .map(|(_, values)| values.iter()
+                .map(|value| value.bytes().to_vec())
+                .filter(|value| !value.is_empty())
+                .collect());
+    }
 }
 
 impl Group<'_, '_> {
-    pub fn with_base_dn(self, base_dn: &DN) -> WithBaseDN<Self> {
+    pub fn with_base_dn<'dn>(self, base_dn: &'dn DN, layout: &'dn Layout) -> WithBaseDN<'dn, Self> {
         return WithBaseDN {
             base_dn,
+            layout,
             entity: self,
         };
     }
 }
 
-impl Entity for WithBaseDN<'_, Group<'_, '_>> {
+impl<'dn, 'db, 'data> WithBaseDN<'dn, Group<'db, 'data>> {
+    pub fn dn(&self) -> DN {
+        return self.layout.pattern("groups").dn(self.base_dn, self.name);
+    }
+
+    pub fn with_posix(self, posix: PosixGroups) -> GroupEntry<'dn, 'db, 'data> {
+        return GroupEntry {
+            group: self,
+            posix,
+        };
+    }
+}
+
+/// A group exposed as entry according to the configured POSIX group exposure
+pub struct GroupEntry<'dn, 'db, 'data> {
+    group: WithBaseDN<'dn, Group<'db, 'data>>,
+    posix: PosixGroups,
+}
+
+impl GroupEntry<'_, '_, '_> {
+    /// The GID number of the group if it is exposed as POSIX group
+    fn posix_gid_number(&self) -> Option<u32> {
+        return self.gid_number()
+            .filter(|_| self.posix != PosixGroups::Disabled);
+    }
+
+    /// Whether the group is exposed as POSIX group only
+    fn posix_only(&self) -> bool {
+        return self.posix == PosixGroups::Exclusive && self.gid_number().is_some();
+    }
+}
+
+impl<'dn, 'db, 'data> Deref for GroupEntry<'dn, 'db, 'data> {
+    type Target = WithBaseDN<'dn, Group<'db, 'data>>;
+
+    fn deref(&self) -> &Self::Target {
+        return &self.group;
+    }
+}
+
+impl Entity for GroupEntry<'_, '_, '_> {
     const OBJECT_CLASSES: &'static [&'static str] = &[
         "groupOfUniqueNames",
-        "top"
     ];
 
     const ATTRIBUTES: &'static [AttributeName] = &[
         ATTR_OBJECT_CLASS,
         ATTR_ENTRY_DN,
+        ATTR_ENTRY_UUID,
+        ATTR_CREATE_TIMESTAMP,
+        ATTR_MODIFY_TIMESTAMP,
         ATTR_CN,
+        ATTR_DESCRIPTION,
+        ATTR_MAIL,
         ATTR_UNIQUE_MEMBERS,
+        ATTR_OWNER,
+        ATTR_UID,
+        ATTR_GID_NUMBER,
+        ATTR_MEMBER_UID,
     ];
 
     fn dn(&self) -> DN {
-        return self.base_dn
-            .join(("ou", "groups"))
-            .join(("cn", self.name));
+        return self.group.dn();
     }
 
     fn get(attribute: &AttributeName) -> Option<for<'a> fn(&'a Self) -> Vec<String>> {
         if attribute == &ATTR_OBJECT_CLASS {
-            return Some(|_| Self::OBJECT_CLASSES.iter().map(ToString::to_string).collect());
+            return Some(|e| match (e.posix_gid_number(), e.posix_only()) {
+                (Some(_), true) => object_class_hierarchy(&["posixGroup"]),
+                (Some(_), false) => object_class_hierarchy(&[Self::OBJECT_CLASSES, &["posixGroup"]].concat()),
+                (None, _) => Self::object_classes(),
+            });
         }
 
         if attribute == &ATTR_ENTRY_DN {
             return Some(|e| vec![e.dn().to_string()]);
         }
 
+        if attribute == &ATTR_ENTRY_UUID {
+            return Some(|e| vec![e.uuid().to_string()]);
+        }
+
+        if attribute == &ATTR_CREATE_TIMESTAMP {
+            return Some(|e| e.created().map(generalized_time).into_iter().collect());
+        }
+
+        if attribute == &ATTR_MODIFY_TIMESTAMP {
+            return Some(|e| e.modified().map(generalized_time).into_iter().collect());
+        }
+
         if attribute == &ATTR_CN {
             return Some(|e| vec![e.name.to_string()]);
         }
 
+        if attribute == &ATTR_DESCRIPTION {
+            return Some(|e| e.description().map(ToString::to_string).into_iter().collect());
+        }
+
+        if attribute == &ATTR_MAIL {
+            return Some(|e| e.mail().to_vec());
+        }
+
         if attribute == &ATTR_UNIQUE_MEMBERS {
             return Some(|e| e.members()
-                .map(|user| user.with_base_dn(e.base_dn()))
+                .filter(|_| !e.posix_only())
+                .map(|user| user.with_base_dn(e.base_dn(), e.layout()))
                 .map(|user| user.dn().to_string())
                 .collect());
         }
 
+        if attribute == &ATTR_OWNER {
+            return Some(|e| e.managers()
+                .filter(|_| !e.posix_only())
+                .map(|user| user.with_base_dn(e.base_dn(), e.layout()))
+                .map(|user| user.dn().to_string())
+                .chain(e.owner()
+                    .filter(|_| !e.posix_only())
+                    .and_then(|owner| owner.parse::<DN>().ok())
+                    .map(|owner| e.base_dn().join(owner).to_string()))
+                .collect());
+        }
+
+        // The name is only exposed as user ID if used as RDN
+        if attribute == &ATTR_UID {
+            return Some(|e| match e.layout().pattern("groups").rdn {
+                RdnAttribute::Uid => vec![e.name.to_string()],
+                RdnAttribute::Cn => vec![],
+            });
+        }
+
+        if attribute == &ATTR_GID_NUMBER {
+            return Some(|e| e.posix_gid_number().iter().map(ToString::to_string).collect());
+        }
+
+        if attribute == &ATTR_MEMBER_UID {
+            return Some(|e| e.members()
+                .filter(|_| e.posix_gid_number().is_some())
+                .map(|user| user.name.to_string())
+                .collect());
+        }
+
+        return None;
+    }
+}
+
+impl Service<'_> {
+    pub fn with_base_dn<'dn>(self, base_dn: &'dn DN, layout: &'dn Layout) -> WithBaseDN<'dn, Self> {
+        return WithBaseDN {
+            base_dn,
+            layout,
+            entity: self,
+        };
+    }
+}
+
+impl Entity for WithBaseDN<'_, Service<'_>> {
+    const OBJECT_CLASSES: &'static [&'static str] = &[
+        "applicationProcess",
+    ];
+
+    const ATTRIBUTES: &'static [AttributeName] = &[
+        ATTR_OBJECT_CLASS,
+        ATTR_ENTRY_DN,
+        ATTR_ENTRY_UUID,
+        ATTR_CREATE_TIMESTAMP,
+        ATTR_MODIFY_TIMESTAMP,
+        ATTR_CN,
+        ATTR_DESCRIPTION,
+    ];
+
+    fn dn(&self) -> DN {
+        return self.layout.pattern("services").dn(self.base_dn(), self.name);
+    }
+
+    fn get(attribute: &AttributeName) -> Option<for<'a> fn(&'a Self) -> Vec<String>> {
+        if attribute == &ATTR_OBJECT_CLASS {
+            return Some(|_| Self::object_classes());
+        }
+
+        if attribute == &ATTR_ENTRY_DN {
+            return Some(|e| vec![e.dn().to_string()]);
+        }
+
+        if attribute == &ATTR_ENTRY_UUID {
+            return Some(|e| vec![e.uuid.to_string()]);
+        }
+
+        if attribute == &ATTR_CREATE_TIMESTAMP {
+            return Some(|e| e.created.map(generalized_time).into_iter().collect());
+        }
+
+        if attribute == &ATTR_MODIFY_TIMESTAMP {
+            return Some(|e| e.modified.map(generalized_time).into_iter().collect());
+        }
+
+        if attribute == &ATTR_CN {
+            return Some(|e| vec![e.name.to_string()]);
+        }
+
+        if attribute == &ATTR_DESCRIPTION {
+            return Some(|e| e.description.iter().map(ToString::to_string).collect());
+        }
+
         return None;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_object_class_hierarchy() {
+        assert_eq!(object_class_hierarchy(&["inetOrgPerson"]), vec!["inetOrgPerson", "organizationalPerson", "person", "top"]);
+        assert_eq!(object_class_hierarchy(&["alias", "extensibleObject"]), vec!["alias", "top", "extensibleObject"]);
+        assert_eq!(object_class_hierarchy(&["unknown"]), vec!["unknown"]);
+    }
+}
\ No newline at end of file
@@ -5,32 +5,101 @@ use crate::ldap::dn::DN;
 
 use super::dn::AttributeName;
 use super::entities::Entity;
+use super::schema::{MatchingRule, Schema};
 
 pub enum Filter {
     And(Vec<Filter>),
     Or(Vec<Filter>),
     Not(Box<Filter>),
     Equality(AttributeName, String),
+    Approx(AttributeName, String),
     Present(AttributeName),
+    Extensible {
+        attribute: Option<AttributeName>,
+        rule: Option<MatchingRule>,
+        value: String,
+
+        /// Whether the attributes of the DN of the entry are matched as well
+        dn_attributes: bool,
+    },
 }
 
 impl Filter {
-    pub fn evaluate<E: Entity>(&self, entity: &E) -> bool {
+    /// Checks whether the entity matches the filter, terms on attributes not released to the client never match
+    pub fn evaluate<E: Entity>(&self, entity: &E, schema: &Schema, released: &dyn Fn(&AttributeName) -> bool) -> bool {
+        return self.check(entity, schema, released) == Some(true);
+    }
+
+    /// Evaluates the filter to true, false or undefined (`None`) following RFC 4511, section 4.5.1.7
+    ///
+    /// Terms on attributes not released to the client are undefined, so the values can not be probed by filters and
+    /// not even negated terms match.
+    fn check<E: Entity>(&self, entity: &E, schema: &Schema, released: &dyn Fn(&AttributeName) -> bool) -> Option<bool> {
         return match self {
-            Filter::And(filters) => filters.iter()
-                .all(|filter| filter.evaluate(entity)),
+            // False wins over undefined for conjunctions, as does true for disjunctions
+            Filter::And(filters) => {
+                let results = filters.iter()
+                    .map(|filter| filter.check(entity, schema, released))
+                    .collect::<Vec<_>>();
+                if results.contains(&Some(false)) { Some(false) } else if results.contains(&None) { None } else { Some(true) }
+            }
+
+            Filter::Or(filters) => {
+                let results = filters.iter()
+                    .map(|filter| filter.check(entity, schema, released))
+                    .collect::<Vec<_>>();
+                if results.contains(&Some(true)) { Some(true) } else if results.contains(&None) { None } else { Some(false) }
+            }
+
+            Filter::Not(filter) => filter.check(entity, schema, released).map(|result| !result),
+
+            Filter::Equality(attribute, expected) => {
+                let attribute = schema.resolve(attribute);
+                let rule = schema.matching_rule(&attribute);
+                released(&attribute).then(|| match entity.values(&attribute) {
+                    Some(values) => values.iter().any(|value| rule.matches(value, expected)),
+                    None => false,
+                })
+            }
+
+            Filter::Approx(attribute, expected) => {
+                let attribute = schema.resolve(attribute);
+                released(&attribute).then(|| match entity.values(&attribute) {
+                    Some(values) => values.iter().any(|value| schema.approx_match.matches(value, expected)),
+                    None => false,
+                })
+            }
+
+            Filter::Present(attribute) => {
+                let attribute = schema.resolve(attribute);
+                released(&attribute).then(|| entity.has(&attribute))
+            }
+
+            Filter::Extensible { attribute, rule, value, dn_attributes } => {
+                let attribute = attribute.as_ref().map(|attribute| schema.resolve(attribute));
+                let rule = |attribute: &AttributeName| rule.unwrap_or_else(|| schema.matching_rule(attribute));
 
-            Filter::Or(filters) => filters.iter()
-                .any(|filter| filter.evaluate(entity)),
+                if attribute.as_ref().map_or(false, |attribute| !released(attribute)) {
+                    return None;
+                }
 
-            Filter::Not(filter) => !filter.evaluate(entity),
+                // Without an attribute type, all attributes of the entry released to the client are matched
+                let attributes = match &attribute {
+                    Some(attribute) => vec![attribute.clone()],
+                    None => E::ATTRIBUTES.iter().cloned().chain(entity.extra_attributes())
+                        .filter(|attribute| released(attribute))
+                        .collect(),
+                };
 
-            Filter::Equality(attribute, expected) => match E::get(attribute).map(|attribute| attribute(entity)) {
-                Some(values) => values.iter().any(|value| value == expected),
-                None => false,
-            },
+                let matched = attributes.iter()
+                    .any(|attribute| entity.values(attribute)
+                        .map_or(false, |values| values.iter().any(|v| rule(attribute).matches(v, value))));
 
-            Filter::Present(attribute) => E::has(&attribute),
+                Some(matched || (*dn_attributes && entity.dn().iter()
+                    .flat_map(|rdn| rdn.iter())
+                    .filter(|component| attribute.as_ref().map_or(true, |attribute| component.name() == attribute))
+                    .any(|component| rule(component.name()).matches(component.value(), value))))
+            }
         };
     }
 }
@@ -43,13 +112,65 @@ impl TryFrom<&LdapFilter> for Filter {
             LdapFilter::And(filters) => Ok(Self::And(filters.iter().map(Filter::try_from).collect::<Result<_>>()?)),
             LdapFilter::Or(filters) => Ok(Self::Or(filters.iter().map(Filter::try_from).collect::<Result<_>>()?)),
             LdapFilter::Not(filter) => Ok(Self::Not(Box::new(Filter::try_from(filter.as_ref())?))),
-            LdapFilter::Equality(attribute, value) => Ok(Self::Equality(attribute.parse()?, value.to_string())),
+            LdapFilter::Equality(attribute, value) => Ok(Self::Equality(attribute.parse()?, unescape(value))),
             LdapFilter::Substring(_, _) => Err(anyhow!("Not supported")),
             LdapFilter::Present(attribute) => Ok(Self::Present(attribute.parse()?)),
+            LdapFilter::GreaterOrEqual(_, _) => Err(anyhow!("Not supported")),
+            LdapFilter::LessOrEqual(_, _) => Err(anyhow!("Not supported")),
+            LdapFilter::Approx(attribute, value) => Ok(Self::Approx(attribute.parse()?, unescape(value))),
+            LdapFilter::Extensible(assertion) => {
+                let rule = match &assertion.matching_rule {
+                    Some(rule) => Some(MatchingRule::lookup(rule).ok_or_else(|| anyhow!("Unsupported matching rule: {}", rule))?),
+                    None => None,
+                };
+
+                if rule.is_none() && assertion.type_.is_none() {
+                    return Err(anyhow!("Matching rule or attribute type required"));
+                }
+
+                Ok(Self::Extensible {
+                    attribute: assertion.type_.as_deref().map(str::parse).transpose()?,
+                    rule,
+                    value: unescape(&assertion.match_value),
+                    dn_attributes: assertion.dn_attributes,
+                })
+            }
         };
     }
 }
 
+/// Decodes RFC 4515 escapes (`\2a`, `\28`, ...) left in an assertion value by clients passing the string
+/// representation through
+///
+/// Escapes are decoded to bytes first so escaped multi-byte UTF-8 sequences are restored as a whole. Backslashes not
+/// followed by two hex digits are kept as they are, as are values not decoding to valid UTF-8.
+fn unescape(value: &str) -> String {
+    let raw = value.as_bytes();
+
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        let hex = raw.get(i + 1..i + 3)
+            .filter(|hex| raw[i] == b'\\' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match hex {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            None => {
+                bytes.push(raw[i]);
+                i += 1;
+            }
+        }
+    }
+
+    return String::from_utf8(bytes)
+        .unwrap_or_else(|_| value.to_owned());
+}
+
 pub struct Scope {
     pub base: DN,
     pub scope: LdapSearchScope,
@@ -61,10 +182,69 @@ impl Scope {
             LdapSearchScope::Base => entity.dn() == self.base,
             LdapSearchScope::OneLevel => entity.dn().parent() == self.base,
             LdapSearchScope::Subtree => entity.dn().is_descendant_of(&self.base),
+            LdapSearchScope::Children => entity.dn().is_descendant_of(&self.base) && entity.dn() != self.base,
         };
     }
 
     pub fn is_root_dse(&self) -> bool {
         return self.base == DN::ROOT && self.scope == LdapSearchScope::Base;
     }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ldap::entities::{ATTR_CN, ATTR_MAIL};
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("Lucifer \\28the devil\\29"), "Lucifer (the devil)");
+        assert_eq!(unescape("\\2a\\5c"), "*\\");
+        assert_eq!(unescape("M\\c3\\bcller"), "Müller");
+        assert_eq!(unescape("Müller"), "Müller");
+        assert_eq!(unescape("trailing\\2"), "trailing\\2");
+        assert_eq!(unescape("\\ff"), "\\ff");
+        assert_eq!(unescape("\\+f"), "\\+f");
+    }
+
+    struct Person;
+
+    impl Entity for Person {
+        const OBJECT_CLASSES: &'static [&'static str] = &["person"];
+        const ATTRIBUTES: &'static [AttributeName] = &[ATTR_CN, ATTR_MAIL];
+
+        fn dn(&self) -> DN {
+            return "cn=brain,dc=example,dc=com".parse().unwrap();
+        }
+
+        fn get(attribute: &AttributeName) -> Option<for<'a> fn(&'a Self) -> Vec<String>> {
+            if attribute == &ATTR_CN {
+                return Some(|_| vec!["brain".to_string()]);
+            }
+
+            if attribute == &ATTR_MAIL {
+                return Some(|_| vec!["brain@example.com".to_string()]);
+            }
+
+            return None;
+        }
+    }
+
+    #[test]
+    fn test_unreleased_undefined() {
+        let schema = Schema::default();
+        let released = |attribute: &AttributeName| attribute != &ATTR_MAIL;
+
+        let cn = || Filter::Equality(ATTR_CN, "brain".to_string());
+        let mail = || Filter::Equality(ATTR_MAIL, "pinky@example.com".to_string());
+
+        assert!(!mail().evaluate(&Person, &schema, &released));
+        assert!(!Filter::Not(Box::new(mail())).evaluate(&Person, &schema, &released));
+        assert!(!Filter::Present(ATTR_MAIL).evaluate(&Person, &schema, &released));
+        assert!(!Filter::And(vec![cn(), Filter::Not(Box::new(mail()))]).evaluate(&Person, &schema, &released));
+        assert!(Filter::Or(vec![cn(), mail()]).evaluate(&Person, &schema, &released));
+
+        assert!(Filter::Not(Box::new(mail())).evaluate(&Person, &schema, &|_| true));
+        assert!(Filter::And(vec![cn(), Filter::Not(Box::new(mail()))]).evaluate(&Person, &schema, &|_| true));
+    }
 }
\ No newline at end of file
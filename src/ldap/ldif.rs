@@ -0,0 +1,548 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{Days, NaiveDate};
+use itertools::Itertools;
+use rand_core::{OsRng, RngCore};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::database::{Actor, BinaryValue, Database, GroupEntity, PasswordHash, PosixAccount, SharedDatabase, User, UserEntity};
+
+use super::{ATTR_UNIQUE_MEMBER, ATTR_USER_PASSWORD, Config};
+use super::alias::AliasEntry;
+use super::container::ContainerEntry;
+use super::dn::{AttributeName, DN};
+use super::entities::{ATTR_CN, ATTR_DESCRIPTION, ATTR_ENTRY_DN, ATTR_ENTRY_UUID, ATTR_GID_NUMBER, ATTR_GIVEN_NAME, ATTR_HOME_DIRECTORY, ATTR_LOGIN_SHELL, ATTR_MAIL, ATTR_MAIL_ALIAS, ATTR_MEMBER, ATTR_MEMBER_UID, ATTR_OBJECT_CLASS, ATTR_OWNER, ATTR_SHADOW_EXPIRE, ATTR_SHADOW_LAST_CHANGE, ATTR_SHADOW_MAX, ATTR_SN, ATTR_SSH_PUBLIC_KEY, ATTR_UID, ATTR_UID_NUMBER, Entity, WithBaseDN};
+
+/// Object classes of entries imported as users
+pub const USER_CLASSES: &[&str] = &["person", "organizationalperson", "inetorgperson", "posixaccount"];
+
+/// Object classes of entries imported as groups
+pub const GROUP_CLASSES: &[&str] = &["groupofnames", "groupofuniquenames", "posixgroup"];
+
+/// Operational attributes maintained by other directories which are not kept
+const MAINTAINED: &[AttributeName] = &[
+    ATTR_USER_PASSWORD,
+    AttributeName::from("structuralObjectClass"),
+    AttributeName::from("entryCSN"),
+    AttributeName::from("creatorsName"),
+    AttributeName::from("modifiersName"),
+    AttributeName::from("hasSubordinates"),
+    AttributeName::from("subschemaSubentry"),
+    AttributeName::from("pwdChangedTime"),
+];
+
+/// Entry of an LDIF file with the attribute values in order of appearance
+#[derive(Debug)]
+pub struct Record {
+    pub dn: DN,
+    pub attributes: Vec<(AttributeName, Vec<u8>)>,
+}
+
+impl Record {
+    /// All values of the attribute which are valid UTF-8
+    pub fn strings(&self, name: &AttributeName) -> Vec<String> {
+        return self.attributes.iter()
+            .filter(|(attribute, _)| attribute == name)
+            .filter_map(|(_, value)| String::from_utf8(value.clone()).ok())
+            .collect();
+    }
+
+    pub fn first(&self, name: &AttributeName) -> Option<String> {
+        return self.strings(name).into_iter().next();
+    }
+
+    /// The first value of the attribute, falling back to the value of the RDN
+    pub fn name(&self, name: &AttributeName) -> Option<String> {
+        return self.first(name).or_else(|| rdn_value(&self.dn));
+    }
+
+    pub fn is(&self, classes: &[&str]) -> bool {
+        return self.strings(&ATTR_OBJECT_CLASS).iter()
+            .any(|class| classes.contains(&class.to_lowercase().as_str()));
+    }
+}
+
+/// Splits an attribute line into the name without options and the decoded value
+fn parse_line(line: &str) -> Result<(&str, Vec<u8>)> {
+    let (description, value) = line.split_once(':')
+        .context("Missing separator")?;
+
+    // Options like `;binary` or language tags are not distinguished
+    let name = description.split(';').next().unwrap_or_default();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+        bail!("Invalid attribute name: {:?}", description);
+    }
+
+    if let Some(value) = value.strip_prefix(':') {
+        return Ok((name, BASE64.decode(value.trim()).context("Invalid base64")?));
+    }
+
+    if value.starts_with('<') {
+        bail!("Values given by URL are not supported");
+    }
+
+    return Ok((name, value.trim_start_matches(' ').as_bytes().to_vec()));
+}
+
+/// Parses the records of an LDIF file as described by RFC 2849, permitting only records adding entries
+pub fn parse(input: &str) -> Result<Vec<Record>> {
+    // Folded lines are joined while keeping the number of the first line for errors
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        if let Some(continuation) = line.strip_prefix(' ') {
+            match lines.last_mut() {
+                Some((_, last)) if !last.is_empty() => last.push_str(continuation),
+                _ => bail!("Line {}: Continuation without preceding line", number + 1),
+            }
+        } else {
+            lines.push((number + 1, line.to_owned()));
+        }
+    }
+
+    let mut records = Vec::new();
+    let mut record: Option<Record> = None;
+
+    for (number, line) in lines {
+        if line.starts_with('#') {
+            continue;
+        }
+
+        if line.is_empty() {
+            records.extend(record.take());
+            continue;
+        }
+
+        let (name, value) = parse_line(&line)
+            .with_context(|| format!("Line {}", number))?;
+
+        match &mut record {
+            None if name.eq_ignore_ascii_case("version") && records.is_empty() => {
+                if value != b"1" {
+                    bail!("Line {}: Unsupported version", number);
+                }
+            }
+
+            None if name.eq_ignore_ascii_case("dn") => {
+                let dn = String::from_utf8(value)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|dn| DN::from_str(&dn).map_err(|err| anyhow::anyhow!("{}", err)))
+                    .with_context(|| format!("Line {}: Invalid DN", number))?;
+
+                record = Some(Record {
+                    dn,
+                    attributes: Vec::new(),
+                });
+            }
+
+            None => bail!("Line {}: Record must start with a DN", number),
+
+            Some(_) if name.eq_ignore_ascii_case("changetype") => {
+                if value != b"add" {
+                    bail!("Line {}: Only records adding entries are supported", number);
+                }
+            }
+
+            Some(record) => record.attributes.push((AttributeName::from(name.to_owned()), value)),
+        }
+    }
+
+    records.extend(record);
+
+    return Ok(records);
+}
+
+/// Value of the first attribute in the RDN of the DN
+fn rdn_value(dn: &DN) -> Option<String> {
+    return dn.iter().next()?
+        .iter().next()
+        .map(|attribute| attribute.value().to_owned());
+}
+
+/// Converts a password of another directory, which is only possible for supported hashes and plain text
+pub fn password(database: &Database, value: Option<String>) -> Result<PasswordHash, String> {
+    return match value {
+        None => Err("No password".to_owned()),
+        Some(value) => super::parse_password(database, &value),
+    };
+}
+
+/// Hash of a random password, locking users whose password could not be converted
+pub fn random_password(database: &Database) -> PasswordHash {
+    let mut random = [0u8; 32];
+    OsRng.fill_bytes(&mut random);
+    return database.hash_password(&random).expect("Hashing random password");
+}
+
+/// Converts an entry to a user, keeping all attributes not exposed by concess itself verbatim
+pub fn user(record: &Record, password: PasswordHash, groups: Vec<String>) -> UserEntity {
+    let number = |attribute: &AttributeName| record.first(attribute)
+        .and_then(|value| value.trim().parse::<u32>().ok());
+    let date = |attribute: &AttributeName| number(attribute)
+        .and_then(|days| NaiveDate::from_ymd_opt(1970, 1, 1)?.checked_add_days(Days::new(days as u64)));
+
+    let posix = match (number(&ATTR_UID_NUMBER), number(&ATTR_GID_NUMBER), record.first(&ATTR_HOME_DIRECTORY)) {
+        (Some(uid_number), Some(gid_number), Some(home_directory)) => Some(PosixAccount {
+            uid_number,
+            gid_number,
+            home_directory,
+            login_shell: record.first(&ATTR_LOGIN_SHELL),
+        }),
+        _ => None,
+    };
+
+    let mut user = UserEntity {
+        password: password.into(),
+        first_name: record.first(&ATTR_GIVEN_NAME).unwrap_or_default(),
+        last_name: record.first(&ATTR_SN).unwrap_or_default(),
+        mail: record.strings(&ATTR_MAIL),
+        mail_aliases: record.strings(&ATTR_MAIL_ALIAS),
+        groups,
+        canary: false,
+        posix,
+        ssh_keys: record.strings(&ATTR_SSH_PUBLIC_KEY),
+        password_changed: date(&ATTR_SHADOW_LAST_CHANGE),
+        password_max_age: number(&ATTR_SHADOW_MAX),
+        expires: date(&ATTR_SHADOW_EXPIRE),
+        disabled: false,
+        locked_until: None,
+        not_before: None,
+        not_after: None,
+        totp_secret: None,
+        eap_password: None,
+        verifier: None,
+        attributes: Default::default(),
+        binary_attributes: Default::default(),
+        uuid: record.first(&ATTR_ENTRY_UUID).and_then(|uuid| Uuid::parse_str(&uuid).ok()),
+    };
+
+    let exposed = <WithBaseDN<User> as Entity>::ATTRIBUTES;
+    for (attribute, value) in record.attributes.iter() {
+        if exposed.contains(attribute) || MAINTAINED.contains(attribute) {
+            continue;
+        }
+
+        match String::from_utf8(value.clone()) {
+            Ok(value) => user.attributes.entry(attribute.to_string()).or_default().push(value),
+            Err(_) => user.binary_attributes.entry(attribute.to_string()).or_default().push(BinaryValue::Inline(value.clone())),
+        }
+    }
+
+    return user;
+}
+
+/// Converts an entry to a group without any members
+pub fn group(record: &Record, base_dn: &DN) -> GroupEntity {
+    let owner = record.first(&ATTR_OWNER)
+        .and_then(|owner| DN::from_str(&owner).ok())
+        .and_then(|owner| owner.relative_to(base_dn));
+
+    return GroupEntity {
+        description: record.first(&ATTR_DESCRIPTION),
+        owner: owner.map(|owner| owner.to_string()),
+        mail: record.strings(&ATTR_MAIL),
+        gid_number: record.first(&ATTR_GID_NUMBER).and_then(|gid| gid.trim().parse().ok()),
+        ..GroupEntity::default()
+    };
+}
+
+/// Names of the members of a group entry, resolved by the normalized DNs of the users
+pub fn members(record: &Record, names: &HashMap<String, String>) -> Vec<String> {
+    return record.strings(&ATTR_MEMBER).into_iter()
+        .chain(record.strings(&ATTR_UNIQUE_MEMBER))
+        .filter_map(|member| DN::from_str(&member).ok())
+        .filter_map(|member| names.get(&member.normalize()).cloned().or_else(|| rdn_value(&member)))
+        .chain(record.strings(&ATTR_MEMBER_UID))
+        .collect();
+}
+
+/// Summary of an import
+#[derive(Debug, Default)]
+pub struct Imported {
+    pub users: usize,
+    pub groups: usize,
+    pub skipped: usize,
+}
+
+impl fmt::Display for Imported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "Imported {} users and {} groups, skipped {} entries", self.users, self.groups, self.skipped);
+    }
+}
+
+/// Converts the users and groups of an LDIF file to entities while keeping existing entities untouched
+///
+/// Memberships of imported users are declared by the users while existing users are listed by the group.
+pub async fn import(path: &Path, base_dn: &DN, database: &mut Database) -> Result<Imported> {
+    let input = tokio::fs::read_to_string(path).await
+        .with_context(|| format!("Reading LDIF: {:?}", path))?;
+    let records = parse(&input)
+        .with_context(|| format!("Parsing LDIF: {:?}", path))?;
+
+    let actor = Actor {
+        protocol: "ldif",
+        identity: "import".to_owned(),
+        source: path.display().to_string(),
+    };
+
+    let mut imported = Imported::default();
+
+    let mut users = Vec::new();
+    let mut groups = Vec::new();
+    for record in records.iter() {
+        if record.is(USER_CLASSES) {
+            users.extend(record.name(&ATTR_UID).map(|name| (name, record)));
+        } else if record.is(GROUP_CLASSES) {
+            groups.extend(record.name(&ATTR_CN).map(|name| (name, record)));
+        } else {
+            debug!("Skipping entry {}", record.dn);
+            imported.skipped += 1;
+        }
+    }
+
+    // Members are referenced by the DN of their entry which is not necessarily following the layout
+    let names = users.iter()
+        .map(|(name, record)| (record.dn.normalize(), name.clone()))
+        .collect::<HashMap<_, _>>();
+
+    let new = users.iter()
+        .map(|(name, _)| name.clone())
+        .filter(|name| database.user_entity(name).is_none())
+        .collect::<HashSet<_>>();
+
+    let mut memberships: HashMap<String, Vec<String>> = HashMap::new();
+    let mut entities = Vec::new();
+    for (name, record) in groups {
+        let mut entity = group(record, base_dn);
+
+        for member in members(record, &names) {
+            if new.contains(&member) {
+                memberships.entry(member).or_default().push(name.clone());
+            } else if database.user_entity(&member).is_some() {
+                entity.members.push(member);
+            } else {
+                warn!("Skipping unknown member {} of group {}", member, name);
+            }
+        }
+
+        entities.push((name, entity));
+    }
+
+    for (name, record) in users {
+        if !new.contains(&name) {
+            warn!("Skipping existing user {}", name);
+            imported.skipped += 1;
+            continue;
+        }
+
+        let password = password(database, record.first(&ATTR_USER_PASSWORD)).unwrap_or_else(|err| {
+            warn!("Locking user {} by a random password: {}", name, err);
+            return random_password(database);
+        });
+
+        let user = user(record, password, memberships.remove(&name).unwrap_or_default());
+        database.add_user(&actor, Vec::new(), name, user).await?;
+        imported.users += 1;
+    }
+
+    for (name, mut entity) in entities {
+        if database.group_entity(&name).is_some() {
+            warn!("Skipping existing group {}", name);
+            imported.skipped += 1;
+            continue;
+        }
+
+        entity.members = entity.members.into_iter().unique().collect();
+        database.save_group(&actor, &name, entity).await?;
+        imported.groups += 1;
+    }
+
+    return Ok(imported);
+}
+
+/// Attribute values of an entry as served to clients, leaving out the DN which is given by the record itself
+fn entry<E: Entity>(entity: E) -> (DN, Vec<(AttributeName, Vec<Vec<u8>>)>) {
+    let attributes = E::ATTRIBUTES.iter().cloned()
+        .chain(entity.extra_attributes())
+        .filter(|attribute| attribute != &ATTR_ENTRY_DN)
+        .filter_map(|attribute| entity.raw_values(&attribute)
+            .filter(|values| !values.is_empty())
+            .map(|values| (attribute, values)))
+        .collect();
+
+    return (entity.dn(), attributes);
+}
+
+/// Checks whether a value can be written as is instead of being base64 encoded
+fn is_safe(value: &[u8]) -> bool {
+    return match value.first() {
+        None => true,
+        Some(b' ' | b':' | b'<') => false,
+        Some(_) => value.last() != Some(&b' ') && value.iter().all(|c| c.is_ascii() && !matches!(c, b'\0' | b'\n' | b'\r')),
+    };
+}
+
+/// Writes an attribute value folded to lines of at most 76 characters
+fn write_line(out: &mut String, name: &str, value: &[u8]) {
+    let line = match is_safe(value) {
+        true => format!("{}: {}", name, String::from_utf8_lossy(value)),
+        false => format!("{}:: {}", name, BASE64.encode(value)),
+    };
+
+    // Lines are plain ASCII as everything else is encoded, so folding never splits a character
+    let mut rest = line.as_str();
+    let mut width = 76;
+    while rest.len() > width {
+        let (head, tail) = rest.split_at(width);
+        out.push_str(head);
+        out.push_str("\n ");
+        rest = tail;
+        width = 75;
+    }
+
+    out.push_str(rest);
+    out.push('\n');
+}
+
+/// Formats the entries as LDIF, ordered to have parents preceding their children
+pub fn format(mut entries: Vec<(DN, Vec<(AttributeName, Vec<Vec<u8>>)>)>) -> String {
+    // The sort is stable to keep the order of entries on the same level
+    entries.sort_by_key(|(dn, _)| dn.iter().count());
+
+    let mut out = String::from("version: 1\n");
+    for (dn, attributes) in entries {
+        out.push('\n');
+        write_line(&mut out, "dn", dn.to_string().as_bytes());
+
+        for (attribute, values) in attributes {
+            for value in values {
+                write_line(&mut out, &attribute.to_string(), &value);
+            }
+        }
+    }
+
+    return out;
+}
+
+/// Writes all entries of the directory as served to clients, including the mapping of attributes
+pub async fn export(config: &Config, database: &Arc<SharedDatabase>, tenants: &[Arc<SharedDatabase>]) -> String {
+    let contexts = super::naming_contexts(config, database, tenants);
+
+    let mut entries = Vec::new();
+    for context in contexts.iter() {
+        let database = context.database.snapshot();
+
+        entries.extend(ContainerEntry::collect(&context.base_dn, &context.layout, &database).into_iter()
+            .map(entry));
+
+        entries.extend(database.users()
+            .map(|user| user.with_base_dn(&context.base_dn, &context.layout))
+            .map(|user| config.mapping.users.apply(config.active_directory.user(&context.base_dn, user)))
+            .map(entry));
+
+        entries.extend(database.groups()
+            .map(|group| group.with_base_dn(&context.base_dn, &context.layout).with_posix(config.posix_groups))
+            .map(|group| config.mapping.groups.apply(config.active_directory.group(&context.base_dn, group)))
+            .map(entry));
+
+        entries.extend(database.services()
+            .map(|service| config.mapping.services.apply(service.with_base_dn(&context.base_dn, &context.layout)))
+            .map(entry));
+
+        entries.extend(database.aliases()
+            .filter_map(|alias| AliasEntry::resolve(alias, &context.base_dn))
+            .map(entry));
+    }
+
+    // Containers of nested naming contexts may be shared
+    let mut seen = HashSet::new();
+    entries.retain(|(dn, _)| seen.insert(dn.clone()));
+
+    return format(entries);
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use crate::database::{Config, Database};
+    use crate::ldap::dn::{AttributeName, DN};
+
+    use super::{format, parse, password};
+
+    #[test]
+    fn test_parse() {
+        let records = parse("version: 1\n\
+                             # comment\n\
+                             dn: uid=brain,ou=people,\n \
+                             dc=example,dc=com\n\
+                             objectClass: inetOrgPerson\n\
+                             cn:: QnLDpGlu\n\
+                             userCertificate;binary:: AAE=\n\
+                             \n\
+                             \n\
+                             dn: ou=people,dc=example,dc=com\n\
+                             changetype: add\n\
+                             ou: people\n").unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].dn, DN::from_str("uid=brain,ou=people,dc=example,dc=com").unwrap());
+        assert_eq!(records[0].attributes, vec![
+            (AttributeName::from("objectClass"), b"inetOrgPerson".to_vec()),
+            (AttributeName::from("cn"), "Bräin".as_bytes().to_vec()),
+            (AttributeName::from("userCertificate"), vec![0, 1]),
+        ]);
+        assert_eq!(records[1].dn, DN::from_str("ou=people,dc=example,dc=com").unwrap());
+
+        assert!(parse("objectClass: top\n").is_err());
+        assert!(parse("dn: ou=people\nchangetype: delete\n").is_err());
+        assert!(parse(" ou: people\n").is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let ldif = format(vec![
+            (DN::from_str("uid=brain,ou=users,dc=example,dc=com").unwrap(), vec![
+                (AttributeName::from("cn"), vec!["Bräin".as_bytes().to_vec(), b" brain".to_vec()]),
+                (AttributeName::from("description"), vec![b"x".repeat(100)]),
+            ]),
+            (DN::from_str("dc=example,dc=com").unwrap(), vec![]),
+        ]);
+
+        assert_eq!(ldif, format!("version: 1\n\
+                                  \n\
+                                  dn: dc=example,dc=com\n\
+                                  \n\
+                                  dn: uid=brain,ou=users,dc=example,dc=com\n\
+                                  cn:: QnLDpGlu\n\
+                                  cn:: IGJyYWlu\n\
+                                  description: {}\n {}\n", "x".repeat(63), "x".repeat(37)));
+
+        let records = parse(&ldif).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].attributes[2].1, b"x".repeat(100));
+    }
+
+    #[tokio::test]
+    async fn test_password() {
+        let dir = std::env::temp_dir().join(format!("concess-ldif-{}", std::process::id()));
+        tokio::fs::create_dir_all(dir.join("users")).await.unwrap();
+        let database = Database::load(&dir, &Config::default()).await.unwrap().snapshot();
+
+        // Unknown schemes are not mistaken for plain text passwords
+        assert!(password(&database, Some("{SSHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=".to_owned())).is_err());
+        assert!(password(&database, Some("{CRYPT}$1$narf$zort".to_owned())).is_err());
+        assert!(password(&database, None).is_err());
+
+        assert!(password(&database, Some("{narf zort}".to_owned())).is_ok());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
\ No newline at end of file
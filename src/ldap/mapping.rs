@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use itertools::Itertools;
+use serde::Deserialize;
+
+use super::dn::{AttributeName, DN};
+use super::entities::{ATTR_OBJECT_CLASS, Entity, object_class_hierarchy};
+
+/// Adjustments of the entries exposed for each kind of entity
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Mapping {
+    #[serde(default)]
+    pub users: EntityMapping,
+
+    #[serde(default)]
+    pub groups: EntityMapping,
+
+    #[serde(default)]
+    pub services: EntityMapping,
+}
+
+/// Adjustments of the attributes and object classes of entries on top of the built-in ones
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EntityMapping {
+    /// Object classes emitted in addition to the built-in ones
+    #[serde(default)]
+    pub object_classes: Vec<String>,
+
+    /// Emits only the configured object classes instead of the built-in ones
+    #[serde(default)]
+    pub replace_object_classes: bool,
+
+    /// Attributes computed from templates referring to built-in attributes like `{givenName} {sn}`
+    #[serde(default)]
+    pub attributes: HashMap<String, Template>,
+
+    /// Attributes exposed under another name
+    #[serde(default)]
+    pub rename: HashMap<AttributeName, String>,
+
+    /// Attributes which are not exposed at all
+    #[serde(default)]
+    pub hide: Vec<AttributeName>,
+}
+
+impl EntityMapping {
+    pub fn apply<E: Entity>(&self, entity: E) -> Mapped<'_, E> {
+        return Mapped {
+            entity,
+            mapping: self,
+        };
+    }
+
+    /// Names of the computed attributes keeping their case as configured
+    fn computed(&self) -> impl Iterator<Item=(AttributeName, &Template)> {
+        return self.attributes.iter()
+            .map(|(name, template)| (AttributeName::from(name.clone()), template));
+    }
+
+    /// Pairs of exposed attributes and the names they are exposed under keeping their case as configured
+    fn renamed(&self) -> impl Iterator<Item=(&AttributeName, AttributeName)> {
+        return self.rename.iter()
+            .map(|(source, target)| (source, AttributeName::from(target.clone())));
+    }
+
+    /// The attribute of the entity exposed under the given name if not hidden or renamed
+    fn source(&self, attribute: &AttributeName) -> Option<AttributeName> {
+        if let Some((source, _)) = self.renamed().find(|(_, target)| target == attribute) {
+            return Some(source.clone());
+        }
+
+        if self.hide.contains(attribute) || self.rename.contains_key(attribute) {
+            return None;
+        }
+
+        return Some(attribute.clone());
+    }
+}
+
+/// Value built by replacing references like `{mail}` by the first value of the referenced attribute
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Template(String);
+
+impl Template {
+    /// Renders the template unless a referenced attribute has no value
+    pub fn render(&self, lookup: impl Fn(&AttributeName) -> Option<String>) -> Option<String> {
+        let mut rendered = String::new();
+
+        let mut rest = self.0.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = start + rest[start..].find('}')?;
+
+            rendered.push_str(&rest[..start]);
+            rendered.push_str(&lookup(&AttributeName::from_str(&rest[start + 1..end]).ok()?)?);
+
+            rest = &rest[end + 1..];
+        }
+
+        rendered.push_str(rest);
+
+        return Some(rendered);
+    }
+}
+
+/// Entity exposed according to the configured mapping
+pub struct Mapped<'m, E> {
+    entity: E,
+    mapping: &'m EntityMapping,
+}
+
+impl<E: Entity> Entity for Mapped<'_, E> {
+    const OBJECT_CLASSES: &'static [&'static str] = E::OBJECT_CLASSES;
+
+    const ATTRIBUTES: &'static [AttributeName] = E::ATTRIBUTES;
+
+    fn dn(&self) -> DN {
+        return self.entity.dn();
+    }
+
+    // All values are looked up by the instance as the mapping is only known at runtime
+    fn get(_: &AttributeName) -> Option<for<'a> fn(&'a Self) -> Vec<String>> {
+        return None;
+    }
+
+    fn extra_attributes(&self) -> Vec<AttributeName> {
+        return self.entity.extra_attributes().into_iter()
+            .chain(self.mapping.renamed().map(|(_, target)| target))
+            .chain(self.mapping.computed().map(|(name, _)| name))
+            .filter(|attribute| !E::ATTRIBUTES.contains(attribute))
+            .unique()
+            .collect();
+    }
+
+    fn values(&self, attribute: &AttributeName) -> Option<Vec<String>> {
+        if let Some((_, template)) = self.mapping.computed().find(|(name, _)| name == attribute) {
+            return Some(template.render(|attribute| self.entity.values(attribute)?.into_iter().next())
+                .into_iter()
+                .collect());
+        }
+
+        let values = self.entity.values(&self.mapping.source(attribute)?)?;
+
+        if attribute == &ATTR_OBJECT_CLASS {
+            let classes = self.mapping.object_classes.iter().map(String::as_str).collect::<Vec<_>>();
+            let classes = object_class_hierarchy(&classes);
+
+            // Every entry is of the abstract top class even if not configured explicitly
+            if self.mapping.replace_object_classes {
+                return Some(classes.into_iter()
+                    .chain(["top".to_string()])
+                    .unique()
+                    .collect());
+            }
+
+            return Some(values.into_iter()
+                .chain(classes)
+                .unique()
+                .collect());
+        }
+
+        return Some(values);
+    }
+
+    fn raw_values(&self, attribute: &AttributeName) -> Option<Vec<Vec<u8>>> {
+        if self.mapping.computed().any(|(name, _)| &name == attribute) || attribute == &ATTR_OBJECT_CLASS {
+            return self.values(attribute)
+                .map(|values| values.into_iter().map(String::into_bytes).collect());
+        }
+
+        return self.entity.raw_values(&self.mapping.source(attribute)?);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ldap::entities::{ATTR_GIVEN_NAME, ATTR_SN};
+
+    #[test]
+    fn test_template() {
+        let lookup = |attribute: &AttributeName| match attribute {
+            attribute if attribute == &ATTR_GIVEN_NAME => Some("Brain".to_string()),
+            attribute if attribute == &ATTR_SN => Some("Mouse".to_string()),
+            _ => None,
+        };
+
+        assert_eq!(Template("{givenName} {sn}".to_string()).render(lookup), Some("Brain Mouse".to_string()));
+        assert_eq!(Template("static".to_string()).render(lookup), Some("static".to_string()));
+        assert_eq!(Template("{givenName}@{mail}".to_string()).render(lookup), None);
+        assert_eq!(Template("{givenName".to_string()).render(lookup), None);
+    }
+}
\ No newline at end of file
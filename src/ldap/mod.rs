@@ -1,29 +1,110 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
-use std::net::SocketAddr;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{Context, Result};
+use bytes::BytesMut;
 use futures::{SinkExt, StreamExt};
-use ldap3_proto::{LdapCodec, LdapPartialAttribute, LdapResultCode, LdapSearchResultEntry, SearchRequest, ServerOps, SimpleBindRequest, UnbindRequest, WhoamiRequest};
-use ldap3_proto::proto::LdapMsg;
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::RwLock;
+use futures::stream::FuturesUnordered;
+use lber::structure::StructureTag;
+use ldap3_proto::{CompareRequest, LdapPartialAttribute, LdapResultCode, LdapSearchScope, LdapSearchResultEntry, SearchRequest, ServerOps, SimpleBindRequest, UnbindRequest, WhoamiRequest};
+use ldap3_proto::proto::{LdapAddRequest, LdapBindCred, LdapBindRequest, LdapBindResponse, LdapDerefAliases, LdapExtendedResponse, LdapModify, LdapModifyDNRequest, LdapModifyRequest, LdapModifyType, LdapMsg, LdapOp, LdapResult, SaslCredentials};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tokio_rustls::rustls::Certificate;
+use tokio_rustls::TlsAcceptor;
 use tokio_util::codec::{FramedRead, FramedWrite};
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
 
-use crate::Database;
+use crate::admin::{Control, SessionHandle};
+use crate::database::{Actor, CANARY, is_valid_name, PasswordHash, User, UserEntity};
+use crate::database::{Database, SharedDatabase};
 use crate::ldap::filter::Scope;
+use crate::messages::{Message, Messages};
 
 pub use self::config::Config;
-use self::dn::DN;
-use self::entities::Entity;
+pub use self::ldif::{export, import};
+pub use self::peer::Peer;
+pub use self::tls::{acceptor, connector};
+pub use self::upstream::{mirror, verify_bind};
+use self::acl::Access;
+use self::alias::{AliasEntry, MAX_DEREF_HOPS};
+use self::capture::Capture;
+use self::codec::{Codec, Request};
+use self::container::ContainerEntry;
+use self::config::{AnonymousAccess, BindLookup};
+use self::context::{NamingContext, NamingContexts};
+use self::dn::{AttributeName, DN, RDN};
+use self::entities::{ATTR_CN, ATTR_GIVEN_NAME, ATTR_MAIL, ATTR_MAIL_ALIAS, ATTR_MEMBER_OF, ATTR_OBJECT_CLASS, ATTR_SN, ATTR_UID, ATTR_UNIQUE_MEMBERS, Entity};
 use self::filter::Filter;
+use self::monitor::{MonitorEntry, MONITOR};
+use self::schema::{MatchingRule, SUBSCHEMA};
+use self::psearch::{ChangeType, PersistentSearch};
+use self::selection::Selection;
 
+mod acl;
+mod alias;
+mod approx;
+mod capture;
+mod cancel;
+mod codec;
+mod container;
+mod context;
 mod dn;
 mod filter;
 mod entities;
 mod config;
+mod ldif;
+mod tls;
+mod schema;
+mod peer;
+mod selection;
+mod psearch;
+mod upstream;
+mod monitor;
+mod mapping;
+mod compat;
+
+/// Number of requests read ahead while operations are processed
+const PIPELINE_DEPTH: usize = 16;
+
+/// Number of operations processed concurrently per connection
+const MAX_CONCURRENT_OPERATIONS: usize = 8;
+
+/// Interval in which persistent searches check whether they have been interrupted
+const INTERRUPTION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time a client has to complete the TLS handshake after connecting
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const ATTR_USER_PASSWORD: AttributeName = AttributeName::from("userPassword");
+const ATTR_UNIQUE_MEMBER: AttributeName = AttributeName::from("uniqueMember");
+
+/// Unsolicited notification sent before terminating a session (RFC 4511, section 4.4.1)
+const NOTICE_OF_DISCONNECTION_OID: &str = "1.3.6.1.4.1.1466.20036";
+
+/// Client sent something the session can not recover from
+#[derive(Debug)]
+struct ProtocolViolation(String);
+
+impl std::fmt::Display for ProtocolViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "Protocol violation: {}", self.0);
+    }
+}
+
+impl std::error::Error for ProtocolViolation {}
+
+/// Reason for stopping an in-flight operation early
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Interruption {
+    Abandoned,
+    Canceled,
+}
 
 enum Binding {
     Unbound,
@@ -31,115 +112,1389 @@ enum Binding {
     Anonymous,
 }
 
-struct Session {
-    addr: SocketAddr,
+struct Session {
+    id: u64,
+    peer: Peer,
+
+    config: Arc<Config>,
+    contexts: Arc<NamingContexts>,
+    control: Arc<Control>,
+    messages: Arc<Messages>,
+
+    /// Client certificate presented during the TLS handshake
+    certificate: Option<Certificate>,
+
+    operations: Arc<Mutex<HashMap<i32, Option<Interruption>>>>,
+
+    /// Shared by the concurrently processed operations but only changed by exclusive ones
+    binding: Mutex<Binding>,
+
+    /// Entries returned by persistent searches while they are in progress
+    entries: mpsc::Sender<(i32, StructureTag)>,
+}
+
+fn response(msgid: i32, op: fn(LdapResult) -> LdapOp, code: LdapResultCode, message: &str) -> LdapMsg {
+    return LdapMsg {
+        msgid,
+        op: op(LdapResult {
+            code,
+            matcheddn: "".to_string(),
+            message: message.to_string(),
+            referral: vec![],
+        }),
+        ctrl: vec![],
+    };
+}
+
+fn extended_response(res: LdapResult) -> LdapOp {
+    return LdapOp::ExtendedResponse(LdapExtendedResponse {
+        res,
+        name: None,
+        value: None,
+    });
+}
+
+/// Returns the result operation for all requests the protocol defines a response for
+fn result_op(op: &LdapOp) -> Option<fn(LdapResult) -> LdapOp> {
+    return match op {
+        LdapOp::BindRequest(_) => Some(|res| LdapOp::BindResponse(LdapBindResponse {
+            res,
+            saslcreds: None,
+        })),
+        LdapOp::ExtendedRequest(_) => Some(extended_response),
+        op => cancel::result_op(op),
+    };
+}
+
+fn notice_of_disconnection(code: LdapResultCode, message: &str) -> LdapMsg {
+    return LdapMsg {
+        msgid: 0,
+        op: LdapOp::ExtendedResponse(LdapExtendedResponse {
+            res: LdapResult {
+                code,
+                matcheddn: "".to_string(),
+                message: message.to_string(),
+                referral: vec![],
+            },
+            name: Some(NOTICE_OF_DISCONNECTION_OID.to_string()),
+            value: None,
+        }),
+        ctrl: vec![],
+    };
+}
+
+/// Builds an LDAP URL (RFC 4516) pointing to the given DN on another server
+fn referral_url(server: &str, dn: &DN) -> String {
+    let dn = dn.to_string().bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'=' | b',' | b'+' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect::<String>();
+
+    return format!("{}/{}", server.trim_end_matches('/'), dn);
+}
+
+/// Accepts a pre-hashed password in `{ARGON2}` or `{CRYPT}` notation or hashes a plain text password
+///
+/// Any supported hash is accepted in `{ARGON2}` notation, while `{CRYPT}` is limited to bcrypt. Values in other
+/// `{SCHEME}` notations are rejected instead of hashing them as plain text.
+fn parse_password(database: &Database, value: &str) -> Result<PasswordHash, String> {
+    if let Some(hash) = value.strip_prefix("{ARGON2}") {
+        return PasswordHash::parse(hash);
+    }
+
+    if let Some(hash) = value.strip_prefix("{CRYPT}") {
+        return match PasswordHash::parse(hash)? {
+            hash @ PasswordHash::Bcrypt(_) => Ok(hash),
+            _ => Err("Unsupported crypt scheme".to_owned()),
+        };
+    }
+
+    if let Some((scheme, _)) = value.strip_prefix('{').and_then(|value| value.split_once('}')) {
+        if !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+            return Err(format!("Unsupported password scheme: {{{}}}", scheme));
+        }
+    }
+
+    return database.hash_password(value.as_bytes()).map_err(|err| format!("{:#}", err));
+}
+
+fn bind_response(msgid: i32, code: LdapResultCode, message: &str) -> LdapMsg {
+    return LdapMsg {
+        msgid,
+        op: LdapOp::BindResponse(LdapBindResponse {
+            res: LdapResult {
+                code,
+                matcheddn: "".to_string(),
+                message: message.to_string(),
+                referral: vec![],
+            },
+            saslcreds: None,
+        }),
+        ctrl: vec![],
+    };
+}
+
+impl Session {
+    /// DN of the bound user if the session is bound
+    fn bound_dn(&self) -> Option<DN> {
+        return match &*self.binding.lock().unwrap() {
+            Binding::Bound(dn) => Some(dn.clone()),
+            _ => None,
+        };
+    }
+
+    /// Checks if binding as the given DN would exceed the number of sessions permitted per DN
+    fn sessions_exhausted(&self, dn: &DN) -> bool {
+        return self.config.max_sessions_per_dn
+            .map_or(false, |limit| self.control.sessions().bound_as(&dn.normalize(), self.id) >= limit);
+    }
+
+    fn set_binding(&self, binding: Binding) {
+        *self.binding.lock().unwrap() = binding;
+    }
+
+    /// Binds local clients connected to the LDAPI socket as the user configured for their user ID
+    async fn auto_bind(&self) {
+        let uid = if let Peer::Unix { uid: Some(uid) } = self.peer { uid } else {
+            return;
+        };
+
+        let name = if let Some(name) = self.config.ldapi.as_ref().and_then(|ldapi| ldapi.peers.get(&uid)) { name } else {
+            return;
+        };
+
+        if self.control.maintenance() {
+            debug!("Skipping auto-bind in maintenance mode");
+            return;
+        }
+
+        let context = self.contexts.primary();
+        let database = context.database.snapshot();
+        let user = database.user(name)
+            .map(|user| user.with_base_dn(&context.base_dn, &context.layout));
+
+        match user {
+            Some(user) if self.suspension(&user).is_some() => debug!("User {} is suspended", user.name),
+            Some(user) if self.may_bind(&user.groups) => {
+                debug!("Auto-binding {} as {}", self.peer, user.name);
+                self.set_binding(Binding::Bound(user.dn()));
+            }
+            Some(user) => debug!("User {} not permitted to bind", user.name),
+            None => warn!("No user {} found for local user ID {}", name, uid),
+        }
+    }
+
+    /// Groups of the bound user deciding about the release of restricted attributes
+    ///
+    /// Groups are only effective within the naming context of the user.
+    fn bound_groups(&self, context: &NamingContext, database: &Database) -> Vec<String> {
+        return match self.bound_dn() {
+            Some(dn) => context.user(database, &dn)
+                .map(|user| user.groups.clone())
+                .unwrap_or_default(),
+            None => vec![],
+        };
+    }
+
+    /// Checks whether the session is permitted to search and compare
+    fn may_read(&self) -> bool {
+        return self.config.anonymous == AnonymousAccess::Read || self.bound_dn().is_some();
+    }
+
+    /// Checks whether the user is permitted to bind at all
+    fn may_bind(&self, groups: &[String]) -> bool {
+        return match &self.config.bind_group {
+            Some(group) => groups.contains(group),
+            None => true,
+        };
+    }
+
+    /// Message refusing the bind if the account of the user is disabled, locked or outside of its validity
+    fn suspension(&self, user: &User) -> Option<String> {
+        if user.disabled {
+            return Some(self.messages.render(Message::AccountDisabled, &[("user", user.name)]));
+        }
+
+        if let Some(until) = user.locked() {
+            return Some(self.messages.render(Message::AccountLocked, &[("user", user.name), ("until", &until.to_rfc3339())]));
+        }
+
+        if let Some(since) = user.pending() {
+            return Some(self.messages.render(Message::AccountPending, &[("user", user.name), ("since", &since.to_rfc3339())]));
+        }
+
+        return user.expired()
+            .map(|until| self.messages.render(Message::AccountExpired, &[("user", user.name), ("until", &until.to_rfc3339())]));
+    }
+
+    /// Identity recorded for changes made by this session
+    fn actor(&self) -> Actor {
+        return Actor {
+            protocol: "ldap",
+            identity: match self.bound_dn() {
+                Some(dn) => dn.to_string(),
+                None => "anonymous".to_string(),
+            },
+            source: self.peer.to_string(),
+        };
+    }
+
+    /// Access to the entry granted to the bound user by the access control list
+    fn access(&self, context: &NamingContext, database: &Database, entry: &DN) -> Access {
+        return self.config.acl.access(entry, self.bound_dn().as_ref(), &self.bound_groups(context, database));
+    }
+
+    /// Checks whether the bound user is permitted to modify the directory
+    fn may_write(&self, context: &NamingContext, database: &Database) -> bool {
+        let groups = self.bound_groups(context, database);
+        return self.config.writers.iter().any(|group| groups.contains(group));
+    }
+
+    /// Checks whether the bound user is permitted to change the members of the group
+    fn may_manage(&self, context: &NamingContext, database: &Database, group: &str) -> bool {
+        if self.may_write(context, database) {
+            return true;
+        }
+
+        let name = self.bound_dn()
+            .and_then(|dn| self.entry_name(context, database, &dn, "users"));
+
+        return match (name, database.group_entity(group)) {
+            (Some(name), Some(group)) => group.managers.contains(&name),
+            _ => false,
+        };
+    }
+
+    /// Resolves the name of the user or group from the DN of an entry
+    ///
+    /// Users may be placed in organizational units, which must be the units of the user if it exists.
+    fn entry_name(&self, context: &NamingContext, database: &Database, dn: &DN, container: &str) -> Option<String> {
+        let pattern = context.layout.pattern(container);
+        if container != "users" {
+            return pattern.name(&context.base_dn, dn);
+        }
+
+        let (units, name) = pattern.nested_name(&context.base_dn, dn)?;
+        return match database.user_units(&name) {
+            Some(existing) if existing != units.as_slice() => None,
+            _ => Some(name),
+        };
+    }
+
+    /// Resolves the names of referenced users or groups from their DNs
+    fn entry_names(&self, context: &NamingContext, database: &Database, dns: &[String], container: &str) -> Result<Vec<String>, String> {
+        return dns.iter()
+            .map(|dn| DN::parse_limited(dn, self.config.strict_dn).ok()
+                .and_then(|dn| self.entry_name(context, database, &dn, container))
+                .ok_or_else(|| format!("Invalid reference: {}", dn)))
+            .collect();
+    }
+
+    /// Checks whether the user is returned by searches
+    fn is_visible(&self, user: &User) -> bool {
+        return !self.config.hide_expired || user.expired().is_none();
+    }
+
+    fn is_released(&self, attribute: &AttributeName, groups: &[String]) -> bool {
+        return match self.config.attribute_release.get(attribute) {
+            Some(allowed) => allowed.iter().any(|group| groups.contains(group)),
+            None => true,
+        };
+    }
+
+    /// Resolves the DN of the entry an alias refers to
+    async fn alias_target(&self, dn: &DN) -> Option<DN> {
+        let context = self.contexts.find(dn)?;
+        return context.database.snapshot().aliases()
+            .filter_map(|alias| AliasEntry::resolve(alias, &context.base_dn))
+            .find(|alias| &alias.dn() == dn)
+            .map(|alias| alias.target);
+    }
+
+    pub async fn do_search(&self, req: SearchRequest, size_limit: i32, time_limit: i32, types_only: bool, deref: LdapDerefAliases) -> Result<Vec<LdapMsg>> {
+        if !self.may_read() {
+            debug!("Refusing search without bind");
+            return Ok(vec![req.gen_error(LdapResultCode::InsufficentAccessRights, "Bind required".to_string())]);
+        }
+
+        self.control.counters().increment(monitor::SEARCHES, None);
+
+        // TODO: Move error response handling to outer callee
+
+        let base = match DN::parse_limited(&req.base, self.config.strict_dn) {
+            Ok(base) => base,
+            Err(err) => return Ok(vec![req.gen_error(LdapResultCode::InvalidDNSyntax, err.to_string())]),
+        };
+
+        if base == DN::from_str(SUBSCHEMA)? {
+            if req.scope != LdapSearchScope::Base {
+                return Ok(vec![req.gen_success()]);
+            }
+            return Ok(vec![
+                req.gen_result_entry(self.config.schema.subschema_entry()),
+                req.gen_success(),
+            ]);
+        }
+
+        let monitor = DN::from_str(MONITOR)?;
+        if base.is_descendant_of(&monitor) {
+            let scope = Scope {
+                base,
+                scope: req.scope.clone(),
+            };
+
+            let filter = match Filter::try_from(&req.filter) {
+                Ok(filter) => filter,
+                Err(err) => return Ok(vec![req.gen_error(LdapResultCode::InvalidAttributeSyntax, err.to_string())])
+            };
+
+            let selection = Selection::parse(&req.attrs, types_only, &self.config.schema);
+
+            // Counters are subject to the access control list like any other entry
+            let primary = self.contexts.primary();
+            let groups = self.bound_groups(primary, &*primary.database.snapshot());
+            let bound = self.bound_dn();
+
+            return Ok(MonitorEntry::collect(&self.control).into_iter()
+                .filter(|entity| scope.matches(entity))
+                .filter(|entity| self.config.acl.access(&entity.dn(), bound.as_ref(), &groups) >= Access::Read)
+                .filter(|entity| filter.evaluate(entity, &self.config.schema, &|_| true))
+                .map(|entity| req.gen_result_entry(result_entry(entity, &selection, &|_| true)))
+                .chain(std::iter::once(req.gen_success()))
+                .collect());
+        }
+
+        // An alias as search base is replaced by the aliased entry if dereferenced when finding the base
+        let mut base = base;
+        if matches!(deref, LdapDerefAliases::FindingBaseObj | LdapDerefAliases::Always) {
+            let mut hops = 0;
+            while let Some(target) = self.alias_target(&base).await {
+                hops += 1;
+                if hops > MAX_DEREF_HOPS {
+                    return Ok(vec![req.gen_error(LdapResultCode::AliasProblem, "Too many aliases".to_string())]);
+                }
+                base = target;
+            }
+        }
+
+        let scope = Scope {
+            base,
+            scope: req.scope.clone(),
+        };
+
+        // Bases outside of all naming contexts can not contain any entity
+        let contexts = self.contexts.iter()
+            .filter(|context| context.overlaps(&scope.base))
+            .collect::<Vec<_>>();
+        if contexts.is_empty() {
+            debug!("Search base outside of naming context: {}", scope.base);
+            return Ok(vec![match self.config.referral(&scope.base) {
+                Some(server) => LdapMsg {
+                    msgid: req.msgid,
+                    op: LdapOp::SearchResultDone(LdapResult {
+                        code: LdapResultCode::Referral,
+                        matcheddn: "".to_string(),
+                        message: "".to_string(),
+                        referral: vec![referral_url(server, &scope.base)],
+                    }),
+                    ctrl: vec![],
+                },
+                None => req.gen_error(LdapResultCode::NoSuchObject, self.messages.render(Message::OutsideNamingContext, &[("base", &req.base)])),
+            }]);
+        }
+
+        let filter = match Filter::try_from(&req.filter) {
+            Ok(filter) => filter,
+            Err(err) => return Ok(vec![req.gen_error(LdapResultCode::InvalidAttributeSyntax, err.to_string())])
+        };
+
+        let selection = Selection::parse(&req.attrs, types_only, &self.config.schema);
+
+        let mut databases = Vec::new();
+        for context in contexts {
+            let database = context.database.snapshot();
+            let groups = self.bound_groups(context, &database);
+            databases.push((context, database, groups));
+        }
+
+        // Entries not readable by the client are skipped before evaluating the filter to not disclose anything about them
+        let bound = self.bound_dn();
+        let readable = |dn: DN, groups: &[String]| self.config.acl.access(&dn, bound.as_ref(), groups) >= Access::Read;
+
+        // Requested attributes are returned even if they have no values
+        fn result_entry<E: Entity>(entity: E, selection: &Selection, released: &dyn Fn(&AttributeName) -> bool) -> LdapSearchResultEntry {
+            // Attributes without any value are only part of the entry if requested by name
+            let attributes = E::ATTRIBUTES.iter().cloned()
+                .chain(entity.extra_attributes())
+                .filter(|attribute| selection.includes(attribute))
+                .filter(|attribute| released(attribute))
+                .filter_map(|attribute| entity.raw_values(&attribute)
+                    .filter(|values| !values.is_empty() || selection.names(&attribute))
+                    .map(|values| LdapPartialAttribute {
+                        atype: attribute.to_string(),
+                        vals: match selection.types_only() {
+                            true => vec![],
+                            false => values,
+                        },
+                    }))
+                .collect();
+
+            return LdapSearchResultEntry {
+                dn: entity.dn().to_string(),
+                attributes,
+            };
+        }
+
+        // Limits requested by the client are capped by the server-side maximums
+        let time_limit = [(time_limit > 0).then(|| time_limit as u64), self.config.search_timeout].into_iter()
+            .flatten()
+            .min();
+        let size_limit = [(size_limit > 0).then(|| size_limit as usize), self.config.size_limit].into_iter()
+            .flatten()
+            .min();
+
+        let deadline = time_limit
+            .map(|timeout| Instant::now() + Duration::from_secs(timeout));
+        let expired = Cell::new(false);
+        let within_deadline = || {
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                expired.set(true);
+            }
+            return !expired.get() && self.interruption(req.msgid).is_none();
+        };
+
+        let deref_in_searching = matches!(deref, LdapDerefAliases::InSearching | LdapDerefAliases::Always);
+
+        let selection = &selection;
+        let filter = &filter;
+        let scope = &scope;
+        let results = databases.iter().flat_map(|(context, database, bound_groups)| {
+            let released = move |attribute: &AttributeName| self.is_released(attribute, bound_groups);
+
+            // Search for the base and the containers holding users and groups
+            let containers = ContainerEntry::collect(&context.base_dn, &context.layout, database).into_iter()
+                .filter(|entity| scope.matches(entity))
+                .filter(|entity| readable(entity.dn(), bound_groups))
+                .filter(move |entity| filter.evaluate(entity, &self.config.schema, &released))
+                .map(move |entity| result_entry(entity, selection, &released))
+                .map(|entry| req.gen_result_entry(entry));
+
+            // Search for users
+            let users = database.users()
+                .filter(|user| self.is_visible(user))
+                .map(|user| user.with_base_dn(&context.base_dn, &context.layout))
+                .map(|user| self.config.mapping.users.apply(self.config.active_directory.user(&context.base_dn, user)))
+                .take_while(|_| within_deadline())
+                .filter(|entity| scope.matches(entity))
+                .filter(|entity| readable(entity.dn(), bound_groups))
+                .filter(move |user| filter.evaluate(user, &self.config.schema, &released))
+                .map(move |entity| result_entry(entity, selection, &released))
+                .map(|entry| req.gen_result_entry(entry));
+
+            // Search for groups
+            let groups = database.groups()
+                .map(|group| group.with_base_dn(&context.base_dn, &context.layout).with_posix(self.config.posix_groups))
+                .map(|group| self.config.mapping.groups.apply(self.config.active_directory.group(&context.base_dn, group)))
+                .take_while(|_| within_deadline())
+                .filter(|entity| scope.matches(entity))
+                .filter(|entity| readable(entity.dn(), bound_groups))
+                .filter(move |group| filter.evaluate(group, &self.config.schema, &released))
+                .map(move |entity| result_entry(entity, selection, &released))
+                .map(|entry| req.gen_result_entry(entry));
+
+            // Search for services
+            let services = database.services()
+                .map(|service| self.config.mapping.services.apply(service.with_base_dn(&context.base_dn, &context.layout)))
+                .take_while(|_| within_deadline())
+                .filter(|entity| scope.matches(entity))
+                .filter(|entity| readable(entity.dn(), bound_groups))
+                .filter(move |service| filter.evaluate(service, &self.config.schema, &released))
+                .map(move |entity| result_entry(entity, selection, &released))
+                .map(|entry| req.gen_result_entry(entry));
+
+            // Search for aliases which are replaced by the aliased entry if dereferenced while searching
+            let aliases = database.aliases()
+                .filter_map(|alias| AliasEntry::resolve(alias, &context.base_dn))
+                .take_while(|_| within_deadline())
+                .filter(|entity| scope.matches(entity))
+                .filter(|entity| readable(entity.dn(), bound_groups))
+                .filter_map(move |alias| {
+                    // The base itself is only dereferenced when finding the base
+                    if !deref_in_searching || alias.dn() == scope.base {
+                        return filter.evaluate(&alias, &self.config.schema, &released)
+                            .then(|| result_entry(alias, selection, &released));
+                    }
+
+                    if !readable(alias.target.clone(), bound_groups) {
+                        return None;
+                    }
+
+                    let user = context.user(database, &alias.target)
+                        .filter(|user| self.is_visible(user))
+                        .map(|user| self.config.mapping.users.apply(self.config.active_directory.user(&context.base_dn, user)));
+                    if let Some(user) = user {
+                        return filter.evaluate(&user, &self.config.schema, &released)
+                            .then(|| result_entry(user, selection, &released));
+                    }
+
+                    let group = database.groups()
+                        .map(|group| group.with_base_dn(&context.base_dn, &context.layout).with_posix(self.config.posix_groups))
+                        .find(|group| group.dn() == alias.target)
+                        .map(|group| self.config.mapping.groups.apply(self.config.active_directory.group(&context.base_dn, group)));
+                    if let Some(group) = group {
+                        return filter.evaluate(&group, &self.config.schema, &released)
+                            .then(|| result_entry(group, selection, &released));
+                    }
+
+                    return None;
+                })
+                .map(|entry| req.gen_result_entry(entry));
+
+            return containers.chain(users).chain(groups).chain(services).chain(aliases);
+        });
+
+        // Entries reachable by multiple aliases are returned only once
+        let mut seen = HashSet::new();
+        let results = results.filter(|result| match &result.op {
+            LdapOp::SearchResultEntry(entry) => seen.insert(entry.dn.clone()),
+            _ => true,
+        });
+
+        // One more entry than permitted is collected to detect exceeding the size limit
+        let mut results = results
+            .take(size_limit.map_or(usize::MAX, |size_limit| size_limit + 1))
+            .collect::<Vec<_>>();
+
+        if let Some(size_limit) = size_limit.filter(|size_limit| results.len() > *size_limit) {
+            debug!("Search exceeded size limit");
+            results.truncate(size_limit);
+            results.push(req.gen_error(LdapResultCode::SizeLimitExceeded, self.messages.render(Message::SizeLimitExceeded, &[])));
+            return Ok(results);
+        }
+
+        if expired.get() {
+            debug!("Search exceeded time limit");
+            results.push(req.gen_error(LdapResultCode::TimeLimitExceeded, self.messages.render(Message::TimeLimitExceeded, &[])));
+            return Ok(results);
+        }
+
+        results.push(req.gen_success());
+
+        return Ok(results);
+    }
+
+    /// Returns the entries found by the search whenever they change until the operation is abandoned or canceled
+    pub async fn do_persistent_search(&self, req: SearchRequest, types_only: bool, deref: LdapDerefAliases, persistent: PersistentSearch) -> Result<Vec<LdapMsg>> {
+        let msgid = req.msgid;
+        let mut changes = Vec::new();
+        for context in self.contexts.iter() {
+            changes.push(context.database.subscribe());
+        }
+
+        let mut entries = HashMap::<String, LdapSearchResultEntry>::new();
+        let mut initial = true;
+
+        loop {
+            let mut current = HashMap::new();
+            for result in self.do_search(req.clone(), 0, 0, types_only, deref.clone()).await? {
+                match result.op {
+                    LdapOp::SearchResultEntry(entry) => {
+                        current.insert(entry.dn.clone(), entry);
+                    }
+
+                    // Searches failing with an error end the persistent search
+                    LdapOp::SearchResultDone(ref res) if res.code != LdapResultCode::Success => return Ok(vec![result]),
+
+                    _ => {}
+                }
+            }
+
+            if self.interruption(msgid).is_some() {
+                return Ok(vec![]);
+            }
+
+            let mut changed = Vec::new();
+            if initial {
+                if !persistent.changes_only {
+                    changed.extend(current.values().map(|entry| (entry.clone(), None)));
+                }
+            } else {
+                for (dn, entry) in current.iter() {
+                    let change = match entries.get(dn) {
+                        None => ChangeType::Add,
+                        Some(previous) if previous != entry => ChangeType::Modify,
+                        Some(_) => continue,
+                    };
+                    changed.push((entry.clone(), Some(change)));
+                }
+
+                changed.extend(entries.iter()
+                    .filter(|(dn, _)| !current.contains_key(*dn))
+                    .map(|(_, entry)| (entry.clone(), Some(ChangeType::Delete))));
+            }
+
+            for (entry, change) in changed {
+                if change.map_or(false, |change| !persistent.includes(change)) {
+                    continue;
+                }
+
+                if self.entries.send((msgid, persistent.entry(msgid, entry, change))).await.is_err() {
+                    return Ok(vec![]);
+                }
+            }
+
+            entries = current;
+            initial = false;
+
+            // Waiting for changes is interrupted regularly to notice abandon and cancel requests
+            loop {
+                let changed = tokio::select! {
+                    (res, _, _) = futures::future::select_all(changes.iter_mut().map(|changes| Box::pin(changes.changed()))) => res.is_ok(),
+                    _ = tokio::time::sleep(INTERRUPTION_POLL_INTERVAL) => false,
+                };
+
+                if self.interruption(msgid).is_some() {
+                    return Ok(vec![]);
+                }
+
+                if changed {
+                    break;
+                }
+            }
+        }
+    }
+
+    pub async fn do_bind(&self, req: SimpleBindRequest) -> Result<Vec<LdapMsg>> {
+        debug!("Bind Request for {:?}", req.dn);
+
+        if self.control.maintenance() {
+            debug!("Refusing bind in maintenance mode");
+            return Ok(vec![req.gen_error(LdapResultCode::Unavailable, self.messages.render(Message::Maintenance, &[]))]);
+        }
+
+        if req.dn.is_empty() {
+            if self.config.anonymous == AnonymousAccess::Deny {
+                debug!("Refusing anonymous bind");
+                return Ok(vec![req.gen_error(LdapResultCode::UnwillingToPerform, "Anonymous binds are not permitted".to_string())]);
+            }
+
+            debug!("Anonymous bind");
+            self.set_binding(Binding::Anonymous);
+            return Ok(vec![req.gen_success()]);
+        }
+
+        let mut databases = Vec::new();
+        for context in self.contexts.iter() {
+            databases.push((context, context.database.snapshot()));
+        }
+
+        let bind_dn = match DN::parse_limited(&req.dn, self.config.strict_dn) {
+            Ok(bind_dn) => {
+                trace!("Parsed User DN: {:?}", bind_dn);
+                Some(bind_dn)
+            }
+            Err(err) if self.config.bind_lookup.is_empty() => return Ok(vec![req.gen_error(LdapResultCode::InvalidDNSyntax, err.to_string())]),
+            Err(_) => None,
+        };
+
+        // Services bind by their DN only and are not subject to the bind group
+        let service = bind_dn.as_ref().and_then(|bind_dn| databases.iter()
+            .find_map(|(context, database)| context.service(database, bind_dn)));
+        if let Some(service) = service {
+            if !service.verify_password(req.pw.as_bytes()) {
+                debug!("Password mismatch");
+                self.control.counters().increment(monitor::BINDS, Some(("result", "failure")));
+                return Ok(vec![req.gen_error(LdapResultCode::InvalidCredentials, self.messages.render(Message::InvalidCredentials, &[]))]);
+            }
+
+            if self.sessions_exhausted(&service.dn()) {
+                debug!("Too many sessions bound as {}", service.dn());
+                return Ok(vec![req.gen_error(LdapResultCode::Busy, "Too many sessions bound as this user".to_string())]);
+            }
+
+            self.control.counters().increment(monitor::BINDS, Some(("result", "success")));
+            self.set_binding(Binding::Bound(service.dn()));
+            return Ok(vec![req.gen_success()]);
+        }
+
+        let user = bind_dn.and_then(|bind_dn| databases.iter()
+            .find_map(|(context, database)| context.user(database, &bind_dn)));
+
+        // Clients unable to construct a DN may bind using the name or mail address of the user
+        let user = user.or_else(|| self.config.bind_lookup.iter()
+            .find_map(|lookup| databases.iter()
+                .find_map(|(context, database)| match lookup {
+                    BindLookup::Name => database.user(&req.dn),
+                    BindLookup::Mail => database.user_by_mail(&req.dn),
+                }.map(|user| user.with_base_dn(&context.base_dn, &context.layout)))));
+        let user = if let Some(user) = user { user } else {
+            debug!("No user found");
+            self.control.counters().increment(monitor::BINDS, Some(("result", "failure")));
+            return Ok(vec![req.gen_error(LdapResultCode::InvalidCredentials, self.messages.render(Message::InvalidCredentials, &[]))]);
+        };
+
+        let verified = match self.config.totp {
+            true => user.verify_password_with_otp(req.pw.as_bytes()).await,
+            false => user.verify_password(req.pw.as_bytes()).await,
+        };
+
+        if user.canary {
+            info!(target: CANARY, "Bind for canary {} from {}: {}", user.name, self.peer, if verified { "success" } else { "failure" });
+        }
+
+        if !verified {
+            debug!("Password mismatch");
+            monitor::count_bind(self.control.counters(), user.canary, "failure");
+            return Ok(vec![req.gen_error(LdapResultCode::InvalidCredentials, self.messages.render(Message::InvalidCredentials, &[]))]);
+        }
+
+        if let Some(suspension) = self.suspension(&user) {
+            debug!("User {} is suspended", user.name);
+            monitor::count_bind(self.control.counters(), user.canary, "failure");
+            return Ok(vec![req.gen_error(LdapResultCode::UnwillingToPerform, suspension)]);
+        }
+
+        if !self.may_bind(&user.groups) {
+            debug!("User {} not permitted to bind", user.name);
+            return Ok(vec![req.gen_error(LdapResultCode::InsufficentAccessRights, self.messages.render(Message::BindNotPermitted, &[("user", &user.name)]))]);
+        }
+
+        if self.sessions_exhausted(&user.dn()) {
+            debug!("Too many sessions bound as {}", user.dn());
+            return Ok(vec![req.gen_error(LdapResultCode::Busy, "Too many sessions bound as this user".to_string())]);
+        }
+
+        monitor::count_bind(self.control.counters(), user.canary, "success");
+        self.set_binding(Binding::Bound(user.dn()));
+
+        if let Some(context) = self.contexts.find(&user.dn()) {
+            let password = match self.config.totp {
+                true => user.strip_otp(req.pw.as_bytes()),
+                false => req.pw.as_bytes(),
+            };
+            context.database.upgrade_password(self.actor(), user.name, user.password, password);
+        }
+
+        return Ok(vec![req.gen_success()]);
+    }
+
+    pub async fn do_sasl_bind(&self, msgid: i32, sasl: SaslCredentials) -> Result<Vec<LdapMsg>> {
+        debug!("SASL Bind Request using {}", sasl.mechanism);
+
+        if self.control.maintenance() {
+            debug!("Refusing bind in maintenance mode");
+            return Ok(vec![bind_response(msgid, LdapResultCode::Unavailable, &self.messages.render(Message::Maintenance, &[]))]);
+        }
+
+        if sasl.mechanism != "EXTERNAL" {
+            return Ok(vec![bind_response(msgid, LdapResultCode::AuthMethodNotSupported, "Only EXTERNAL is supported")]);
+        }
+
+        let name = if let Some(name) = self.certificate.as_ref().and_then(tls::subject_cn) { name } else {
+            debug!("No client certificate");
+            return Ok(vec![bind_response(msgid, LdapResultCode::InappropriateAuthentication, "No client certificate")]);
+        };
+
+        let context = self.contexts.primary();
+        let database = context.database.snapshot();
+
+        // The certificate subject CN is mapped to the user with the same name
+        let user = database.user(&name)
+            .map(|user| user.with_base_dn(&context.base_dn, &context.layout));
+        let user = if let Some(user) = user { user } else {
+            debug!("No user found for certificate subject {}", name);
+            self.control.counters().increment(monitor::BINDS, Some(("result", "failure")));
+            return Ok(vec![bind_response(msgid, LdapResultCode::InvalidCredentials, &self.messages.render(Message::InvalidCredentials, &[]))]);
+        };
+
+        let user_dn = user.dn();
+
+        // An explicit authorization identity must match the identity of the certificate
+        let authzid = String::from_utf8_lossy(&sasl.credentials);
+        if !authzid.is_empty() && authzid != format!("dn:{}", user_dn) {
+            debug!("Authorization identity {} does not match {}", authzid, user_dn);
+            monitor::count_bind(self.control.counters(), user.canary, "failure");
+            return Ok(vec![bind_response(msgid, LdapResultCode::InvalidCredentials, &self.messages.render(Message::InvalidCredentials, &[]))]);
+        }
+
+        if user.canary {
+            info!(target: CANARY, "SASL EXTERNAL bind for canary {} from {}", user.name, self.peer);
+        }
+
+        if let Some(suspension) = self.suspension(&user) {
+            debug!("User {} is suspended", user.name);
+            monitor::count_bind(self.control.counters(), user.canary, "failure");
+            return Ok(vec![bind_response(msgid, LdapResultCode::UnwillingToPerform, &suspension)]);
+        }
+
+        if !self.may_bind(&user.groups) {
+            debug!("User {} not permitted to bind", user.name);
+            return Ok(vec![bind_response(msgid, LdapResultCode::InsufficentAccessRights, &self.messages.render(Message::BindNotPermitted, &[("user", &user.name)]))]);
+        }
+
+        if self.sessions_exhausted(&user_dn) {
+            debug!("Too many sessions bound as {}", user_dn);
+            return Ok(vec![bind_response(msgid, LdapResultCode::Busy, "Too many sessions bound as this user")]);
+        }
+
+        monitor::count_bind(self.control.counters(), user.canary, "success");
+        self.set_binding(Binding::Bound(user_dn));
+        return Ok(vec![bind_response(msgid, LdapResultCode::Success, "")]);
+    }
+
+    pub async fn do_compare(&self, req: CompareRequest) -> Result<Vec<LdapMsg>> {
+        if !self.may_read() {
+            debug!("Refusing compare without bind");
+            return Ok(vec![req.gen_error(LdapResultCode::InsufficentAccessRights, "Bind required".to_string())]);
+        }
+
+        let dn = match DN::parse_limited(&req.entry, self.config.strict_dn) {
+            Ok(dn) => dn,
+            Err(err) => return Ok(vec![req.gen_error(LdapResultCode::InvalidDNSyntax, err.to_string())]),
+        };
+
+        let context = match self.contexts.find(&dn) {
+            Some(context) => context,
+            None => return Ok(vec![req.gen_error(LdapResultCode::NoSuchObject, "".to_string())]),
+        };
+        let database = context.database.snapshot();
+
+        let attribute = match AttributeName::from_str(&req.atype) {
+            Ok(attribute) => self.config.schema.resolve(&attribute),
+            Err(err) => return Ok(vec![req.gen_error(LdapResultCode::UndefinedAttributeType, err.to_string())]),
+        };
+
+        // Passwords are only verified by binds, which enforce suspensions, one-time passwords and canary logging
+        if attribute == ATTR_USER_PASSWORD {
+            return Ok(vec![req.gen_error(LdapResultCode::UnwillingToPerform, "Passwords can only be verified by binding".to_string())]);
+        }
+
+        if self.access(context, &database, &dn) < Access::Read {
+            return Ok(vec![req.gen_error(LdapResultCode::InsufficentAccessRights, "".to_string())]);
+        }
+
+        let groups = self.bound_groups(context, &database);
+        if !self.is_released(&attribute, &groups) {
+            return Ok(vec![req.gen_error(LdapResultCode::NoSuchAttribute, "".to_string())]);
+        }
+
+        fn compare<E: Entity>(entity: &E, attribute: &AttributeName, rule: MatchingRule, value: &str) -> Option<bool> {
+            return entity.values(attribute)
+                .map(|values| values.iter().any(|v| rule.matches(v, value)));
+        }
+
+        let rule = self.config.schema.matching_rule(&attribute);
+
+        let user = context.user(&database, &dn);
+
+        let group = database.groups()
+            .map(|group| group.with_base_dn(&context.base_dn, &context.layout).with_posix(self.config.posix_groups))
+            .find(|group| group.dn() == dn);
+
+        let result = match (user, group) {
+            (Some(user), _) => compare(&self.config.mapping.users.apply(self.config.active_directory.user(&context.base_dn, user)), &attribute, rule, &req.val),
+            (None, Some(group)) => compare(&self.config.mapping.groups.apply(self.config.active_directory.group(&context.base_dn, group)), &attribute, rule, &req.val),
+            (None, None) => return Ok(vec![req.gen_error(LdapResultCode::NoSuchObject, "".to_string())]),
+        };
+
+        return Ok(vec![match result {
+            Some(true) => req.gen_compare_true(),
+            Some(false) => req.gen_compare_false(),
+            None => req.gen_error(LdapResultCode::NoSuchAttribute, "".to_string()),
+        }]);
+    }
+
+    pub async fn do_add(&self, msgid: i32, req: LdapAddRequest) -> Result<Vec<LdapMsg>> {
+        let respond = |code, message: &str| Ok(vec![response(msgid, LdapOp::AddResponse, code, message)]);
+
+        if self.control.maintenance() {
+            debug!("Refusing write in maintenance mode");
+            return respond(LdapResultCode::Unavailable, &self.messages.render(Message::Maintenance, &[]));
+        }
+
+        let dn = match DN::parse_limited(&req.dn, self.config.strict_dn) {
+            Ok(dn) => dn,
+            Err(err) => return respond(LdapResultCode::InvalidDNSyntax, &err.to_string()),
+        };
+
+        let context = match self.contexts.find(&dn) {
+            Some(context) => context,
+            None => return respond(LdapResultCode::NoSuchObject, ""),
+        };
+
+        if context.database.is_read_only() {
+            return respond(LdapResultCode::UnwillingToPerform, "Directory is a read-only mirror");
+        }
+
+        let mut database = context.database.write().await;
+
+        if !self.may_write(context, &database) {
+            return respond(LdapResultCode::InsufficentAccessRights, "");
+        }
+
+        if self.access(context, &database, &dn) < Access::Write {
+            return respond(LdapResultCode::InsufficentAccessRights, "");
+        }
+
+        let mut attributes = Vec::new();
+        for attribute in req.attributes {
+            let name = match AttributeName::from_str(&attribute.atype) {
+                Ok(name) => name,
+                Err(err) => return respond(LdapResultCode::UndefinedAttributeType, &err.to_string()),
+            };
+
+            let values = match attribute.vals.into_iter().map(String::from_utf8).collect::<Result<Vec<_>, _>>() {
+                Ok(values) => values,
+                Err(err) => return respond(LdapResultCode::InvalidAttributeSyntax, &err.to_string()),
+            };
+
+            attributes.push((name, values));
+        }
+
+        let values = |name: &AttributeName| attributes.iter()
+            .filter(|(attribute, _)| attribute == name)
+            .flat_map(|(_, values)| values.iter().cloned())
+            .collect::<Vec<_>>();
+        let single = |name: &AttributeName| match values(name).as_slice() {
+            [value] => Ok(value.clone()),
+            _ => Err(format!("Exactly one value required: {}", name)),
+        };
+
+        let names = |dns: Vec<String>, container: &str| self.entry_names(context, &database, &dns, container);
+
+        // Object classes and naming attributes are derived, while other attributes would get lost if not modeled
+        let unsupported = |supported: &[AttributeName]| attributes.iter()
+            .map(|(attribute, _)| attribute)
+            .filter(|attribute| ![ATTR_OBJECT_CLASS, ATTR_CN, ATTR_UID].contains(attribute))
+            .filter(|attribute| !dn.iter().next().map_or(false, |rdn| rdn.iter().any(|naming| naming.name() == *attribute)))
+            .find(|attribute| !supported.contains(attribute))
+            .cloned();
+
+        if let Some((units, name)) = context.layout.pattern("users").nested_name(&context.base_dn, &dn) {
+            if !is_valid_name(&name) {
+                return respond(LdapResultCode::NamingViolation, "Invalid user name");
+            }
+
+            if !units.iter().all(|unit| is_valid_name(unit)) {
+                return respond(LdapResultCode::NamingViolation, "Invalid organizational unit");
+            }
+
+            if database.user(&name).is_some() {
+                return respond(LdapResultCode::EntryAlreadyExists, "");
+            }
+
+            if let Some(attribute) = unsupported(&[ATTR_USER_PASSWORD, ATTR_GIVEN_NAME, ATTR_SN, ATTR_MAIL, ATTR_MAIL_ALIAS, ATTR_MEMBER_OF]) {
+                return respond(LdapResultCode::UnwillingToPerform, &format!("Attribute can not be added: {}", attribute));
+            }
+
+            let password = match single(&ATTR_USER_PASSWORD) {
+                Ok(value) => match parse_password(&database, &value) {
+                    Ok(password) => password,
+                    Err(err) => return respond(LdapResultCode::InvalidAttributeSyntax, &err),
+                },
+                Err(err) => return respond(LdapResultCode::ObjectClassViolation, &err),
+            };
+
+            let user = (|| {
+                return Ok::<_, String>(UserEntity {
+                    password: password.into(),
+                    first_name: single(&ATTR_GIVEN_NAME)?,
+                    last_name: single(&ATTR_SN)?,
+                    mail: Some(values(&ATTR_MAIL))
+                        .filter(|mail| !mail.is_empty())
+                        .ok_or_else(|| format!("At least one value required: {}", ATTR_MAIL))?,
+                    mail_aliases: values(&ATTR_MAIL_ALIAS),
+                    groups: names(values(&ATTR_MEMBER_OF), "groups")?,
+                    canary: false,
+                    posix: None,
+                    ssh_keys: vec![],
+                    password_changed: None,
+                    password_max_age: None,
+                    expires: None,
+                    disabled: false,
+                    locked_until: None,
+                    not_before: None,
+                    not_after: None,
+                    totp_secret: None,
+                    eap_password: None,
+                    verifier: None,
+                    attributes: Default::default(),
+                    binary_attributes: Default::default(),
+                    uuid: None,
+                });
+            })();
+
+            let user = match user {
+                Ok(user) => user,
+                Err(err) => return respond(LdapResultCode::ObjectClassViolation, &err),
+            };
+
+            info!("Adding user {} by {}", name, self.peer);
+            database.add_user(&self.actor(), units, name, user).await?;
+
+            return respond(LdapResultCode::Success, "");
+        }
+
+        if let Some(name) = self.entry_name(context, &database, &dn, "groups") {
+            if database.groups().any(|group| group.name == name) {
+                return respond(LdapResultCode::EntryAlreadyExists, "");
+            }
+
+            if let Some(attribute) = unsupported(&[ATTR_UNIQUE_MEMBER, ATTR_UNIQUE_MEMBERS]) {
+                return respond(LdapResultCode::UnwillingToPerform, &format!("Attribute can not be added: {}", attribute));
+            }
+
+            let mut members = values(&ATTR_UNIQUE_MEMBER);
+            members.extend(values(&ATTR_UNIQUE_MEMBERS));
+
+            // Groups only exist by having members
+            let members = match names(members, "users") {
+                Ok(members) if !members.is_empty() => members,
+                Ok(_) => return respond(LdapResultCode::ObjectClassViolation, "Groups require at least one member"),
+                Err(err) => return respond(LdapResultCode::ObjectClassViolation, &err),
+            };
+
+            if let Some(member) = members.iter().find(|member| database.user(member).is_none()) {
+                return respond(LdapResultCode::ConstraintViolation, &format!("No such user: {}", member));
+            }
+
+            info!("Adding group {} by {}", name, self.peer);
+            database.add_group_members(&self.actor(), &name, &members).await?;
+
+            return respond(LdapResultCode::Success, "");
+        }
+
+        return respond(LdapResultCode::UnwillingToPerform, "Entries can only be added below ou=users or ou=groups");
+    }
+
+    pub async fn do_modify(&self, msgid: i32, req: LdapModifyRequest) -> Result<Vec<LdapMsg>> {
+        let respond = |code, message: &str| Ok(vec![response(msgid, LdapOp::ModifyResponse, code, message)]);
+
+        if self.control.maintenance() {
+            debug!("Refusing write in maintenance mode");
+            return respond(LdapResultCode::Unavailable, &self.messages.render(Message::Maintenance, &[]));
+        }
+
+        let dn = match DN::parse_limited(&req.dn, self.config.strict_dn) {
+            Ok(dn) => dn,
+            Err(err) => return respond(LdapResultCode::InvalidDNSyntax, &err.to_string()),
+        };
+
+        let context = match self.contexts.find(&dn) {
+            Some(context) => context,
+            None => return respond(LdapResultCode::NoSuchObject, ""),
+        };
+
+        if context.database.is_read_only() {
+            return respond(LdapResultCode::UnwillingToPerform, "Directory is a read-only mirror");
+        }
+
+        let mut database = context.database.write().await;
+
+        if self.access(context, &database, &dn) < Access::Write {
+            return respond(LdapResultCode::InsufficentAccessRights, "");
+        }
+
+        if let Some(group) = self.entry_name(context, &database, &dn, "groups") {
+            return self.do_modify_group(msgid, context, &mut database, group, req.changes).await;
+        }
+
+        if !self.may_write(context, &database) {
+            return respond(LdapResultCode::InsufficentAccessRights, "");
+        }
+
+        let name = match self.entry_name(context, &database, &dn, "users") {
+            Some(name) => name,
+            None => return respond(LdapResultCode::UnwillingToPerform, "Only users and groups can be modified"),
+        };
+
+        let mut user = match database.user_entity(&name) {
+            Some(user) => user.clone(),
+            None => return respond(LdapResultCode::NoSuchObject, ""),
+        };
+
+        // All changes are applied to a copy which is only persisted if every change succeeds
+        for change in req.changes {
+            let attribute = match AttributeName::from_str(&change.modification.atype) {
+                Ok(attribute) => attribute,
+                Err(err) => return respond(LdapResultCode::UndefinedAttributeType, &err.to_string()),
+            };
+
+            let values = match change.modification.vals.into_iter().map(String::from_utf8).collect::<Result<Vec<_>, _>>() {
+                Ok(values) => values,
+                Err(err) => return respond(LdapResultCode::InvalidAttributeSyntax, &err.to_string()),
+            };
+
+            let single = match attribute {
+                ref attribute if attribute == &ATTR_GIVEN_NAME => Some(&mut user.first_name),
+                ref attribute if attribute == &ATTR_SN => Some(&mut user.last_name),
+                _ => None,
+            };
+
+            if let Some(field) = single {
+                match (change.operation, values.as_slice()) {
+                    (LdapModifyType::Replace, [value]) => *field = value.clone(),
+                    (LdapModifyType::Add, _) => return respond(LdapResultCode::ConstraintViolation, &format!("Single-valued attribute: {}", attribute)),
+                    (LdapModifyType::Delete, [value]) if value != field => return respond(LdapResultCode::NoSuchAttribute, ""),
+                    _ => return respond(LdapResultCode::ObjectClassViolation, &format!("Exactly one value required: {}", attribute)),
+                }
+                continue;
+            }
+
+            let multiple = match attribute {
+                ref attribute if attribute == &ATTR_MAIL => Some(&mut user.mail),
+                ref attribute if attribute == &ATTR_MAIL_ALIAS => Some(&mut user.mail_aliases),
+                _ => None,
+            };
+
+            if let Some(field) = multiple {
+                match change.operation {
+                    LdapModifyType::Add => field.extend(values),
+                    LdapModifyType::Replace => *field = values,
+                    LdapModifyType::Delete if values.is_empty() => field.clear(),
+                    LdapModifyType::Delete => {
+                        if let Some(value) = values.iter().find(|value| !field.iter().any(|v| v.eq_ignore_ascii_case(value))) {
+                            return respond(LdapResultCode::NoSuchAttribute, &format!("No such value: {}", value));
+                        }
+                        field.retain(|v| !values.iter().any(|value| v.eq_ignore_ascii_case(value)));
+                    }
+                }
+                continue;
+            }
+
+            if attribute == ATTR_MEMBER_OF {
+                let groups = match self.entry_names(context, &database, &values, "groups") {
+                    Ok(groups) => groups,
+                    Err(err) => return respond(LdapResultCode::InvalidAttributeSyntax, &err),
+                };
+
+                match change.operation {
+                    LdapModifyType::Add => user.groups.extend(groups),
+                    LdapModifyType::Replace => user.groups = groups,
+                    LdapModifyType::Delete if groups.is_empty() => user.groups.clear(),
+                    LdapModifyType::Delete => {
+                        if let Some(group) = groups.iter().find(|group| !user.groups.contains(group)) {
+                            return respond(LdapResultCode::NoSuchAttribute, &format!("Not a member of {}", group));
+                        }
+                        user.groups.retain(|group| !groups.contains(group));
+                    }
+                }
+                continue;
+            }
+
+            if attribute == ATTR_USER_PASSWORD {
+                if user.password.secret.is_some() {
+                    return respond(LdapResultCode::UnwillingToPerform, "Password is managed by a secret");
+                }
+
+                user.password = match (change.operation, values.as_slice()) {
+                    (LdapModifyType::Replace, [value]) => match parse_password(&database, value) {
+                        Ok(password) => password.into(),
+                        Err(err) => return respond(LdapResultCode::InvalidAttributeSyntax, &err),
+                    },
+                    _ => return respond(LdapResultCode::UnwillingToPerform, "Passwords can only be replaced"),
+                };
+                continue;
+            }
+
+            return respond(LdapResultCode::UnwillingToPerform, &format!("Attribute can not be modified: {}", attribute));
+        }
+
+        info!("Modifying user {} by {}", name, self.peer);
+        database.update_user(&self.actor(), &name, user).await?;
+
+        return respond(LdapResultCode::Success, "");
+    }
+
+    /// Changes the members of a group which is permitted for writers and managers of the group
+    async fn do_modify_group(&self, msgid: i32, context: &NamingContext, database: &mut Database, group: String, changes: Vec<LdapModify>) -> Result<Vec<LdapMsg>> {
+        let respond = |code, message: &str| Ok(vec![response(msgid, LdapOp::ModifyResponse, code, message)]);
+
+        if !self.may_manage(context, database, &group) {
+            return respond(LdapResultCode::InsufficentAccessRights, "");
+        }
+
+        let mut members = database.groups()
+            .find(|g| g.name == group)
+            .map(|g| g.members().map(|user| user.name.to_owned()).collect::<Vec<_>>())
+            .unwrap_or_default();
 
-    config: Arc<Config>,
-    database: Arc<RwLock<Database>>,
+        // Changes are validated against the resulting member list before any user is persisted
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
 
-    binding: Binding,
-}
+        for change in changes {
+            let attribute = match AttributeName::from_str(&change.modification.atype) {
+                Ok(attribute) => attribute,
+                Err(err) => return respond(LdapResultCode::UndefinedAttributeType, &err.to_string()),
+            };
 
-impl Session {
-    pub async fn do_search(&mut self, req: SearchRequest) -> Result<Vec<LdapMsg>> {
-        let database = self.database.read().await;
+            if attribute != ATTR_UNIQUE_MEMBER && attribute != ATTR_UNIQUE_MEMBERS {
+                return respond(LdapResultCode::UnwillingToPerform, &format!("Attribute can not be modified: {}", attribute));
+            }
 
-        // todo!("Process attrs");
-        // todo!("Requested attrs must be present - even if empty");
-        // TODO: Move error response handling to outer callee
+            let values = match change.modification.vals.into_iter().map(String::from_utf8).collect::<Result<Vec<_>, _>>() {
+                Ok(values) => values,
+                Err(err) => return respond(LdapResultCode::InvalidAttributeSyntax, &err.to_string()),
+            };
 
-        let scope = Scope {
-            base: req.base.parse()?,
-            scope: req.scope.clone(),
+            let users = match self.entry_names(context, database, &values, "users") {
+                Ok(users) => users,
+                Err(err) => return respond(LdapResultCode::InvalidAttributeSyntax, &err),
+            };
+
+            match change.operation {
+                LdapModifyType::Add => {
+                    if let Some(user) = users.iter().find(|user| database.user(user).is_none()) {
+                        return respond(LdapResultCode::ConstraintViolation, &format!("No such user: {}", user));
+                    }
+                    for user in users {
+                        if !members.contains(&user) {
+                            removed.retain(|u| u != &user);
+                            members.push(user.clone());
+                            added.push(user);
+                        }
+                    }
+                }
+
+                LdapModifyType::Delete => {
+                    if let Some(user) = users.iter().find(|user| !members.contains(user)) {
+                        return respond(LdapResultCode::NoSuchAttribute, &format!("Not a member: {}", user));
+                    }
+                    let users = if users.is_empty() { members.clone() } else { users };
+                    for user in users {
+                        members.retain(|u| u != &user);
+                        added.retain(|u| u != &user);
+                        removed.push(user);
+                    }
+                }
+
+                LdapModifyType::Replace => return respond(LdapResultCode::UnwillingToPerform, "Members can only be added or removed"),
+            }
+        }
+
+        info!("Modifying members of group {} by {}: added {:?}, removed {:?}", group, self.peer, added, removed);
+        database.add_group_members(&self.actor(), &group, &added).await?;
+        database.remove_group_members(&self.actor(), &group, &removed).await?;
+
+        return respond(LdapResultCode::Success, "");
+    }
+
+    pub async fn do_modify_dn(&self, msgid: i32, req: LdapModifyDNRequest) -> Result<Vec<LdapMsg>> {
+        let respond = |code, message: &str| Ok(vec![response(msgid, LdapOp::ModifyDNResponse, code, message)]);
+
+        if self.control.maintenance() {
+            debug!("Refusing write in maintenance mode");
+            return respond(LdapResultCode::Unavailable, &self.messages.render(Message::Maintenance, &[]));
+        }
+
+        let dn = match DN::parse_limited(&req.dn, self.config.strict_dn) {
+            Ok(dn) => dn,
+            Err(err) => return respond(LdapResultCode::InvalidDNSyntax, &err.to_string()),
         };
 
-        let filter = match Filter::try_from(&req.filter) {
-            Ok(filter) => filter,
-            Err(err) => return Ok(vec![req.gen_error(LdapResultCode::InvalidAttributeSyntax, err.to_string())])
+        let context = match self.contexts.find(&dn) {
+            Some(context) => context,
+            None => return respond(LdapResultCode::NoSuchObject, ""),
         };
 
-        fn result_entry<E: Entity>(entity: E) -> LdapSearchResultEntry {
-            let attributes = E::ATTRIBUTES.iter()
-                .map(|attribute| E::get(attribute)
-                    .map(|getter| LdapPartialAttribute {
-                        atype: attribute.to_string(),
-                        vals: getter(&entity),
-                    })
-                    .unwrap_or_else(|| LdapPartialAttribute {
-                        atype: attribute.to_string(),
-                        vals: vec![],
-                    }))
-                .collect();
+        if context.database.is_read_only() {
+            return respond(LdapResultCode::UnwillingToPerform, "Directory is a read-only mirror");
+        }
 
-            return LdapSearchResultEntry {
-                dn: entity.dn().to_string(),
-                attributes,
-            };
+        let mut database = context.database.write().await;
+
+        if !self.may_write(context, &database) {
+            return respond(LdapResultCode::InsufficentAccessRights, "");
         }
 
-        let mut results = Vec::new();
+        if self.access(context, &database, &dn) < Access::Write {
+            return respond(LdapResultCode::InsufficentAccessRights, "");
+        }
 
-        // Search for users
-        results.extend(database.users()
-            .map(|user| user.with_base_dn(&self.config.base_dn))
-            .filter(|entity| scope.matches(entity))
-            .filter(|user| filter.evaluate(user))
-            .map(result_entry)
-            .map(|entry| req.gen_result_entry(entry)));
+        let rdn = match RDN::from_str(&req.newrdn) {
+            Ok(rdn) => rdn,
+            Err(err) => return respond(LdapResultCode::InvalidDNSyntax, &err.to_string()),
+        };
 
-        // Search for groups
-        results.extend(database.groups()
-            .map(|group| group.with_base_dn(&self.config.base_dn))
-            .filter(|entity| scope.matches(entity))
-            .filter(|group| filter.evaluate(group))
-            .map(result_entry)
-            .map(|entry| req.gen_result_entry(entry)));
+        // Entries can only be renamed in place as the container is defined by the entity type
+        if let Some(new_superior) = &req.new_superior {
+            match DN::parse_limited(new_superior, self.config.strict_dn) {
+                Ok(new_superior) if new_superior == dn.parent() => {}
+                Ok(_) => return respond(LdapResultCode::UnwillingToPerform, "Entries can not be moved"),
+                Err(err) => return respond(LdapResultCode::InvalidDNSyntax, &err.to_string()),
+            }
+        }
 
-        results.push(req.gen_success());
+        let new_dn = dn.parent().join(rdn);
 
-        return Ok(results);
-    }
+        if let Some(name) = self.entry_name(context, &database, &dn, "users") {
+            let new_name = match self.entry_name(context, &database, &new_dn, "users") {
+                Some(new_name) => new_name,
+                None => return respond(LdapResultCode::NamingViolation, "Users must be named by cn"),
+            };
 
-    pub async fn do_bind(&mut self, req: SimpleBindRequest) -> Result<Vec<LdapMsg>> {
-        debug!("Bind Request for {:?}", req.dn);
+            if !is_valid_name(&new_name) {
+                return respond(LdapResultCode::NamingViolation, "Invalid user name");
+            }
 
-        if req.dn.is_empty() {
-            debug!("Anonymous bind");
-            self.binding = Binding::Anonymous;
-            return Ok(vec![req.gen_success()]);
+            if database.user(&name).is_none() {
+                return respond(LdapResultCode::NoSuchObject, "");
+            }
+
+            if database.user(&new_name).is_some() {
+                return respond(LdapResultCode::EntryAlreadyExists, "");
+            }
+
+            info!("Renaming user {} to {} by {}", name, new_name, self.peer);
+            database.rename_user(&self.actor(), &name, new_name).await?;
+
+            // Keep the session bound to the renamed entry
+            if self.bound_dn().as_ref() == Some(&dn) {
+                self.set_binding(Binding::Bound(new_dn));
+            }
+
+            return respond(LdapResultCode::Success, "");
         }
 
-        let user_dn = DN::from_str(&req.dn)?;
-        trace!("Parsed User DN: {:?}", user_dn);
+        if let Some(name) = self.entry_name(context, &database, &dn, "groups") {
+            let new_name = match self.entry_name(context, &database, &new_dn, "groups") {
+                Some(new_name) => new_name,
+                None => return respond(LdapResultCode::NamingViolation, "Groups must be named by cn"),
+            };
 
-        let database = self.database.read().await;
+            if !is_valid_name(&new_name) {
+                return respond(LdapResultCode::NamingViolation, "Invalid group name");
+            }
 
-        let user = database.users()
-            .map(|user| user.with_base_dn(&self.config.base_dn))
-            .find(|user| user.dn() == user_dn);
-        let user = if let Some(user) = user { user } else {
-            debug!("No user found");
-            return Ok(vec![req.gen_invalid_cred()]);
-        };
+            if !database.groups().any(|group| group.name == name) {
+                return respond(LdapResultCode::NoSuchObject, "");
+            }
 
-        if !user.verify_password(req.pw.as_bytes()) {
-            debug!("Password mismatch");
-            return Ok(vec![req.gen_invalid_cred()]);
+            if database.groups().any(|group| group.name == new_name) {
+                return respond(LdapResultCode::EntryAlreadyExists, "");
+            }
+
+            info!("Renaming group {} to {} by {}", name, new_name, self.peer);
+            database.rename_group(&self.actor(), &name, &new_name).await?;
+
+            return respond(LdapResultCode::Success, "");
         }
 
-        self.binding = Binding::Bound(user_dn.clone());
-        return Ok(vec![req.gen_success()]);
+        return respond(LdapResultCode::NoSuchObject, "");
     }
 
-    pub async fn do_unbind(&mut self, req: UnbindRequest) -> Result<Vec<LdapMsg>> {
-        self.binding = Binding::Unbound;
+    pub async fn do_unbind(&self, _req: UnbindRequest) -> Result<Vec<LdapMsg>> {
+        self.set_binding(Binding::Unbound);
 
         // No need to notify on unbind (per rfc4511)
         return Ok(vec![]);
     }
 
-    pub async fn do_whoami(&mut self, req: WhoamiRequest) -> Result<Vec<LdapMsg>> {
-        return Ok(match &self.binding {
+    pub async fn do_whoami(&self, req: WhoamiRequest) -> Result<Vec<LdapMsg>> {
+        return Ok(match &*self.binding.lock().unwrap() {
             Binding::Unbound => vec![],
             Binding::Bound(dn) => vec![req.gen_success(&format!("dn: {}", dn))],
             Binding::Anonymous => vec![],
@@ -147,67 +1502,547 @@ impl Session {
     }
 }
 
-async fn serve_client(socket: TcpStream,
-                      addr: SocketAddr,
+impl Session {
+    /// Dispatches a request to the operation handlers
+    pub async fn handle(&self, request: Request) -> Result<Vec<LdapMsg>> {
+        let Request { msg: req, controls } = request;
+
+        let persistent = match controls.iter().find(|control| control.oid == psearch::OID) {
+            Some(control) if matches!(req.op, LdapOp::SearchRequest(_)) => match PersistentSearch::parse(control) {
+                Some(persistent) => Some(persistent),
+                None => return Ok(vec![response(req.msgid, LdapOp::SearchResultDone, LdapResultCode::ProtocolError, "Invalid persistent search control")]),
+            },
+            _ => None,
+        };
+
+        // Critical controls which are not understood must not be ignored (RFC 4511, section 4.1.11)
+        let unsupported = controls.iter()
+            .filter(|control| persistent.is_none() || control.oid != psearch::OID)
+            .find(|control| control.critical);
+        if let Some(control) = unsupported {
+            debug!("Unsupported critical control {}", control.oid);
+            return Ok(result_op(&req.op)
+                .map(|result_op| vec![response(req.msgid, result_op, LdapResultCode::UnavailableCriticalExtension, &format!("Unsupported control: {}", control.oid))])
+                .unwrap_or_default());
+        }
+
+        // Operations not covered by the simple server API
+        let req = match req {
+            LdapMsg { msgid, op: LdapOp::BindRequest(LdapBindRequest { cred: LdapBindCred::SASL(sasl), .. }), .. } => {
+                debug!("Got SASL bind request");
+                return self.do_sasl_bind(msgid, sasl).await;
+            }
+
+            LdapMsg { msgid, op: LdapOp::AddRequest(req), .. } => {
+                debug!("Got add request: {:?}", req.dn);
+                return self.do_add(msgid, req).await;
+            }
+
+            LdapMsg { msgid, op: LdapOp::ModifyRequest(req), .. } => {
+                debug!("Got modify request: {:?}", req.dn);
+                return self.do_modify(msgid, req).await;
+            }
+
+            LdapMsg { msgid, op: LdapOp::ModifyDNRequest(req), .. } => {
+                debug!("Got modify DN request: {:?}", req.dn);
+                return self.do_modify_dn(msgid, req).await;
+            }
+
+            req => req,
+        };
+
+        // Limits and flags are not part of the simple server API
+        let (size_limit, time_limit, types_only, deref) = match &req.op {
+            LdapOp::SearchRequest(search) => (search.sizelimit, search.timelimit, search.typesonly, search.aliases.clone()),
+            _ => (0, 0, false, LdapDerefAliases::Never),
+        };
+
+        // Unsupported requests are answered if the protocol defines a response for them
+        let (msgid, result_op) = (req.msgid, result_op(&req.op));
+
+        let req = match ServerOps::try_from(req) {
+            Ok(req) => req,
+            Err(()) => return match result_op {
+                Some(result_op) => {
+                    debug!("Got unsupported request from {}", self.peer);
+                    Ok(vec![response(msgid, result_op, LdapResultCode::ProtocolError, "Unsupported operation")])
+                }
+                None => Err(ProtocolViolation(format!("Unexpected message {}", msgid)).into()),
+            },
+        };
+
+        debug!("Got request: {:?}", req);
+        return match req {
+            ServerOps::Search(req) => match persistent {
+                Some(persistent) => self.do_persistent_search(req, types_only, deref, persistent).await,
+                None => self.do_search(req, size_limit, time_limit, types_only, deref).await,
+            },
+            ServerOps::SimpleBind(req) => self.do_bind(req).await,
+            ServerOps::Unbind(req) => self.do_unbind(req).await,
+            ServerOps::Whoami(req) => self.do_whoami(req).await,
+            ServerOps::Compare(req) => self.do_compare(req).await,
+        };
+    }
+
+    /// Checks whether the client has abandoned or canceled the operation
+    fn interruption(&self, msgid: i32) -> Option<Interruption> {
+        return self.operations.lock().unwrap()
+            .get(&msgid)
+            .copied()
+            .flatten();
+    }
+}
+
+/// Sends a pre-encoded message bypassing the codec
+async fn send_raw<W>(w: &mut FramedWrite<W, Codec>, tag: StructureTag) -> Result<()>
+    where
+        W: AsyncWrite + Unpin {
+    let decoded = w.encoder().capture().map(|_| format!("{:?}", tag));
+
+    let mut buf = BytesMut::new();
+    lber::write::encode_into(&mut buf, tag)?;
+
+    if let (Some(capture), Some(decoded)) = (w.encoder().capture(), decoded) {
+        capture.response(&buf, &format_args!("{}", decoded));
+    }
+
+    w.flush().await?;
+    w.get_mut().write_all(&buf).await?;
+    w.get_mut().flush().await?;
+
+    return Ok(());
+}
+
+/// Outcome of an operation processed independently of the connection
+struct Completion {
+    msgid: i32,
+    result_op: Option<fn(LdapResult) -> LdapOp>,
+
+    /// Responses to send or nothing if the operation has been interrupted before it was started
+    responses: Option<Vec<LdapMsg>>,
+}
+
+async fn process(session: Arc<Session>, request: Request) -> Result<Completion> {
+    let msgid = request.msg.msgid;
+    let result_op = cancel::result_op(&request.msg.op);
+
+    let responses = match session.interruption(msgid) {
+        None => Some(session.handle(request).await?),
+        Some(_) => None,
+    };
+
+    return Ok(Completion {
+        msgid,
+        result_op,
+        responses,
+    });
+}
+
+async fn serve_client(socket: impl AsyncRead + AsyncWrite,
+                      peer: Peer,
                       config: Arc<Config>,
-                      database: Arc<RwLock<Database>>) -> Result<()> {
+                      contexts: Arc<NamingContexts>,
+                      control: Arc<Control>,
+                      messages: Arc<Messages>,
+                      certificate: Option<Certificate>) -> Result<()> {
     let (r, w) = tokio::io::split(socket);
-    let mut r = FramedRead::new(r, LdapCodec);
-    let mut w = FramedWrite::new(w, LdapCodec);
+    let registration = SessionHandle::register(control.clone(), peer);
+
+    let capture = match &config.capture {
+        Some(capture) => Some(Arc::new(Capture::open(capture, peer, registration.id)?)),
+        None => None,
+    };
+
+    let mut r = FramedRead::new(r, Codec::new(capture.clone()));
+    let mut w = FramedWrite::new(w, Codec::new(capture));
+
+    // In-flight operations by message ID and whether they have been interrupted
+    let operations = Arc::new(Mutex::new(HashMap::new()));
+
+    let (entries, mut streamed) = mpsc::channel(PIPELINE_DEPTH);
 
-    let mut session = Session {
-        addr,
+    let session = Arc::new(Session {
+        id: registration.id,
+        peer,
         config,
-        database,
-        binding: Binding::Unbound,
+        contexts,
+        control,
+        messages,
+        certificate,
+        operations: operations.clone(),
+        binding: Mutex::new(Binding::Unbound),
+        entries,
+    });
+
+    session.auto_bind().await;
+
+    let (queue, mut requests) = mpsc::channel::<Request>(PIPELINE_DEPTH);
+
+    // Reading continues while operations are processed to receive abandon and cancel requests
+    let reader = async move {
+        while let Some(request) = r.next().await {
+            let request = request.map_err(|err| ProtocolViolation(err.to_string()))
+                .with_context(|| format!("Invalid request form client {}", peer))?;
+            let req = &request.msg;
+
+            if let LdapOp::AbandonRequest(msgid) = req.op {
+                debug!("Got abandon request for {}", msgid);
+                if let Some(interruption) = operations.lock().unwrap().get_mut(&msgid) {
+                    *interruption = Some(Interruption::Abandoned);
+                }
+                continue;
+            }
+
+            // The cancel request itself is answered after the targeted operation has completed
+            if let Some(target) = cancel::is_request(req).then(|| cancel::target(req)).flatten() {
+                debug!("Got cancel request for {}", target);
+                if let Some(interruption) = operations.lock().unwrap().get_mut(&target) {
+                    // Operations already abandoned stay abandoned
+                    interruption.get_or_insert(Interruption::Canceled);
+                }
+            }
+
+            // Only operations which can be abandoned or canceled are tracked
+            if cancel::result_op(&req.op).is_some() {
+                operations.lock().unwrap().insert(req.msgid, None);
+            }
+
+            if queue.send(request).await.is_err() {
+                break;
+            }
+        }
+
+        return Ok::<_, anyhow::Error>(());
     };
 
-    // TODO: Support processing multiplexed requests in parallel by spawning into a pool
-    // TODO: Send DisconnectionNotice in case of errors
+    let processor = async {
+        // Cancel requests waiting for their target operation to complete by message ID of the target
+        let mut cancels = HashMap::new();
 
-    while let Some(req) = r.next().await {
-        let req = req.with_context(|| format!("Invalid request form client {}", addr))?;
-        let req = ServerOps::try_from(req)
-            .map_err(|()| anyhow!("Failed to map server request"))
-            .with_context(|| format!("Invalid server request form client {}", addr))?;
+        let mut pending = FuturesUnordered::new();
 
-        debug!("Got request: {:?}", req);
-        let responses = match req {
-            ServerOps::Search(req) => session.do_search(req).await?,
-            ServerOps::SimpleBind(req) => session.do_bind(req).await?,
-            ServerOps::Unbind(req) => session.do_unbind(req).await?,
-            ServerOps::Whoami(req) => session.do_whoami(req).await?,
-        };
+        // Request which must not run concurrently with any other operation
+        let mut exclusive = None;
+
+        // Persistent searches in progress which only end if interrupted
+        let mut persistent = HashSet::new();
+
+        let mut exhausted = false;
+
+        loop {
+            let completion = if pending.is_empty() && exclusive.is_some() {
+                process(session.clone(), exclusive.take().unwrap()).await?
+            } else {
+                tokio::select! {
+                    // Entries are written before the completion of the operation which produced them
+                    biased;
+
+                    Some((msgid, entry)) = streamed.recv(), if !pending.is_empty() => {
+                        // Entries of interrupted operations are dropped
+                        if session.interruption(msgid).is_none() {
+                            send_raw(&mut w, entry).await?;
+                        }
+                        continue;
+                    }
+
+                    Some(completion) = pending.next() => completion??,
+
+                    request = requests.recv(), if !exhausted && exclusive.is_none() && pending.len() < MAX_CONCURRENT_OPERATIONS => {
+                        let request = match request {
+                            Some(request) => request,
+                            None => {
+                                exhausted = true;
+                                continue;
+                            }
+                        };
+                        let req = &request.msg;
+
+                        if cancel::is_request(req) {
+                            match cancel::target(req) {
+                                Some(target) if session.operations.lock().unwrap().contains_key(&target) => {
+                                    cancels.insert(target, req.msgid);
+                                }
+                                Some(_) => send_raw(&mut w, cancel::cancel_response(req.msgid, cancel::NO_SUCH_OPERATION)).await?,
+                                None => send_raw(&mut w, cancel::cancel_response(req.msgid, LdapResultCode::ProtocolError as u8)).await?,
+                            }
+                            continue;
+                        }
+
+                        // Binding changes the authorization of all operations and therefore waits for them to complete
+                        if matches!(req.op, LdapOp::BindRequest(_) | LdapOp::UnbindRequest) {
+                            // Persistent searches would never complete and are abandoned instead (RFC 4511, section 4.2.1)
+                            for msgid in persistent.iter() {
+                                if let Some(interruption) = session.operations.lock().unwrap().get_mut(msgid) {
+                                    interruption.get_or_insert(Interruption::Abandoned);
+                                }
+                            }
+
+                            exclusive = Some(request);
+                            continue;
+                        }
+
+                        if request.control(psearch::OID).is_some() {
+                            persistent.insert(req.msgid);
+                        }
+
+                        pending.push(tokio::spawn(process(session.clone(), request)));
+                        continue;
+                    }
+
+                    else => break,
+                }
+            };
+
+            let Completion { msgid, result_op, responses } = completion;
+            persistent.remove(&msgid);
+
+            let mut completed = false;
+            if let Some(responses) = responses {
+                registration.operation();
+                registration.bound(session.bound_dn().map(|dn| dn.normalize()));
+
+                completed = true;
+                for response in responses {
+                    // Interrupted operations are not responded to any further
+                    if session.interruption(msgid).is_some() {
+                        completed = false;
+                        break;
+                    }
+
+                    debug!("Responding with {:?}", response.op);
+                    w.send(response).await?;
+                }
+
+                w.flush().await?;
+            }
+
+            let interruption = session.operations.lock().unwrap()
+                .remove(&msgid)
+                .flatten();
+            match (interruption, result_op) {
+                (Some(Interruption::Canceled), Some(result_op)) => {
+                    debug!("Operation {} canceled", msgid);
+                    if !completed {
+                        send_raw(&mut w, cancel::canceled(msgid, result_op)).await?;
+                    }
+                }
+
+                (Some(Interruption::Abandoned), _) => debug!("Operation {} abandoned", msgid),
+
+                _ => {}
+            }
+
+            // The cancel request is answered after the targeted operation has completed
+            if let Some(cancel_msgid) = cancels.remove(&msgid) {
+                let code = match interruption {
+                    Some(Interruption::Canceled) if !completed => LdapResultCode::Success as u8,
+                    Some(Interruption::Canceled) => cancel::TOO_LATE,
+                    _ => cancel::NO_SUCH_OPERATION,
+                };
+
+                send_raw(&mut w, cancel::cancel_response(cancel_msgid, code)).await?;
+            }
+        }
 
-        for response in responses {
-            debug!("Responding with {:?}", response.op);
-            w.send(response).await?;
+        return Ok::<_, anyhow::Error>(());
+    };
+
+    let res = tokio::select! {
+        res = async { tokio::try_join!(reader, processor) } => res.map(|_| ()),
+
+        _ = registration.kill.cancelled() => {
+            info!("Session {} from {} killed", registration.id, peer);
+            Ok(())
         }
+    };
 
-        w.flush().await?;
+    // Tell the client why the connection is going away instead of just resetting it
+    if let Err(err) = &res {
+        let notice = match err.downcast_ref::<ProtocolViolation>() {
+            Some(violation) => notice_of_disconnection(LdapResultCode::ProtocolError, &violation.to_string()),
+            None => notice_of_disconnection(LdapResultCode::Other, "Internal server error"),
+        };
+
+        // The connection might already be broken, so this is best effort only
+        if let Err(err) = w.send(notice).await {
+            debug!("Failed to send notice of disconnection to {}: {}", peer, err);
+        }
     }
 
-    debug!("Client disconnected {}", addr);
+    debug!("Client disconnected {}", peer);
 
-    return Ok(());
+    return res;
 }
 
-pub async fn serve(config: Config,
-                   database: Arc<RwLock<Database>>,
+/// Takes a permit for an accepted connection, which fails if the connection limit is reached
+fn acquire_connection(connections: &Option<Arc<Semaphore>>) -> Result<Option<OwnedSemaphorePermit>, TryAcquireError> {
+    return connections.as_ref()
+        .map(|connections| connections.clone().try_acquire_owned())
+        .transpose();
+}
+
+/// The naming contexts served according to the given config
+fn naming_contexts(config: &Config, database: &Arc<SharedDatabase>, tenants: &[Arc<SharedDatabase>]) -> Arc<NamingContexts> {
+    return Arc::new(NamingContexts::new(
+        NamingContext { base_dn: config.base_dn.clone(), layout: config.layout.clone(), database: database.clone() },
+        config.tenants.iter().zip(tenants).map(|(tenant, database)| NamingContext { base_dn: tenant.base_dn.clone(), layout: config.layout.clone(), database: database.clone() })));
+}
+
+/// Serves clients until shutdown, each connection is served with the config current when accepting it
+pub async fn serve(configs: watch::Receiver<Arc<Config>>,
+                   database: Arc<SharedDatabase>,
+                   tenants: Vec<Arc<SharedDatabase>>,
+                   control: Arc<Control>,
+                   messages: Arc<Messages>,
                    shutdown: impl Future) -> Result<()> {
+    let config = configs.borrow().clone();
+
     let listener = TcpListener::bind(config.listen).await
         .with_context(|| format!("Listening on {}", config.listen))?;
 
-    let config = Arc::new(config);
+    let tls = match &config.tls {
+        Some(tls) => Some((TcpListener::bind(tls.listen).await
+                               .with_context(|| format!("Listening on {}", tls.listen))?,
+                           tls.acceptor().await
+                               .context("Loading TLS config")?)),
+        None => None,
+    };
+
+    let ldapi = match &config.ldapi {
+        Some(ldapi) => {
+            // Remove stale socket left over from previous runs
+            if ldapi.socket.exists() {
+                tokio::fs::remove_file(&ldapi.socket).await
+                    .with_context(|| format!("Removing stale socket: {:?}", ldapi.socket))?;
+            }
+
+            Some(UnixListener::bind(&ldapi.socket)
+                .with_context(|| format!("Listening on {:?}", ldapi.socket))?)
+        }
+        None => None,
+    };
+
+    // Shared by all listeners, a permit is held for the lifetime of a connection
+    let connections = config.max_connections.map(|max| Arc::new(Semaphore::new(max)));
 
     let serve = async {
         loop {
             match listener.accept().await {
                 Ok((socket, addr)) => {
-                    tokio::spawn(serve_client(socket,
-                                              addr,
-                                              config.clone(),
-                                              database.clone()));
+                    let permit = match acquire_connection(&connections) {
+                        Ok(permit) => permit,
+                        Err(_) => {
+                            warn!("Connection limit reached, closing connection from {}", addr);
+                            continue;
+                        }
+                    };
+
+                    let config = configs.borrow().clone();
+                    let contexts = naming_contexts(&config, &database, &tenants);
+                    let session = serve_client(socket,
+                                               Peer::Tcp(addr),
+                                               config,
+                                               contexts,
+                                               control.clone(),
+                                               messages.clone(),
+                                               None);
+                    tokio::spawn(async move {
+                        let _permit = permit;
+                        return session.await;
+                    });
+                }
+
+                Err(err) => {
+                    error!("Failed to accept connection: {}", err);
+                }
+            }
+        }
+    };
+
+    let serve_tls = async {
+        let (listener, acceptor): (TcpListener, TlsAcceptor) = match tls {
+            Some(tls) => tls,
+            None => return futures::future::pending().await,
+        };
+
+        loop {
+            match listener.accept().await {
+                Ok((socket, addr)) => {
+                    let permit = match acquire_connection(&connections) {
+                        Ok(permit) => permit,
+                        Err(_) => {
+                            warn!("Connection limit reached, closing connection from {}", addr);
+                            continue;
+                        }
+                    };
+
+                    let acceptor = acceptor.clone();
+                    let config = configs.borrow().clone();
+                    let contexts = naming_contexts(&config, &database, &tenants);
+                    let control = control.clone();
+                    let messages = messages.clone();
+                    tokio::spawn(async move {
+                        let _permit = permit;
+
+                        let socket = match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+                            Ok(Ok(socket)) => socket,
+                            Ok(Err(err)) => {
+                                warn!("TLS handshake with {} failed: {}", addr, err);
+                                return Ok(());
+                            }
+                            Err(_) => {
+                                warn!("TLS handshake with {} timed out", addr);
+                                return Ok(());
+                            }
+                        };
+
+                        let certificate = socket.get_ref().1.peer_certificates()
+                            .and_then(|certificates| certificates.first())
+                            .cloned();
+
+                        return serve_client(socket, Peer::Tcp(addr), config, contexts, control, messages, certificate).await;
+                    });
+                }
+
+                Err(err) => {
+                    error!("Failed to accept connection: {}", err);
+                }
+            }
+        }
+    };
+
+    let serve_ldapi = async {
+        let listener = match ldapi {
+            Some(listener) => listener,
+            None => return futures::future::pending().await,
+        };
+
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    let uid = socket.peer_cred().ok().map(|cred| cred.uid());
+
+                    let permit = match acquire_connection(&connections) {
+                        Ok(permit) => permit,
+                        Err(_) => {
+                            warn!("Connection limit reached, closing connection from uid {:?}", uid);
+                            continue;
+                        }
+                    };
+
+                    let config = configs.borrow().clone();
+                    let contexts = naming_contexts(&config, &database, &tenants);
+                    let session = serve_client(socket,
+                                               Peer::Unix { uid },
+                                               config,
+                                               contexts,
+                                               control.clone(),
+                                               messages.clone(),
+                                               None);
+                    tokio::spawn(async move {
+                        let _permit = permit;
+                        return session.await;
+                    });
                 }
 
                 Err(err) => {
@@ -226,5 +2061,112 @@ pub async fn serve(config: Config,
         res = serve => {
             return res;
         }
+
+        res = serve_tls => {
+            return res;
+        }
+
+        res = serve_ldapi => {
+            return res;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::Config as DatabaseConfig;
+
+    const USER: &str = "password: '$argon2id$v=19$m=4096,t=3,p=1$dL8VpIEd5VBf/y+Gy7HtYw$XJsET7CMeA4OmtKis355BFijnJObZbIlcMxORki9m0s'\nfirst_name: 'Rob'\nlast_name: 'Paulsen'\nmail: 'brain@example.com'\ngroups: ['admins']\n";
+
+    const PINKY: &str = "cn=pinky,ou=users,dc=example,dc=com";
+
+    fn code(messages: Result<Vec<LdapMsg>>) -> LdapResultCode {
+        return match messages.unwrap().remove(0).op {
+            LdapOp::AddResponse(res) | LdapOp::ModifyResponse(res) | LdapOp::ModifyDNResponse(res) => res.code,
+            op => panic!("Unexpected response: {:?}", op),
+        };
+    }
+
+    fn attribute(name: &str, values: &[&str]) -> LdapPartialAttribute {
+        return LdapPartialAttribute {
+            atype: name.to_owned(),
+            vals: values.iter().map(|value| value.as_bytes().to_vec()).collect(),
+        };
+    }
+
+    fn replace(name: &str, values: &[&str]) -> LdapModifyRequest {
+        return LdapModifyRequest {
+            dn: PINKY.to_owned(),
+            changes: vec![LdapModify { operation: LdapModifyType::Replace, modification: attribute(name, values) }],
+        };
+    }
+
+    fn rename(newrdn: &str, new_superior: Option<&str>) -> LdapModifyDNRequest {
+        return LdapModifyDNRequest {
+            dn: PINKY.to_owned(),
+            newrdn: newrdn.to_owned(),
+            deleteoldrdn: true,
+            new_superior: new_superior.map(str::to_owned),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_write() {
+        let dir = std::env::temp_dir().join(format!("concess-write-{}", std::process::id()));
+        tokio::fs::create_dir_all(dir.join("users")).await.unwrap();
+        tokio::fs::write(dir.join("users/brain.yaml"), USER).await.unwrap();
+
+        let database = Database::load(&dir, &DatabaseConfig::default()).await.unwrap();
+        let config: Arc<Config> = Arc::new(serde_yaml::from_str("base_dn: 'dc=example,dc=com'\nlisten: '[::1]:389'\nwriters: ['admins']\n").unwrap());
+
+        let (entries, _streamed) = mpsc::channel(1);
+        let session = Session {
+            id: 0,
+            peer: Peer::Unix { uid: None },
+            contexts: naming_contexts(&config, &database, &[]),
+            config,
+            control: Arc::new(Control::default()),
+            messages: Arc::new(Messages::default()),
+            certificate: None,
+            operations: Default::default(),
+            binding: Mutex::new(Binding::Bound(DN::from_str("cn=brain,ou=users,dc=example,dc=com").unwrap())),
+            entries,
+        };
+
+        let add = |attributes: &[LdapPartialAttribute]| LdapAddRequest {
+            dn: PINKY.to_owned(),
+            attributes: attributes.to_vec(),
+        };
+        let user = [
+            attribute("objectClass", &["inetOrgPerson"]),
+            attribute("cn", &["pinky"]),
+            attribute("givenName", &["Pinky"]),
+            attribute("sn", &["Mouse"]),
+            attribute("mail", &["pinky@example.com"]),
+            attribute("userPassword", &["narf"]),
+        ];
+
+        // Attributes which are not modeled are refused instead of getting lost
+        assert_eq!(code(session.do_add(1, add(&[&user[..], &[attribute("description", &["Genius"])]].concat())).await), LdapResultCode::UnwillingToPerform);
+        assert_eq!(code(session.do_add(2, add(&[&user[..5], &[attribute("userPassword", &["{SSHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g="])]].concat())).await), LdapResultCode::InvalidAttributeSyntax);
+        assert_eq!(code(session.do_add(3, add(&user)).await), LdapResultCode::Success);
+        assert_eq!(code(session.do_add(4, add(&user)).await), LdapResultCode::EntryAlreadyExists);
+        assert!(dir.join("users/pinky.yaml").exists());
+
+        assert_eq!(code(session.do_modify(5, replace("mail", &["narf@example.com"])).await), LdapResultCode::Success);
+        assert_eq!(code(session.do_modify(6, replace("description", &["Genius"])).await), LdapResultCode::UnwillingToPerform);
+        assert_eq!(database.snapshot().user_entity("pinky").unwrap().mail, vec!["narf@example.com".to_owned()]);
+
+        assert_eq!(code(session.do_modify_dn(7, rename("cn=..", None)).await), LdapResultCode::NamingViolation);
+        assert_eq!(code(session.do_modify_dn(8, rename("cn=snowball", Some("ou=groups,dc=example,dc=com"))).await), LdapResultCode::UnwillingToPerform);
+        assert_eq!(code(session.do_modify_dn(9, rename("cn=snowball", None)).await), LdapResultCode::Success);
+        assert!(dir.join("users/snowball.yaml").exists());
+        assert!(!dir.join("users/pinky.yaml").exists());
+
+        session.control.set_maintenance(true);
+        assert_eq!(code(session.do_add(10, add(&user)).await), LdapResultCode::Unavailable);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
     }
 }
\ No newline at end of file
@@ -0,0 +1,112 @@
+use std::str::FromStr;
+
+use crate::admin::Control;
+use crate::metrics::Counters;
+
+use super::dn::{AttributeName, DN, dn};
+use super::entities::{ATTR_CN, ATTR_ENTRY_DN, ATTR_OBJECT_CLASS, Entity, object_class_hierarchy};
+
+/// Base of the entries exposing the state of the server
+pub const MONITOR: &str = "cn=monitor";
+
+pub const ATTR_MONITOR_COUNTER: AttributeName = AttributeName::from("monitorCounter");
+
+/// Counters of bind operations by result
+pub const BINDS: &str = "ldap_binds";
+
+/// Counter of search operations
+pub const SEARCHES: &str = "ldap_searches";
+
+/// Counts a bind operation of a user by its result, leaving out canaries which are only recorded in the canary log
+pub fn count_bind(counters: &Counters, canary: bool, result: &'static str) {
+    if canary {
+        return;
+    }
+
+    counters.increment(BINDS, Some(("result", result)));
+}
+
+/// Entry below the monitor base, either the base itself or a single counter
+pub struct MonitorEntry {
+    name: Option<&'static str>,
+    counter: u64,
+}
+
+impl MonitorEntry {
+    /// Collects the monitor base and its counters
+    pub fn collect(control: &Control) -> Vec<Self> {
+        let counters = control.counters().snapshot();
+        let counter = |name, label| counters.get(&(name, label)).copied().unwrap_or_default();
+
+        return vec![
+            Self { name: None, counter: 0 },
+            Self { name: Some("Connections"), counter: control.sessions().list().len() as u64 },
+            Self { name: Some("Successful Binds"), counter: counter(BINDS, Some(("result", "success"))) },
+            Self { name: Some("Failed Binds"), counter: counter(BINDS, Some(("result", "failure"))) },
+            Self { name: Some("Searches"), counter: counter(SEARCHES, None) },
+            Self { name: Some("Uptime"), counter: control.uptime().as_secs() },
+        ];
+    }
+}
+
+impl Entity for MonitorEntry {
+    const OBJECT_CLASSES: &'static [&'static str] = &[
+        "monitorCounterObject",
+    ];
+
+    const ATTRIBUTES: &'static [AttributeName] = &[
+        ATTR_OBJECT_CLASS,
+        ATTR_ENTRY_DN,
+        ATTR_CN,
+        ATTR_MONITOR_COUNTER,
+    ];
+
+    fn dn(&self) -> DN {
+        let base = DN::from_str(MONITOR).expect("Valid monitor DN");
+        return match self.name {
+            Some(name) => dn!["cn" => name; base],
+            None => base,
+        };
+    }
+
+    fn get(attribute: &AttributeName) -> Option<for<'a> fn(&'a Self) -> Vec<String>> {
+        if attribute == &ATTR_OBJECT_CLASS {
+            return Some(|e| match e.name {
+                Some(_) => Self::object_classes(),
+                None => object_class_hierarchy(&["monitorServer"]),
+            });
+        }
+
+        if attribute == &ATTR_ENTRY_DN {
+            return Some(|e| vec![e.dn().to_string()]);
+        }
+
+        if attribute == &ATTR_CN {
+            return Some(|e| vec![e.name.unwrap_or("Monitor").to_string()]);
+        }
+
+        if attribute == &ATTR_MONITOR_COUNTER {
+            return Some(|e| e.name.map(|_| e.counter.to_string()).into_iter().collect());
+        }
+
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_canary_binds() {
+        let counters = Counters::default();
+
+        count_bind(&counters, false, "success");
+        count_bind(&counters, true, "success");
+        count_bind(&counters, true, "failure");
+
+        let snapshot = counters.snapshot();
+        assert_eq!(snapshot.get(&(BINDS, Some(("result", "success")))), Some(&1));
+        assert_eq!(snapshot.get(&(BINDS, Some(("result", "failure")))), None);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,21 @@
+use std::fmt;
+use std::net::SocketAddr;
+
+/// Remote end of a client connection
+#[derive(Debug, Clone, Copy)]
+pub enum Peer {
+    Tcp(SocketAddr),
+
+    /// Local process connected to the LDAPI socket identified by its user ID if known
+    Unix { uid: Option<u32> },
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Peer::Tcp(addr) => write!(f, "{}", addr),
+            Peer::Unix { uid: Some(uid) } => write!(f, "ldapi:uid={}", uid),
+            Peer::Unix { uid: None } => write!(f, "ldapi"),
+        };
+    }
+}
\ No newline at end of file
@@ -0,0 +1,116 @@
+use bytes::BytesMut;
+use lber::common::TagClass;
+use lber::parse::parse_tag;
+use lber::structure::{PL, StructureTag};
+use lber::universal::Types;
+use ldap3_proto::proto::{LdapMsg, LdapOp, LdapSearchResultEntry};
+
+use super::codec::Control;
+
+/// Persistent search control (draft-ietf-ldapext-psearch-03)
+pub const OID: &str = "2.16.840.1.113730.3.4.3";
+
+/// Entry change notification control attached to entries returned by a persistent search
+const ENTRY_CHANGE_OID: &str = "2.16.840.1.113730.3.4.7";
+
+/// Kinds of changes, combined as bit mask in the request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeType {
+    Add = 1,
+    Delete = 2,
+    Modify = 4,
+}
+
+/// Parameters of a persistent search request
+#[derive(Debug, Clone, Copy)]
+pub struct PersistentSearch {
+    change_types: i64,
+
+    /// Skip the initial result set and only return entries after they have changed
+    pub changes_only: bool,
+
+    /// Attach entry change notifications to the returned entries
+    pub return_ecs: bool,
+}
+
+impl PersistentSearch {
+    pub fn parse(control: &Control) -> Option<Self> {
+        let (_, tag) = parse_tag(control.value.as_deref()?).ok()?;
+        let elements = tag
+            .match_class(TagClass::Universal)
+            .and_then(|tag| tag.match_id(Types::Sequence as u64))
+            .and_then(|tag| tag.expect_constructed())?;
+
+        let [change_types, changes_only, return_ecs]: [StructureTag; 3] = elements.try_into().ok()?;
+
+        let change_types = change_types.match_id(Types::Integer as u64)
+            .and_then(|tag| tag.expect_primitive())?
+            .iter()
+            .fold(0i64, |acc, b| (acc << 8) | *b as i64);
+        let boolean = |tag: StructureTag| tag.match_id(Types::Boolean as u64)
+            .and_then(|tag| tag.expect_primitive())
+            .map(|value| value.first().map_or(false, |b| *b != 0));
+
+        return Some(Self {
+            change_types,
+            changes_only: boolean(changes_only)?,
+            return_ecs: boolean(return_ecs)?,
+        });
+    }
+
+    /// Checks whether the client is interested in changes of the given kind
+    pub fn includes(&self, change: ChangeType) -> bool {
+        return self.change_types & change as i64 != 0;
+    }
+
+    /// Encodes an entry to return, optionally annotated with the change that caused it to be returned
+    pub fn entry(&self, msgid: i32, entry: LdapSearchResultEntry, change: Option<ChangeType>) -> StructureTag {
+        let mut tag = StructureTag::from(LdapMsg {
+            msgid,
+            op: LdapOp::SearchResultEntry(entry),
+            ctrl: vec![],
+        });
+
+        if let (true, Some(change), PL::C(message)) = (self.return_ecs, change, &mut tag.payload) {
+            message.push(StructureTag {
+                class: TagClass::Context,
+                id: 0,
+                payload: PL::C(vec![entry_change(change)]),
+            });
+        }
+
+        return tag;
+    }
+}
+
+fn entry_change(change: ChangeType) -> StructureTag {
+    let value = StructureTag {
+        class: TagClass::Universal,
+        id: Types::Sequence as u64,
+        payload: PL::C(vec![StructureTag {
+            class: TagClass::Universal,
+            id: Types::Enumerated as u64,
+            payload: PL::P(vec![change as u8]),
+        }]),
+    };
+
+    let mut buf = BytesMut::new();
+    lber::write::encode_into(&mut buf, value).expect("Encoding into memory");
+
+    return StructureTag {
+        class: TagClass::Universal,
+        id: Types::Sequence as u64,
+        payload: PL::C(vec![
+            StructureTag {
+                class: TagClass::Universal,
+                id: Types::OctetString as u64,
+                payload: PL::P(ENTRY_CHANGE_OID.as_bytes().to_vec()),
+            },
+            StructureTag {
+                class: TagClass::Universal,
+                id: Types::OctetString as u64,
+                payload: PL::P(buf.to_vec()),
+            },
+        ]),
+    };
+}
\ No newline at end of file
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use ldap3_proto::{LdapPartialAttribute, LdapSearchResultEntry};
+use serde::{Deserialize, Deserializer};
+
+use super::approx::ApproxMatch;
+use super::compat::{ATTR_OBJECT_CATEGORY, ATTR_SAM_ACCOUNT_NAME, ATTR_USER_PRINCIPAL_NAME};
+use super::container::{ATTR_DC, ATTR_O, ATTR_OU};
+use super::dn::{AttributeName, DN};
+use super::entities::{ATTR_CN, ATTR_DESCRIPTION, ATTR_DISPLAY_NAME, ATTR_ENTRY_DN, ATTR_ENTRY_UUID, ATTR_GID_NUMBER, ATTR_GIVEN_NAME, ATTR_MAIL, ATTR_MAIL_ALIAS, ATTR_MEMBER, ATTR_MEMBER_OF, ATTR_OBJECT_CLASS, ATTR_OWNER, ATTR_SHADOW_EXPIRE, ATTR_SHADOW_LAST_CHANGE, ATTR_SHADOW_MAX, ATTR_SN, ATTR_UID, ATTR_UID_NUMBER, ATTR_UNIQUE_MEMBERS};
+
+/// Name of the subschema entry
+pub const SUBSCHEMA: &str = "cn=Subschema";
+
+pub const ATTR_ATTRIBUTE_TYPES: AttributeName = AttributeName::from("attributeTypes");
+
+/// Equality matching rules of the standard attributes exposed by the entities as defined by their syntaxes
+const STANDARD_EQUALITY: &[(AttributeName, MatchingRule)] = &[
+    (ATTR_OBJECT_CLASS, MatchingRule::CaseIgnore),
+    (ATTR_CN, MatchingRule::CaseIgnore),
+    (ATTR_DISPLAY_NAME, MatchingRule::CaseIgnore),
+    (ATTR_DESCRIPTION, MatchingRule::CaseIgnore),
+    (ATTR_GIVEN_NAME, MatchingRule::CaseIgnore),
+    (ATTR_SN, MatchingRule::CaseIgnore),
+    (ATTR_MAIL, MatchingRule::CaseIgnore),
+    (ATTR_MAIL_ALIAS, MatchingRule::CaseIgnore),
+    (ATTR_UID, MatchingRule::CaseIgnore),
+    (ATTR_ENTRY_UUID, MatchingRule::CaseIgnore),
+    (ATTR_DC, MatchingRule::CaseIgnore),
+    (ATTR_O, MatchingRule::CaseIgnore),
+    (ATTR_OU, MatchingRule::CaseIgnore),
+    (ATTR_SAM_ACCOUNT_NAME, MatchingRule::CaseIgnore),
+    (ATTR_USER_PRINCIPAL_NAME, MatchingRule::CaseIgnore),
+    (ATTR_OBJECT_CATEGORY, MatchingRule::DistinguishedName),
+    (ATTR_UID_NUMBER, MatchingRule::Integer),
+    (ATTR_GID_NUMBER, MatchingRule::Integer),
+    (ATTR_SHADOW_LAST_CHANGE, MatchingRule::Integer),
+    (ATTR_SHADOW_MAX, MatchingRule::Integer),
+    (ATTR_SHADOW_EXPIRE, MatchingRule::Integer),
+    (ATTR_ENTRY_DN, MatchingRule::DistinguishedName),
+    (ATTR_MEMBER_OF, MatchingRule::DistinguishedName),
+    (ATTR_MEMBER, MatchingRule::DistinguishedName),
+    (ATTR_UNIQUE_MEMBERS, MatchingRule::DistinguishedName),
+    (ATTR_OWNER, MatchingRule::DistinguishedName),
+];
+
+/// Equality matching rules applicable to attribute values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum MatchingRule {
+    #[default]
+    #[serde(rename = "caseExactMatch")]
+    CaseExact,
+
+    #[serde(rename = "caseIgnoreMatch")]
+    CaseIgnore,
+
+    #[serde(rename = "octetStringMatch")]
+    OctetString,
+
+    #[serde(rename = "integerMatch")]
+    Integer,
+
+    #[serde(rename = "distinguishedNameMatch")]
+    DistinguishedName,
+}
+
+impl MatchingRule {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            MatchingRule::CaseExact => "caseExactMatch",
+            MatchingRule::CaseIgnore => "caseIgnoreMatch",
+            MatchingRule::OctetString => "octetStringMatch",
+            MatchingRule::Integer => "integerMatch",
+            MatchingRule::DistinguishedName => "distinguishedNameMatch",
+        };
+    }
+
+    /// Looks up a matching rule by its name or OID as used in extensible match filters
+    ///
+    /// Groups can not be nested, so the transitive membership rule of Active Directory is the same as DN matching.
+    pub fn lookup(rule: &str) -> Option<Self> {
+        return match rule {
+            "2.5.13.5" => Some(MatchingRule::CaseExact),
+            "2.5.13.2" => Some(MatchingRule::CaseIgnore),
+            "2.5.13.17" => Some(MatchingRule::OctetString),
+            "2.5.13.14" => Some(MatchingRule::Integer),
+            "2.5.13.1" | "1.2.840.113556.1.4.1941" => Some(MatchingRule::DistinguishedName),
+            name => [MatchingRule::CaseExact, MatchingRule::CaseIgnore, MatchingRule::OctetString, MatchingRule::Integer, MatchingRule::DistinguishedName].into_iter()
+                .find(|rule| rule.name().eq_ignore_ascii_case(name)),
+        };
+    }
+
+    /// Checks whether an attribute value matches the asserted value
+    pub fn matches(&self, value: &str, assertion: &str) -> bool {
+        return match self {
+            MatchingRule::CaseExact | MatchingRule::OctetString => value == assertion,
+            MatchingRule::CaseIgnore => value.to_lowercase() == assertion.to_lowercase(),
+            MatchingRule::Integer => match (i64::from_str(value.trim()), i64::from_str(assertion.trim())) {
+                (Ok(value), Ok(assertion)) => value == assertion,
+                _ => false,
+            },
+            MatchingRule::DistinguishedName => match (DN::from_str(value), DN::from_str(assertion)) {
+                (Ok(value), Ok(assertion)) => value.matches(&assertion),
+                _ => false,
+            },
+        };
+    }
+}
+
+fn oid<'de, D>(deserializer: D) -> Result<AttributeName, D::Error>
+    where
+        D: Deserializer<'de> {
+    return match AttributeName::deserialize(deserializer)? {
+        oid @ AttributeName::OID(_) => Ok(oid),
+        name => Err(serde::de::Error::custom(format!("Not an OID: {}", name))),
+    };
+}
+
+/// Site-specific attribute type declared in the config
+#[derive(Debug, Clone, Deserialize)]
+pub struct AttributeType {
+    /// Name of the attribute type as published in the subschema entry
+    pub name: String,
+
+    #[serde(deserialize_with = "oid")]
+    pub oid: AttributeName,
+
+    /// OID of the attribute syntax
+    #[serde(deserialize_with = "oid")]
+    pub syntax: AttributeName,
+
+    #[serde(default)]
+    pub equality: MatchingRule,
+
+    pub description: Option<String>,
+}
+
+impl AttributeType {
+    pub fn attribute_name(&self) -> AttributeName {
+        return AttributeName::from(self.name.clone());
+    }
+}
+
+impl fmt::Display for AttributeType {
+    /// Formats the attribute type description as defined by RFC 4512
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "( {} NAME '{}'", self.oid, self.name)?;
+        if let Some(description) = &self.description {
+            write!(f, " DESC '{}'", description.replace('\\', "\\5C").replace('\'', "\\27"))?;
+        }
+        write!(f, " EQUALITY {} SYNTAX {} )", self.equality.name(), self.syntax)?;
+        return Ok(());
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Schema {
+    #[serde(default)]
+    pub attribute_types: Vec<AttributeType>,
+
+    /// Algorithm used for approximate match filters
+    #[serde(default)]
+    pub approx_match: ApproxMatch,
+
+    /// Alternative names of attributes used by clients, resolved in filters and requested attributes
+    #[serde(default)]
+    pub aliases: HashMap<AttributeName, AttributeName>,
+}
+
+impl Schema {
+    /// Looks up a declared attribute type by its name or OID
+    pub fn attribute_type(&self, attribute: &AttributeName) -> Option<&AttributeType> {
+        return self.attribute_types.iter()
+            .find(|attribute_type| &attribute_type.attribute_name() == attribute || &attribute_type.oid == attribute);
+    }
+
+    /// Resolves an attribute given by alias or OID to its declared name
+    pub fn resolve(&self, attribute: &AttributeName) -> AttributeName {
+        let attribute = self.aliases.get(attribute).unwrap_or(attribute);
+        return self.attribute_type(attribute)
+            .map(AttributeType::attribute_name)
+            .unwrap_or_else(|| attribute.clone());
+    }
+
+    /// Equality matching rule of a declared or standard attribute, values of unknown attributes are matched exactly
+    pub fn matching_rule(&self, attribute: &AttributeName) -> MatchingRule {
+        return self.attribute_type(attribute)
+            .map(|attribute_type| attribute_type.equality)
+            .or_else(|| STANDARD_EQUALITY.iter()
+                .find(|(name, _)| name == attribute)
+                .map(|(_, rule)| *rule))
+            .unwrap_or_default();
+    }
+
+    pub fn subschema_entry(&self) -> LdapSearchResultEntry {
+        return LdapSearchResultEntry {
+            dn: SUBSCHEMA.to_string(),
+            attributes: vec![
+                LdapPartialAttribute {
+                    atype: ATTR_OBJECT_CLASS.to_string(),
+                    vals: vec![b"top".to_vec(), b"subschema".to_vec()],
+                },
+                LdapPartialAttribute {
+                    atype: ATTR_CN.to_string(),
+                    vals: vec![b"Subschema".to_vec()],
+                },
+                LdapPartialAttribute {
+                    atype: ATTR_ATTRIBUTE_TYPES.to_string(),
+                    vals: self.attribute_types.iter()
+                        .map(|attribute_type| attribute_type.to_string().into_bytes())
+                        .collect(),
+                },
+            ],
+        };
+    }
+}
\ No newline at end of file
@@ -0,0 +1,103 @@
+use std::str::FromStr;
+
+use super::dn::AttributeName;
+use super::entities::OPERATIONAL_ATTRIBUTES;
+use super::schema::Schema;
+
+/// Requests all user attributes (RFC 4511, section 4.5.1.8)
+const ALL_USER_ATTRIBUTES: &str = "*";
+
+/// Requests all operational attributes (RFC 3673)
+const ALL_OPERATIONAL_ATTRIBUTES: &str = "+";
+
+/// Requests no attributes at all if given as the only attribute
+const NO_ATTRIBUTES: &str = "1.1";
+
+/// Attributes to return for the entries found by a search
+#[derive(Debug, Clone)]
+pub struct Selection {
+    all_user: bool,
+    all_operational: bool,
+    attributes: Vec<AttributeName>,
+
+    /// Only return attribute descriptions without values
+    types_only: bool,
+}
+
+impl Selection {
+    pub fn parse(attrs: &[String], types_only: bool, schema: &Schema) -> Self {
+        // An empty list is the same as asking for all user attributes
+        if attrs.is_empty() {
+            return Self {
+                all_user: true,
+                all_operational: false,
+                attributes: vec![],
+                types_only,
+            };
+        }
+
+        return Self {
+            all_user: attrs.iter().any(|attr| attr == ALL_USER_ATTRIBUTES),
+            all_operational: attrs.iter().any(|attr| attr == ALL_OPERATIONAL_ATTRIBUTES),
+            attributes: attrs.iter()
+                .filter(|attr| ![ALL_USER_ATTRIBUTES, ALL_OPERATIONAL_ATTRIBUTES, NO_ATTRIBUTES].contains(&attr.as_str()))
+                // Attributes unknown to the server are ignored
+                .filter_map(|attr| AttributeName::from_str(attr).ok())
+                .map(|attribute| schema.resolve(&attribute))
+                .collect(),
+            types_only,
+        };
+    }
+
+    pub fn types_only(&self) -> bool {
+        return self.types_only;
+    }
+
+    /// Checks whether the attribute has been requested by its name instead of by a wildcard
+    pub fn names(&self, attribute: &AttributeName) -> bool {
+        return self.attributes.contains(attribute);
+    }
+
+    /// Checks whether the attribute has been requested
+    pub fn includes(&self, attribute: &AttributeName) -> bool {
+        if self.names(attribute) {
+            return true;
+        }
+
+        return if OPERATIONAL_ATTRIBUTES.contains(attribute) {
+            self.all_operational
+        } else {
+            self.all_user
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(attrs: &[&str]) -> Selection {
+        return Selection::parse(&attrs.iter().map(ToString::to_string).collect::<Vec<_>>(), false, &Schema::default());
+    }
+
+    #[test]
+    fn test_selection() {
+        assert!(parse(&[]).includes(&AttributeName::from("mail")));
+        assert!(parse(&["*"]).includes(&AttributeName::from("mail")));
+        assert!(parse(&["cn", "*"]).includes(&AttributeName::from("mail")));
+
+        assert!(parse(&["cn", "MAIL"]).includes(&AttributeName::from("mail")));
+        assert!(!parse(&["cn"]).includes(&AttributeName::from("mail")));
+
+        assert!(!parse(&["1.1"]).includes(&AttributeName::from("cn")));
+
+        assert!(!parse(&[]).includes(&AttributeName::from("entryDN")));
+        assert!(!parse(&["*"]).includes(&AttributeName::from("entryDN")));
+        assert!(parse(&["+"]).includes(&AttributeName::from("entryDN")));
+        assert!(!parse(&["+"]).includes(&AttributeName::from("cn")));
+        assert!(parse(&["*", "entryDN"]).includes(&AttributeName::from("entryDN")));
+
+        assert!(parse(&["*", "MAIL"]).names(&AttributeName::from("mail")));
+        assert!(!parse(&["*"]).names(&AttributeName::from("mail")));
+    }
+}
\ No newline at end of file
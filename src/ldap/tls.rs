@@ -0,0 +1,92 @@
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use tokio_rustls::rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, NoClientAuth};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use super::config::TlsConfig;
+
+impl TlsConfig {
+    pub async fn acceptor(&self) -> Result<TlsAcceptor> {
+        return acceptor(&self.certificate, &self.key, self.client_ca.as_deref()).await;
+    }
+}
+
+/// Acceptor presenting the given certificate, optionally verifying client certificates by the given CA certificates
+pub async fn acceptor(certificate: &Path, key: &Path, client_ca: Option<&Path>) -> Result<TlsAcceptor> {
+    let chain = tokio::fs::read(certificate).await
+        .with_context(|| format!("Reading certificate: {:?}", certificate))?;
+    let chain = rustls_pemfile::certs(&mut BufReader::new(chain.as_slice()))
+        .with_context(|| format!("Parsing certificate: {:?}", certificate))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+
+    let private_key = tokio::fs::read(key).await
+        .with_context(|| format!("Reading key: {:?}", key))?;
+    let private_key = rustls_pemfile::read_all(&mut BufReader::new(private_key.as_slice()))
+        .with_context(|| format!("Parsing key: {:?}", key))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key) |
+            rustls_pemfile::Item::PKCS8Key(key) |
+            rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("No private key found: {:?}", key))?;
+
+    let verifier = match client_ca {
+        Some(client_ca) => {
+            let client_ca = tokio::fs::read(client_ca).await
+                .with_context(|| format!("Reading client CA: {:?}", client_ca))?;
+
+            let mut roots = RootCertStore::empty();
+            for certificate in rustls_pemfile::certs(&mut BufReader::new(client_ca.as_slice()))? {
+                roots.add(&Certificate(certificate))
+                    .context("Invalid client CA certificate")?;
+            }
+
+            AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+        }
+        None => NoClientAuth::new(),
+    };
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(chain, private_key)
+        .context("Invalid certificate or key")?;
+
+    return Ok(TlsAcceptor::from(Arc::new(config)));
+}
+
+/// Connector verifying servers by the given CA certificates
+pub async fn connector(ca: Option<&Path>) -> Result<TlsConnector> {
+    let ca = ca.context("CA certificates required to verify the server")?;
+    let ca = tokio::fs::read(ca).await
+        .with_context(|| format!("Reading CA: {:?}", ca))?;
+
+    let mut roots = RootCertStore::empty();
+    for certificate in rustls_pemfile::certs(&mut BufReader::new(ca.as_slice()))? {
+        roots.add(&Certificate(certificate))
+            .context("Invalid CA certificate")?;
+    }
+
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    return Ok(TlsConnector::from(Arc::new(config)));
+}
+
+/// Extracts the common name from the subject of a certificate
+pub fn subject_cn(certificate: &Certificate) -> Option<String> {
+    let (_, certificate) = X509Certificate::from_der(&certificate.0).ok()?;
+    let cn = certificate.subject().iter_common_name().next()?;
+    return cn.as_str().ok().map(ToString::to_string);
+}
\ No newline at end of file
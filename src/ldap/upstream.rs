@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{SinkExt, StreamExt};
+use ldap3_proto::{LdapCodec, LdapFilter, LdapResultCode, LdapSearchScope};
+use ldap3_proto::control::LdapControl;
+use ldap3_proto::proto::{LdapBindCred, LdapBindRequest, LdapDerefAliases, LdapMsg, LdapOp, LdapResult, LdapSearchRequest, LdapSearchResultEntry};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio_rustls::rustls::ServerName;
+use tokio_util::codec::Framed;
+use tracing::{info, warn};
+
+use crate::database::{Actor, Database, GroupEntity, PasswordHash, SharedDatabase, UserEntity};
+
+use super::{ATTR_USER_PASSWORD, Config};
+use super::config::Upstream;
+use super::dn::{AttributeName, DN};
+use super::entities::{ATTR_CN, ATTR_UID};
+use super::ldif::{self, Record};
+use super::tls;
+
+/// Maximum size of a single message received from the upstream server
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Connection to the upstream server handling a single request at a time
+struct Client {
+    framed: Framed<Box<dyn Stream>, LdapCodec>,
+    timeout: Duration,
+    msgid: i32,
+}
+
+impl Client {
+    async fn connect(url: &str, ca: Option<&Path>, timeout: u64) -> Result<Self> {
+        let timeout = Duration::from_secs(timeout);
+
+        let (tls, address) = match url.split_once("://") {
+            Some(("ldap", address)) => (false, address.trim_end_matches('/')),
+            Some(("ldaps", address)) => (true, address.trim_end_matches('/')),
+            _ => bail!("Unsupported URL: {}", url),
+        };
+
+        // Addresses of IPv6 literals are enclosed in brackets
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) if !port.ends_with(']') => (host, port.parse::<u16>().context("Invalid port")?),
+            _ => (address, if tls { 636 } else { 389 }),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        let stream = tokio::time::timeout(timeout, TcpStream::connect((host, port))).await
+            .context("Timeout while connecting")?
+            .with_context(|| format!("Connecting to {}", address))?;
+
+        let stream: Box<dyn Stream> = if tls {
+            let name = ServerName::try_from(host)
+                .map_err(|_| anyhow!("Invalid server name: {}", host))?;
+            Box::new(tokio::time::timeout(timeout, tls::connector(ca).await?.connect(name, stream)).await
+                .context("Timeout during TLS handshake")?
+                .context("TLS handshake")?)
+        } else {
+            Box::new(stream)
+        };
+
+        return Ok(Self {
+            framed: Framed::new(stream, LdapCodec::new(Some(MAX_MESSAGE_SIZE))),
+            timeout,
+            msgid: 0,
+        });
+    }
+
+    async fn request(&mut self, op: LdapOp, ctrl: Vec<LdapControl>) -> Result<()> {
+        self.msgid += 1;
+        return self.framed.send(LdapMsg { msgid: self.msgid, op, ctrl }).await
+            .context("Sending request");
+    }
+
+    async fn response(&mut self) -> Result<LdapMsg> {
+        let msg = tokio::time::timeout(self.timeout, self.framed.next()).await
+            .context("Timeout while waiting for response")?
+            .context("Connection closed")?
+            .context("Receiving response")?;
+
+        if msg.msgid != self.msgid {
+            bail!("Response to unknown request: {}", msg.msgid);
+        }
+
+        return Ok(msg);
+    }
+
+    async fn try_bind(&mut self, dn: &str, password: &str) -> Result<LdapResult> {
+        self.request(LdapOp::BindRequest(LdapBindRequest {
+            dn: dn.to_owned(),
+            cred: LdapBindCred::Simple(password.to_owned()),
+        }), vec![]).await?;
+
+        return match self.response().await?.op {
+            LdapOp::BindResponse(res) => Ok(res.res),
+            _ => Err(anyhow!("Unexpected response to bind")),
+        };
+    }
+
+    async fn bind(&mut self, dn: &str, password: &str) -> Result<()> {
+        let res = self.try_bind(dn, password).await?;
+        if res.code != LdapResultCode::Success {
+            bail!("Bind failed: {:?}: {}", res.code, res.message);
+        }
+
+        return Ok(());
+    }
+
+    /// Searches the subtree page by page, servers not supporting paging return all entries at once
+    async fn search(&mut self, base: &DN, filter: &LdapFilter, page_size: i64) -> Result<Vec<LdapSearchResultEntry>> {
+        let mut entries = Vec::new();
+        let mut cookie = Vec::new();
+
+        loop {
+            self.request(LdapOp::SearchRequest(LdapSearchRequest {
+                base: base.to_string(),
+                scope: LdapSearchScope::Subtree,
+                aliases: LdapDerefAliases::Never,
+                sizelimit: 0,
+                timelimit: 0,
+                typesonly: false,
+                filter: filter.clone(),
+                attrs: vec!["*".to_owned(), "entryUUID".to_owned()],
+            }), vec![LdapControl::SimplePagedResults { size: page_size, cookie }]).await?;
+
+            let controls = loop {
+                let msg = self.response().await?;
+                match msg.op {
+                    LdapOp::SearchResultEntry(entry) => entries.push(entry),
+
+                    // Referrals to other servers are not followed
+                    LdapOp::SearchResultReference(_) => continue,
+
+                    LdapOp::SearchResultDone(res) if res.code == LdapResultCode::Success => break msg.ctrl,
+                    LdapOp::SearchResultDone(res) => bail!("Search failed: {:?}: {}", res.code, res.message),
+                    _ => bail!("Unexpected response to search"),
+                }
+            };
+
+            cookie = controls.into_iter()
+                .find_map(|control| match control {
+                    LdapControl::SimplePagedResults { cookie, .. } => Some(cookie),
+                    _ => None,
+                })
+                .unwrap_or_default();
+
+            if cookie.is_empty() {
+                return Ok(entries);
+            }
+        }
+    }
+
+    async fn unbind(mut self) {
+        // The server closes the connection without responding
+        let _ = self.request(LdapOp::UnbindRequest, vec![]).await;
+    }
+}
+
+/// Converts an entry of the upstream server to a record, taking over the attributes as configured
+fn record(upstream: &Upstream, entry: LdapSearchResultEntry) -> Result<Record> {
+    let dn = DN::from_str(&entry.dn)
+        .map_err(|err| anyhow!("Invalid DN {}: {}", entry.dn, err))?;
+
+    let mut attributes = Vec::new();
+    for attribute in entry.attributes {
+        // Options like `;binary` are not distinguished
+        let name = match AttributeName::from_str(attribute.atype.split(';').next().unwrap_or_default()) {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+
+        // Attributes taken from others are replaced as a whole
+        let names = std::iter::once(name.clone())
+            .filter(|name| !upstream.attributes.contains_key(name))
+            .chain(upstream.attributes.iter()
+                .filter(|(_, source)| **source == name)
+                .map(|(target, _)| target.clone()))
+            .collect::<Vec<_>>();
+
+        for name in names {
+            attributes.extend(attribute.vals.iter().map(|value| (name.clone(), value.clone())));
+        }
+    }
+
+    return Ok(Record { dn, attributes });
+}
+
+/// Password of a pulled user, keeping the current one if the upstream server does not disclose a usable one
+fn password(database: &Database, name: &str, record: &Record) -> PasswordHash {
+    let current = database.user_entity(name).map(|user| &user.password.hash);
+    let value = record.first(&ATTR_USER_PASSWORD);
+
+    // Passwords in plain text are not hashed again on every pull
+    if let (Some(current), Some(value)) = (current, &value) {
+        if !value.starts_with('{') && current.verify(value.as_bytes()) {
+            return current.clone();
+        }
+    }
+
+    return match (ldif::password(database, value), current) {
+        (Ok(password), _) => password,
+        (Err(_), Some(current)) => current.clone(),
+        (Err(err), None) => {
+            warn!("Locking user {} by a random password: {}", name, err);
+            ldif::random_password(database)
+        }
+    };
+}
+
+/// Converts the records to users declaring their memberships and to groups without listed members
+fn convert(upstream: &Upstream, database: &Database, users: &[Record], groups: &[Record]) -> (Vec<(String, UserEntity)>, Vec<(String, GroupEntity)>) {
+    let users = users.iter()
+        .filter_map(|record| record.name(&ATTR_UID).map(|name| (name, record)))
+        .collect::<Vec<_>>();
+    let groups = groups.iter()
+        .filter_map(|record| record.name(&ATTR_CN).map(|name| (name, record)))
+        .collect::<Vec<_>>();
+
+    // Members are referenced by the DN of their entry which is not necessarily following the layout
+    let names = users.iter()
+        .map(|(name, record)| (record.dn.normalize(), name.clone()))
+        .collect::<HashMap<_, _>>();
+
+    let mut memberships: HashMap<String, Vec<String>> = HashMap::new();
+    for (group, record) in groups.iter() {
+        for member in ldif::members(record, &names) {
+            memberships.entry(member).or_default().push(group.clone());
+        }
+    }
+
+    let users = users.into_iter()
+        .map(|(name, record)| {
+            let password = password(database, &name, record);
+            let user = ldif::user(record, password, memberships.remove(&name).unwrap_or_default());
+            return (name, user);
+        })
+        .collect();
+
+    let groups = groups.into_iter()
+        .map(|(name, record)| (name, ldif::group(record, &upstream.base_dn)))
+        .collect();
+
+    return (users, groups);
+}
+
+/// Escapes a value placed into a DN (RFC 4514)
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for (i, c) in value.char_indices() {
+        let special = matches!(c, '"' | '+' | ',' | ';' | '<' | '>' | '\\' | '=')
+            || (i == 0 && matches!(c, ' ' | '#'))
+            || (i + c.len_utf8() == value.len() && c == ' ');
+        if special {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    return escaped;
+}
+
+/// Verifies the password of a user by binding to a server, telling rejected credentials apart from failures
+///
+/// The bind DN is given as template where `{name}` is replaced by the escaped name of the user.
+pub async fn verify_bind(url: &str, ca: Option<&Path>, timeout: u64, bind_dn: &str, name: &str, password: &str) -> Result<bool> {
+    // Servers treat binds without a password as anonymous binds, which succeed
+    if password.is_empty() {
+        return Ok(false);
+    }
+
+    let mut client = Client::connect(url, ca, timeout).await?;
+    let res = client.try_bind(&bind_dn.replace("{name}", &escape(name)), password).await?;
+    client.unbind().await;
+
+    return match res.code {
+        LdapResultCode::Success => Ok(true),
+        LdapResultCode::InvalidCredentials => Ok(false),
+        code => bail!("Bind failed: {:?}: {}", code, res.message),
+    };
+}
+
+/// Pulls all users and groups from the upstream server and replaces the content of the database by them
+async fn pull(upstream: &Upstream, database: &SharedDatabase) -> Result<()> {
+    let mut client = Client::connect(&upstream.url, upstream.ca.as_deref(), upstream.timeout).await?;
+
+    if let Some(bind_dn) = &upstream.bind_dn {
+        client.bind(bind_dn, &upstream.password).await?;
+    }
+
+    let users = client.search(&upstream.base_dn, &upstream.users, upstream.page_size).await
+        .context("Searching users")?;
+    let groups = client.search(&upstream.base_dn, &upstream.groups, upstream.page_size).await
+        .context("Searching groups")?;
+
+    client.unbind().await;
+
+    // An empty result is most likely caused by a misconfigured server and would remove all users
+    if users.is_empty() {
+        bail!("No users found");
+    }
+
+    let users = users.into_iter()
+        .map(|entry| record(upstream, entry))
+        .collect::<Result<Vec<_>>>()?;
+    let groups = groups.into_iter()
+        .map(|entry| record(upstream, entry))
+        .collect::<Result<Vec<_>>>()?;
+
+    let actor = Actor {
+        protocol: "upstream",
+        identity: upstream.bind_dn.clone().unwrap_or_else(|| "anonymous".to_owned()),
+        source: upstream.url.clone(),
+    };
+
+    // Verifying and hashing passwords is expensive and done on a snapshot without holding up other writers, which is
+    // current as the database is read-only for clients
+    let snapshot = database.snapshot();
+    let (users, groups) = tokio::task::spawn_blocking({
+        let upstream = upstream.clone();
+        move || convert(&upstream, &snapshot, &users, &groups)
+    }).await.context("Converting entries")?;
+
+    let mut database = database.write().await;
+    let (pulled_users, pulled_groups) = (users.len(), groups.len());
+
+    let changed = database.mirror(&actor, users, groups).await?;
+
+    info!("Pulled {} users and {} groups from {}, changed {} entries", pulled_users, pulled_groups, upstream.url, changed);
+    return Ok(());
+}
+
+/// Mirrors the upstream directory into the database by pulling it periodically
+///
+/// The database is read-only for clients from the start. Failed pulls keep the current content, which is served until
+/// the next pull succeeds. Changed settings apply to the next pull.
+pub async fn mirror(configs: watch::Receiver<Arc<Config>>,
+                    database: Arc<SharedDatabase>,
+                    shutdown: impl Future) -> Result<()> {
+    if configs.borrow().upstream.is_none() {
+        return Ok(());
+    }
+
+    database.set_read_only();
+
+    let mirror = async {
+        loop {
+            let upstream = match configs.borrow().upstream.clone() {
+                Some(upstream) => upstream,
+                None => {
+                    warn!("Upstream removed from config, no longer pulling");
+                    return;
+                }
+            };
+
+            if let Err(err) = pull(&upstream, &database).await {
+                warn!("Failed to pull from {}: {:#}", upstream.url, err);
+            }
+
+            tokio::time::sleep(Duration::from_secs(upstream.interval)).await;
+        }
+    };
+
+    tokio::select! {
+        _ = shutdown => {}
+        _ = mirror => {}
+    }
+
+    return Ok(());
+}
\ No newline at end of file
@@ -1,17 +1,28 @@
 #![feature(const_trait_impl)]
 
-use std::path::PathBuf;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use anyhow::{Context, Result};
 use structopt::StructOpt;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{error, info};
 use tracing::level_filters::LevelFilter;
+use crate::admin::Control;
 use crate::config::Config;
 
-use crate::database::Database;
+use crate::database::{Database, SharedDatabase};
+use crate::messages::Messages;
 
+mod admin;
 mod config;
 mod database;
 mod ldap;
+mod messages;
+mod metrics;
 mod radius;
+mod replication;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "concess", about = "A super simple concession provider")]
@@ -21,6 +32,76 @@ pub struct Opt {
 
     #[structopt(short, long, default_value("concess.yaml"))]
     pub config: PathBuf,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Converts the users and groups of an LDIF file to files in the data directory and exits
+    Import {
+        /// LDIF file, e.g. written by slapcat
+        file: PathBuf,
+    },
+
+    /// Writes all entries of the directory as served to clients to an LDIF file and exits
+    Export {
+        file: PathBuf,
+    },
+
+    /// Writes a consistent copy of the data directory to a timestamped file in the given directory and exits
+    Backup {
+        dir: PathBuf,
+    },
+
+    /// Replaces the content of the data directory by a backup and exits
+    Restore {
+        file: PathBuf,
+    },
+}
+
+/// Re-reads the config and all data directories whenever SIGHUP is received
+///
+/// The changed config of LDAP and RADIUS applies to new connections and requests while listeners, secrets and the
+/// location of data directories are kept until restarted.
+async fn hangup(path: &Path,
+                ldap: watch::Sender<Arc<ldap::Config>>,
+                radius: watch::Sender<Arc<radius::Config>>,
+                databases: Vec<(PathBuf, Arc<SharedDatabase>)>,
+                shutdown: impl Future) -> Result<()> {
+    let mut signals = signal(SignalKind::hangup())
+        .context("Installing SIGHUP handler")?;
+
+    let reload = async {
+        while signals.recv().await.is_some() {
+            info!("Reloading on SIGHUP");
+
+            let config = match Config::load(path).await {
+                Ok(config) => config,
+                Err(err) => {
+                    error!("Keeping current config: {:#}", err);
+                    continue;
+                }
+            };
+
+            for (data, database) in databases.iter() {
+                if let Err(err) = database::reload(data, &config.database, database).await {
+                    error!("Failed to reload database {:?}: {:#}", data, err);
+                }
+            }
+
+            ldap.send_replace(Arc::new(config.ldap));
+            radius.send_replace(Arc::new(config.radius));
+        }
+    };
+
+    tokio::select! {
+        _ = shutdown => {}
+        _ = reload => {}
+    }
+
+    return Ok(());
 }
 
 #[tokio::main]
@@ -39,14 +120,79 @@ async fn main() -> Result<()> {
     let config = Config::load(&opt.config).await
         .with_context(|| format!("Failed to load config: {:?}", &opt.config))?;
 
-    let database = Database::load(&config.data).await
+    let database = Database::load(&config.data, &config.database).await
         .with_context(|| format!("Failed to load database: {:?}", config.data))?;
 
-    let ldap = ldap::serve(config.ldap, database.clone(), tokio::signal::ctrl_c());
+    let control = Arc::new(Control::default());
+
+    let messages = Arc::new(Messages::new(config.messages));
+
+    let mut tenants = Vec::new();
+    for tenant in config.ldap.tenants.iter() {
+        tenants.push(Database::load(&tenant.data, &config.database).await
+            .with_context(|| format!("Failed to load database of tenant {}: {:?}", tenant.base_dn, tenant.data))?);
+    }
+
+    match &opt.command {
+        Some(Command::Import { file }) => {
+            let imported = ldap::import(file, &config.ldap.base_dn, &mut *database.write().await).await
+                .with_context(|| format!("Failed to import LDIF: {:?}", file))?;
+
+            println!("{}", imported);
+            return Ok(());
+        }
+
+        Some(Command::Export { file }) => {
+            let ldif = ldap::export(&config.ldap, &database, &tenants).await;
+            tokio::fs::write(file, ldif).await
+                .with_context(|| format!("Failed to write LDIF: {:?}", file))?;
+
+            return Ok(());
+        }
+
+        Some(Command::Backup { dir }) => {
+            let path = database.snapshot().backup(dir).await
+                .with_context(|| format!("Failed to write backup to {:?}", dir))?;
+
+            println!("{}", path.display());
+            return Ok(());
+        }
+
+        Some(Command::Restore { file }) => {
+            let changed = database.write().await.restore(file).await
+                .with_context(|| format!("Failed to restore backup: {:?}", file))?;
+
+            println!("{}", changed);
+            return Ok(());
+        }
+
+        None => {}
+    }
+
+    let watch_tenants = futures::future::try_join_all(config.ldap.tenants.iter().zip(tenants.iter())
+        .map(|(tenant, database)| database::watch(&tenant.data, &config.database, database.clone(), tokio::signal::ctrl_c())));
+
+    let (ldap_config, ldap_configs) = watch::channel(Arc::new(config.ldap.clone()));
+    let mirror = ldap::mirror(ldap_configs.clone(), database.clone(), tokio::signal::ctrl_c());
+    let ldap = ldap::serve(ldap_configs, database.clone(), tenants.clone(), control.clone(), messages.clone(), tokio::signal::ctrl_c());
     
-    let radius = radius::serve(config.radius, database.clone(), tokio::signal::ctrl_c());
+    let (radius_config, radius_configs) = watch::channel(Arc::new(config.radius));
+    let radius = radius::serve(radius_configs, database.clone(), control.clone(), messages.clone(), tokio::signal::ctrl_c());
+
+    let admin = admin::serve(config.admin, control.clone(), database.clone(), tokio::signal::ctrl_c());
+
+    let metrics = metrics::serve(config.metrics, database.clone(), control.clone(), tokio::signal::ctrl_c());
 
-    tokio::try_join!(ldap, radius)?;
+    let replication = replication::serve(config.replication, database.clone(), tokio::signal::ctrl_c());
+
+    let watch = database::watch(&config.data, &config.database, database.clone(), tokio::signal::ctrl_c());
+
+    let databases = std::iter::once((config.data.clone(), database.clone()))
+        .chain(config.ldap.tenants.iter().map(|tenant| tenant.data.clone()).zip(tenants.iter().cloned()))
+        .collect();
+    let hangup = hangup(&opt.config, ldap_config, radius_config, databases, tokio::signal::ctrl_c());
+
+    tokio::try_join!(ldap, mirror, radius, admin, metrics, replication, watch, watch_tenants, hangup)?;
 
     return Ok(());
-}
+}
\ No newline at end of file
@@ -0,0 +1,30 @@
+use std::collections::BTreeMap;
+use serde::Deserialize;
+
+use super::Message;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Locale used to look up messages in the catalog
+    #[serde(default = "Config::default_locale")]
+    pub locale: String,
+
+    /// Message templates per locale overriding the built-in messages
+    #[serde(default)]
+    pub catalog: BTreeMap<String, BTreeMap<Message, String>>,
+}
+
+impl Config {
+    fn default_locale() -> String {
+        return "en".to_string();
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Self {
+            locale: Self::default_locale(),
+            catalog: BTreeMap::new(),
+        };
+    }
+}
\ No newline at end of file
@@ -0,0 +1,93 @@
+use serde::Deserialize;
+
+pub use self::config::Config;
+
+mod config;
+
+/// Locale of the built-in messages and fallback for messages missing in the configured locale
+const FALLBACK_LOCALE: &str = "en";
+
+/// Messages presented to clients and their users
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Message {
+    Maintenance,
+    InvalidCredentials,
+    BindNotPermitted,
+    AccountDisabled,
+    AccountLocked,
+    AccountPending,
+    AccountExpired,
+    SizeLimitExceeded,
+    TimeLimitExceeded,
+    OutsideNamingContext,
+    AccessAccepted,
+    AccessRejected,
+}
+
+impl Message {
+    fn builtin(&self) -> &'static str {
+        return match self {
+            Message::Maintenance => "Server is in maintenance mode",
+            Message::InvalidCredentials => "",
+            Message::BindNotPermitted => "",
+            Message::AccountDisabled => "Account is disabled",
+            Message::AccountLocked => "Account is locked until {until}",
+            Message::AccountPending => "Account is not valid before {since}",
+            Message::AccountExpired => "Account expired at {until}",
+            Message::SizeLimitExceeded => "Search exceeded size limit",
+            Message::TimeLimitExceeded => "Search exceeded time limit",
+            Message::OutsideNamingContext => "Base outside of naming context",
+            Message::AccessAccepted => "",
+            Message::AccessRejected => "",
+        };
+    }
+}
+
+/// Catalog of message templates
+///
+/// Templates can refer to arguments by name using `{name}`.
+#[derive(Debug, Clone, Default)]
+pub struct Messages {
+    config: Config,
+}
+
+impl Messages {
+    pub fn new(config: Config) -> Self {
+        return Self { config };
+    }
+
+    fn template(&self, message: Message) -> &str {
+        return [self.config.locale.as_str(), FALLBACK_LOCALE].iter()
+            .find_map(|locale| self.config.catalog.get(*locale)?.get(&message))
+            .map(String::as_str)
+            .unwrap_or_else(|| message.builtin());
+    }
+
+    pub fn render(&self, message: Message, args: &[(&str, &str)]) -> String {
+        return args.iter()
+            .fold(self.template(message).to_string(), |text, (name, value)| text.replace(&format!("{{{}}}", name), value));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let messages = Messages::new(Config {
+            locale: "de".to_string(),
+            catalog: BTreeMap::from([
+                ("de".to_string(), BTreeMap::from([(Message::AccessRejected, "Zugriff für {user} verweigert".to_string())])),
+                ("en".to_string(), BTreeMap::from([(Message::AccessAccepted, "Welcome, {user}".to_string())])),
+            ]),
+        });
+
+        assert_eq!(messages.render(Message::AccessRejected, &[("user", "pinky")]), "Zugriff für pinky verweigert");
+        assert_eq!(messages.render(Message::AccessAccepted, &[("user", "brain")]), "Welcome, brain");
+        assert_eq!(messages.render(Message::Maintenance, &[]), "Server is in maintenance mode");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,8 @@
+use std::net::SocketAddr;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Address of the HTTP listener serving metrics in the Prometheus text format
+    pub listen: SocketAddr,
+}
\ No newline at end of file
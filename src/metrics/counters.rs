@@ -0,0 +1,23 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Label attached to a counter
+pub type Label = (&'static str, &'static str);
+
+/// Monotonic event counters exported as metrics
+#[derive(Debug, Default)]
+pub struct Counters {
+    counters: Mutex<BTreeMap<(&'static str, Option<Label>), u64>>,
+}
+
+impl Counters {
+    pub fn increment(&self, name: &'static str, label: Option<Label>) {
+        *self.counters.lock().unwrap()
+            .entry((name, label))
+            .or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<(&'static str, Option<Label>), u64> {
+        return self.counters.lock().unwrap().clone();
+    }
+}
\ No newline at end of file
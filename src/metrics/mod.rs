@@ -0,0 +1,175 @@
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, error, info};
+
+use crate::admin::Control;
+use crate::database::{Database, SharedDatabase};
+
+pub use self::config::Config;
+pub use self::counters::Counters;
+
+mod config;
+mod counters;
+
+/// Maximum size of a request head accepted from a scraper
+const MAX_REQUEST: usize = 8192;
+
+struct Metrics {
+    out: String,
+}
+
+fn escape(value: &str) -> String {
+    return value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+}
+
+impl Metrics {
+    fn gauge(&mut self, name: &str, help: &str, values: impl IntoIterator<Item=(Option<(&'static str, String)>, usize)>) {
+        writeln!(self.out, "# HELP concess_{} {}", name, help).unwrap();
+        writeln!(self.out, "# TYPE concess_{} gauge", name).unwrap();
+
+        for (label, value) in values {
+            match label {
+                Some((label, label_value)) => writeln!(self.out, "concess_{}{{{}=\"{}\"}} {}", name, label, escape(&label_value), value),
+                None => writeln!(self.out, "concess_{} {}", name, value),
+            }.unwrap();
+        }
+    }
+
+    fn counters(&mut self, counters: &Counters) {
+        for (name, values) in &counters.snapshot().into_iter().group_by(|((name, _), _)| *name) {
+            writeln!(self.out, "# TYPE concess_{}_total counter", name).unwrap();
+
+            for ((_, label), value) in values {
+                match label {
+                    Some((label, label_value)) => writeln!(self.out, "concess_{}_total{{{}=\"{}\"}} {}", name, label, escape(label_value), value),
+                    None => writeln!(self.out, "concess_{}_total {}", name, value),
+                }.unwrap();
+            }
+        }
+    }
+}
+
+/// Renders the event counters and gauges describing the health of the database content
+///
+/// Canary users are left out as they exist for detecting abuse only.
+pub fn render(database: &Database, control: &Control) -> String {
+    let mut metrics = Metrics { out: String::new() };
+
+    let users = database.users()
+        .filter(|user| !user.canary)
+        .collect::<Vec<_>>();
+
+    let groups = database.groups()
+        .collect::<Vec<_>>();
+
+    metrics.gauge("users", "Number of users",
+                  [(None, users.len())]);
+
+    metrics.gauge("users_without_groups", "Number of users not member of any group",
+                  [(None, users.iter().filter(|user| user.groups.is_empty()).count())]);
+
+    metrics.gauge("users_without_mail", "Number of users without a mail address",
+                  [(None, users.iter().filter(|user| user.mail.iter().all(|mail| mail.trim().is_empty())).count())]);
+
+    metrics.gauge("users_by_scheme", "Number of users per password hash scheme",
+                  users.iter()
+                      .map(|user| user.password.algorithm().to_string())
+                      .counts()
+                      .into_iter()
+                      .sorted()
+                      .map(|(scheme, count)| (Some(("scheme", scheme)), count)));
+
+    metrics.gauge("groups", "Number of groups",
+                  [(None, groups.len())]);
+
+    metrics.gauge("groups_without_members", "Number of groups without members besides canary users",
+                  [(None, groups.iter().filter(|group| group.members().all(|user| user.canary)).count())]);
+
+    metrics.gauge("skipped_files", "Number of entity files skipped while loading",
+                  [(None, database.report().skipped.len())]);
+
+    metrics.gauge("duplicates", "Number of values expected to be unique but used multiple times",
+                  [(None, database.report().duplicates.len())]);
+
+    metrics.gauge("problems", "Number of values which are most likely mistakes like references to unknown groups",
+                  [(None, database.report().problems.len())]);
+
+    metrics.counters(control.counters());
+
+    return metrics.out;
+}
+
+async fn serve_client(mut socket: TcpStream, database: Arc<SharedDatabase>, control: Arc<Control>) -> Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    // Read the request head only, the path and method are ignored
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = socket.read(&mut buf).await?;
+        if n == 0 || request.len() + n > MAX_REQUEST {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let body = render(&*database.snapshot(), &control);
+
+    socket.write_all(format!("HTTP/1.1 200 OK\r\n\
+                              Content-Type: text/plain; version=0.0.4\r\n\
+                              Content-Length: {}\r\n\
+                              Connection: close\r\n\
+                              \r\n", body.len()).as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await?;
+    socket.shutdown().await?;
+
+    return Ok(());
+}
+
+pub async fn serve(config: Option<Config>,
+                   database: Arc<SharedDatabase>,
+                   control: Arc<Control>,
+                   shutdown: impl Future) -> Result<()> {
+    let config = if let Some(config) = config { config } else {
+        return Ok(());
+    };
+
+    let listener = TcpListener::bind(config.listen).await
+        .with_context(|| format!("Listening on {}", config.listen))?;
+
+    let serve = async {
+        loop {
+            match listener.accept().await {
+                Ok((socket, addr)) => {
+                    let database = database.clone();
+                    let control = control.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = serve_client(socket, database, control).await {
+                            debug!("Failed to serve metrics to {}: {}", addr, err);
+                        }
+                    });
+                }
+
+                Err(err) => {
+                    error!("Failed to accept metrics connection: {}", err);
+                }
+            }
+        }
+    };
+
+    tokio::select! {
+         _ = shutdown => {
+            info!("Metrics listener is shutting down");
+            return Ok(());
+        }
+
+        res = serve => {
+            return res;
+        }
+    }
+}
\ No newline at end of file
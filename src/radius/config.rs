@@ -1,12 +1,73 @@
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
 use serde::{Deserialize, Deserializer};
 
+use super::huntgroup::Huntgroup;
+use super::proxy::Upstream;
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub listen: SocketAddr,
 
     #[serde(deserialize_with = "deserialize_secret")]
     pub secret: Vec<u8>,
+
+    #[serde(default)]
+    pub huntgroups: BTreeMap<String, Huntgroup>,
+
+    pub group_attribute: Option<GroupAttribute>,
+
+    /// Handling of authentication requests while in maintenance mode
+    #[serde(default)]
+    pub maintenance: MaintenanceBehavior,
+
+    /// Handling of authentication requests for user names not in the database
+    #[serde(default)]
+    pub unknown_user: UnknownUserBehavior,
+
+    /// Requires users having a TOTP secret to append the current one-time password to their password
+    #[serde(default)]
+    pub totp: bool,
+
+    /// Discard packets with malformed or missing attributes instead of processing them leniently
+    #[serde(default)]
+    pub strict: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownUserBehavior {
+    #[default]
+    Reject,
+    Drop,
+    Proxy(Upstream),
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MaintenanceBehavior {
+    #[default]
+    Reject,
+    Ignore,
+}
+
+/// Attribute used to announce group membership in Access-Accept responses
+#[derive(Debug, Clone, Deserialize)]
+pub struct GroupAttribute {
+    #[serde(rename = "type", default)]
+    pub kind: GroupAttributeKind,
+
+    /// Prefix prepended to each group name
+    #[serde(default)]
+    pub prefix: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GroupAttributeKind {
+    #[default]
+    Class,
+    FilterId,
 }
 
 fn deserialize_secret<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
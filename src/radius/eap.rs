@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use radius::core::packet::Packet;
+use radius::core::rfc2869;
+use rand_core::{OsRng, RngCore};
+
+use super::strict::add_message_authenticator;
+
+/// Codes of EAP packets (RFC 3748)
+pub const CODE_REQUEST: u8 = 1;
+pub const CODE_RESPONSE: u8 = 2;
+pub const CODE_SUCCESS: u8 = 3;
+pub const CODE_FAILURE: u8 = 4;
+
+/// Types of EAP requests and responses (RFC 3748)
+pub const TYPE_IDENTITY: u8 = 1;
+pub const TYPE_MD5_CHALLENGE: u8 = 4;
+
+/// Time a client has to answer a challenge
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A single EAP packet as carried in the EAP-Message attributes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub code: u8,
+    pub identifier: u8,
+
+    /// Type of requests and responses followed by the type data, empty for success and failure
+    pub data: Vec<u8>,
+}
+
+impl Message {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 {
+            return None;
+        }
+
+        let length = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if length < 4 || length > data.len() {
+            return None;
+        }
+
+        let message = Self {
+            code: data[0],
+            identifier: data[1],
+            data: data[4..length].to_vec(),
+        };
+
+        if matches!(message.code, CODE_REQUEST | CODE_RESPONSE) && message.data.is_empty() {
+            return None;
+        }
+
+        return Some(message);
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let length = (4 + self.data.len()) as u16;
+        return [&[self.code, self.identifier][..], &length.to_be_bytes(), &self.data].concat();
+    }
+
+    /// Type of a request or response
+    pub fn typ(&self) -> Option<u8> {
+        return self.data.first().copied();
+    }
+
+    /// Value of an MD5-Challenge request or response, ignoring the name following it
+    pub fn md5_value(&self) -> Option<&[u8]> {
+        if self.typ() != Some(TYPE_MD5_CHALLENGE) {
+            return None;
+        }
+
+        let size = *self.data.get(1)? as usize;
+        return self.data.get(2..2 + size);
+    }
+
+    pub fn success(identifier: u8) -> Self {
+        return Self { code: CODE_SUCCESS, identifier, data: vec![] };
+    }
+
+    pub fn failure(identifier: u8) -> Self {
+        return Self { code: CODE_FAILURE, identifier, data: vec![] };
+    }
+}
+
+/// Expected response of an MD5-Challenge (RFC 3748, section 5.4)
+pub fn md5_response(identifier: u8, password: &[u8], challenge: &[u8]) -> [u8; 16] {
+    return md5::compute([&[identifier][..], password, challenge].concat()).0;
+}
+
+/// Challenge sent to a client, remembered until the client responds
+#[derive(Debug)]
+pub struct Challenge {
+    pub username: String,
+    pub identifier: u8,
+    pub value: [u8; 16],
+    expires: Instant,
+}
+
+/// Conversations waiting for the response of a client, identified by the State attribute
+#[derive(Default)]
+pub struct Conversations {
+    pending: Mutex<HashMap<Vec<u8>, Challenge>>,
+}
+
+impl Conversations {
+    /// Creates a new MD5-Challenge request and returns it together with the state identifying the conversation
+    pub fn challenge(&self, username: &str, identifier: u8) -> (Vec<u8>, Message) {
+        let mut state = vec![0u8; 16];
+        OsRng.fill_bytes(&mut state);
+
+        let mut value = [0u8; 16];
+        OsRng.fill_bytes(&mut value);
+
+        let message = Message {
+            code: CODE_REQUEST,
+            identifier,
+            data: [&[TYPE_MD5_CHALLENGE, value.len() as u8][..], &value].concat(),
+        };
+
+        let now = Instant::now();
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, challenge| challenge.expires > now);
+        pending.insert(state.clone(), Challenge {
+            username: username.to_owned(),
+            identifier,
+            value,
+            expires: now + TIMEOUT,
+        });
+
+        return (state, message);
+    }
+
+    /// Ends the conversation identified by the state and returns its challenge unless expired
+    pub fn take(&self, state: &[u8]) -> Option<Challenge> {
+        return self.pending.lock().unwrap()
+            .remove(state)
+            .filter(|challenge| challenge.expires > Instant::now());
+    }
+}
+
+/// Attaches the EAP message and signs the response with a Message-Authenticator as required by RFC 3579
+pub fn respond(response: &mut Packet, message: &Message) {
+    rfc2869::add_eap_message(response, &message.encode());
+    add_message_authenticator(response);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        let message = Message::parse(&[2, 7, 0, 10, 1, b'b', b'r', b'a', b'i', b'n', 0xff]).unwrap();
+        assert_eq!(message, Message { code: CODE_RESPONSE, identifier: 7, data: b"\x01brain".to_vec() });
+        assert_eq!(message.typ(), Some(TYPE_IDENTITY));
+        assert_eq!(message.encode(), [2, 7, 0, 10, 1, b'b', b'r', b'a', b'i', b'n']);
+
+        assert_eq!(Message::parse(&Message::success(8).encode()), Some(Message::success(8)));
+        assert_eq!(Message::parse(&[2, 7, 0, 11, 1]), None);
+        assert_eq!(Message::parse(&[2, 7, 0, 4]), None);
+    }
+
+    #[test]
+    fn test_conversations() {
+        let conversations = Conversations::default();
+        let (state, request) = conversations.challenge("brain", 8);
+
+        let challenge = conversations.take(&state).unwrap();
+        assert_eq!(request.md5_value(), Some(&challenge.value[..]));
+        assert_eq!(challenge.username, "brain");
+        assert!(conversations.take(&state).is_none());
+    }
+}
\ No newline at end of file
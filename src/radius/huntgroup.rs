@@ -0,0 +1,67 @@
+use std::net::{IpAddr, SocketAddr};
+
+use radius::core::packet::Packet;
+use radius::core::rfc2865;
+use serde::Deserialize;
+
+use crate::database::User;
+
+/// A named set of NAS clients and ports, mirroring the huntgroups known from FreeRADIUS
+#[derive(Debug, Clone, Deserialize)]
+pub struct Huntgroup {
+    /// Addresses of the NAS clients in this group, matched against NAS-IP-Address or the source address
+    #[serde(default)]
+    pub clients: Vec<IpAddr>,
+
+    /// NAS-Identifier values of the NAS clients in this group
+    #[serde(default)]
+    pub identifiers: Vec<String>,
+
+    /// NAS-Port values to restrict this group to - all ports if empty
+    #[serde(default)]
+    pub ports: Vec<u32>,
+
+    /// Groups a user must be member of (any of) to authenticate through this huntgroup
+    pub groups: Option<Vec<String>>,
+}
+
+impl Huntgroup {
+    /// Checks whether the request originates from a NAS client and port in this huntgroup
+    pub fn matches(&self, request: &Packet, remote_addr: SocketAddr) -> bool {
+        let addr = match rfc2865::lookup_nas_ip_address(request) {
+            Some(Ok(addr)) => IpAddr::V4(addr),
+            _ => remote_addr.ip(),
+        };
+
+        let identifier = rfc2865::lookup_nas_identifier(request)
+            .and_then(Result::ok);
+
+        let client = self.clients.contains(&addr)
+            || identifier.map_or(false, |identifier| self.identifiers.contains(&identifier));
+        if !client {
+            return false;
+        }
+
+        if self.ports.is_empty() {
+            return true;
+        }
+
+        return match rfc2865::lookup_nas_port(request) {
+            Some(Ok(port)) => self.ports.contains(&port),
+            _ => false,
+        };
+    }
+
+    /// Checks whether the user is allowed to authenticate through this huntgroup
+    pub fn permits(&self, user: &User) -> bool {
+        return match &self.groups {
+            Some(groups) => user.groups.iter().any(|group| groups.contains(group)),
+            None => true,
+        };
+    }
+
+    /// Checks whether services, which are not member of any group, are allowed to authenticate through this huntgroup
+    pub fn permits_services(&self) -> bool {
+        return self.groups.is_none();
+    }
+}
\ No newline at end of file
@@ -7,17 +7,26 @@ use async_trait::async_trait;
 use radius::core::code::Code;
 use radius::core::packet::Packet;
 use radius::core::request::Request;
-use radius::core::rfc2865;
+use radius::core::{rfc2865, rfc2866, rfc2869};
 use radius::server::{RequestHandler, SecretProvider, SecretProviderError, Server};
 use tokio::net::UdpSocket;
-use tokio::sync::RwLock;
-use tracing::warn;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
 
-use crate::Database;
+use crate::admin::Control;
+use crate::database::{Actor, CANARY, User};
+use crate::database::SharedDatabase;
+use crate::messages::{Message, Messages};
 
 pub use self::config::Config;
+use self::config::{GroupAttributeKind, MaintenanceBehavior, UnknownUserBehavior};
+use self::huntgroup::Huntgroup;
 
 mod config;
+mod eap;
+mod huntgroup;
+mod proxy;
+mod strict;
 
 impl SecretProvider for Config {
     fn fetch_secret(&self, remote_addr: SocketAddr) -> Result<Vec<u8>, SecretProviderError> {
@@ -26,25 +35,259 @@ impl SecretProvider for Config {
 }
 
 struct Handler {
-    database: Arc<RwLock<Database>>,
+    /// The current config, picked up by each request
+    configs: watch::Receiver<Arc<Config>>,
+    database: Arc<SharedDatabase>,
+    control: Arc<Control>,
+    messages: Arc<Messages>,
+
+    /// EAP conversations waiting for the next request of a client
+    conversations: eap::Conversations,
+}
+
+/// Credentials given in an Access-Request
+enum Credentials {
+    Password(Vec<u8>),
+    Eap(Vec<u8>),
 }
 
 impl Handler {
-    async fn handle_auth_request(&self, conn: &UdpSocket, request: &Packet) -> Result<Packet> {
+    /// Attaches the rendered message as Reply-Message unless it renders empty
+    fn add_reply_message(&self, response: &mut Packet, message: Message, username: &str) {
+        let text = self.messages.render(message, &[("user", username)]);
+        if !text.is_empty() {
+            rfc2865::add_reply_message(response, &text);
+        }
+    }
+
+    /// Reply message rejecting a user whose account is disabled, locked or outside of its validity
+    fn suspension(&self, user: &User) -> Option<String> {
+        if user.disabled {
+            return Some(self.messages.render(Message::AccountDisabled, &[("user", user.name)]));
+        }
+
+        if let Some(until) = user.locked() {
+            return Some(self.messages.render(Message::AccountLocked, &[("user", user.name), ("until", &until.to_rfc3339())]));
+        }
+
+        if let Some(since) = user.pending() {
+            return Some(self.messages.render(Message::AccountPending, &[("user", user.name), ("since", &since.to_rfc3339())]));
+        }
+
+        return user.expired()
+            .map(|until| self.messages.render(Message::AccountExpired, &[("user", user.name), ("until", &until.to_rfc3339())]));
+    }
+
+    /// Responds to a user whose credentials have been verified, rejecting the user if suspended
+    fn authorize(&self, config: &Config, request: &Packet, user: &User) -> Packet {
+        if let Some(suspension) = self.suspension(user) {
+            debug!("Rejecting suspended user {}", user.name);
+            let mut response = request.make_response_packet(Code::AccessReject);
+            if !suspension.is_empty() {
+                rfc2865::add_reply_message(&mut response, &suspension);
+            }
+            return response;
+        }
+
+        let mut response = request.make_response_packet(Code::AccessAccept);
+
+        if let Some(attribute) = &config.group_attribute {
+            for group in user.groups.iter() {
+                let value = format!("{}{}", attribute.prefix, group);
+                match attribute.kind {
+                    GroupAttributeKind::Class => rfc2865::add_class(&mut response, value.as_bytes()),
+                    GroupAttributeKind::FilterId => rfc2865::add_filter_id(&mut response, &value),
+                }
+            }
+        }
+
+        self.add_reply_message(&mut response, Message::AccessAccepted, user.name);
+
+        return response;
+    }
+
+    /// Runs EAP-MD5 (RFC 3748) by challenging the identity announced by the client and verifying the response
+    ///
+    /// Requests without a valid Message-Authenticator are discarded as required by RFC 3579. Any other EAP method
+    /// proposed by the client is rejected.
+    fn handle_eap(&self,
+                  config: &Config,
+                  request: &Packet,
+                  remote_addr: SocketAddr,
+                  username: &str,
+                  huntgroups: &[(&String, &Huntgroup)],
+                  message: &[u8]) -> Option<Packet> {
+        if request.lookup(rfc2869::MESSAGE_AUTHENTICATOR_TYPE).is_none() || strict::validate_message_authenticator(request).is_err() {
+            debug!("Discarding EAP request from {} without valid Message-Authenticator", remote_addr);
+            return None;
+        }
+
+        let message = match eap::Message::parse(message) {
+            Some(message) if message.code == eap::CODE_RESPONSE => message,
+            _ => {
+                debug!("Discarding invalid EAP message from {}", remote_addr);
+                return None;
+            }
+        };
+
+        let state = rfc2865::lookup_state(request);
+
+        if message.typ() == Some(eap::TYPE_IDENTITY) && state.is_none() {
+            let (state, challenge) = self.conversations.challenge(username, message.identifier.wrapping_add(1));
+
+            let mut response = request.make_response_packet(Code::AccessChallenge);
+            rfc2865::add_state(&mut response, &state);
+            eap::respond(&mut response, &challenge);
+            return Some(response);
+        }
+
+        let challenge = state.and_then(|state| self.conversations.take(&state))
+            .filter(|challenge| challenge.username == username && challenge.identifier == message.identifier);
+        let value = message.md5_value();
+
+        let database = self.database.snapshot();
+        let user = match (challenge, value) {
+            (Some(challenge), Some(value)) => database.user(username)
+                .filter(|user| huntgroups.iter().all(|(_, huntgroup)| huntgroup.permits(user)))
+                .filter(|user| {
+                    let verified = user.eap_password.map_or(false, |password| {
+                        eap::md5_response(challenge.identifier, password.as_bytes(), &challenge.value)[..] == value[..]
+                    });
+                    if user.canary {
+                        info!(target: CANARY, "EAP-MD5 Access-Request for canary {} from {}: {}", user.name, remote_addr, if verified { "success" } else { "failure" });
+                    }
+                    return verified;
+                }),
+            _ => None,
+        };
+
+        let (mut response, result) = match user {
+            Some(user) => {
+                let response = self.authorize(config, request, &user);
+                let result = match response.get_code() {
+                    Code::AccessAccept => eap::Message::success(message.identifier),
+                    _ => eap::Message::failure(message.identifier),
+                };
+                (response, result)
+            }
+            None => {
+                let mut response = request.make_response_packet(Code::AccessReject);
+                self.add_reply_message(&mut response, Message::AccessRejected, username);
+                (response, eap::Message::failure(message.identifier))
+            }
+        };
+
+        eap::respond(&mut response, &result);
+        return Some(response);
+    }
+
+    async fn handle_auth_request(&self, conn: &UdpSocket, request: &Packet, remote_addr: SocketAddr) -> Result<Option<Packet>> {
+        let config = self.configs.borrow().clone();
+
         let username = rfc2865::lookup_user_name(request);
-        let password = rfc2865::lookup_user_password(request);
-
-        if let (Some(Ok(username)), Some(Ok(password))) = (username, password) {
-            let database = self.database.read().await;
-            let user = database.users()
-                .find(|user| user.name == username)
-                .filter(|user| user.verify_password(&password));
-            if user.is_some() {
-                return Ok(request.make_response_packet(Code::AccessAccept));
+        let credentials = match (rfc2865::lookup_user_password(request), rfc2869::lookup_eap_message(request)) {
+            (_, Some(message)) => Some(Credentials::Eap(message)),
+            (Some(Ok(password)), None) => Some(Credentials::Password(password)),
+            _ => None,
+        };
+
+        let huntgroups = config.huntgroups.iter()
+            .filter(|(_, huntgroup)| huntgroup.matches(request, remote_addr))
+            .collect::<Vec<_>>();
+        debug!("Request from {} matches huntgroups: {:?}", remote_addr, huntgroups.iter().map(|(name, _)| name).collect::<Vec<_>>());
+
+        if let (Some(Ok(username)), Some(credentials)) = (username, credentials) {
+            let known = {
+                let database = self.database.snapshot();
+                database.user(&username).is_some()
+                    || database.service(&username).map_or(false, |service| service.radius)
+            };
+            if !known {
+                match &config.unknown_user {
+                    UnknownUserBehavior::Reject => {}
+                    UnknownUserBehavior::Drop => {
+                        debug!("Dropping request for unknown user {}", username);
+                        return Ok(None);
+                    }
+                    UnknownUserBehavior::Proxy(upstream) => {
+                        debug!("Proxying request for unknown user {} to {}", username, upstream.server);
+                        return Ok(Some(upstream.forward(request).await?));
+                    }
+                }
+            }
+
+            let password = match credentials {
+                Credentials::Password(password) => password,
+                Credentials::Eap(message) => return Ok(self.handle_eap(&config, request, remote_addr, &username, &huntgroups, &message)),
+            };
+
+            let database = self.database.snapshot();
+            let mut user = database.user(&username)
+                .filter(|user| huntgroups.iter().all(|(_, huntgroup)| huntgroup.permits(user)));
+            if let Some(candidate) = &user {
+                let verified = match config.totp {
+                    true => candidate.verify_password_with_otp(&password).await,
+                    false => candidate.verify_password(&password).await,
+                };
+                if candidate.canary {
+                    info!(target: CANARY, "Access-Request for canary {} from {}: {}", candidate.name, remote_addr, if verified { "success" } else { "failure" });
+                }
+                if !verified {
+                    user = None;
+                }
+            }
+            if let Some(user) = user {
+                let response = self.authorize(&config, request, &user);
+                if response.get_code() != Code::AccessAccept {
+                    return Ok(Some(response));
+                }
+
+                let actor = Actor {
+                    protocol: "radius",
+                    identity: user.name.to_owned(),
+                    source: remote_addr.to_string(),
+                };
+                let password = match config.totp {
+                    true => user.strip_otp(&password),
+                    false => &password,
+                };
+                self.database.upgrade_password(actor, user.name, user.password, password);
+
+                return Ok(Some(response));
+            }
+
+            // Services are only accepted if permitted to use RADIUS at all and never get any groups
+            let service = database.service(&username)
+                .filter(|service| service.radius)
+                .filter(|_| huntgroups.iter().all(|(_, huntgroup)| huntgroup.permits_services()))
+                .filter(|service| service.verify_password(&password));
+            if let Some(service) = service {
+                let mut response = request.make_response_packet(Code::AccessAccept);
+                self.add_reply_message(&mut response, Message::AccessAccepted, service.name);
+                return Ok(Some(response));
             }
+
+            let mut response = request.make_response_packet(Code::AccessReject);
+            self.add_reply_message(&mut response, Message::AccessRejected, &username);
+            return Ok(Some(response));
         }
 
-        return Ok(request.make_response_packet(Code::AccessReject));
+        return Ok(Some(request.make_response_packet(Code::AccessReject)));
+    }
+
+    async fn handle_acct_request(&self, request: &Packet) -> Result<Packet> {
+        let username = rfc2865::lookup_user_name(request).and_then(Result::ok);
+        let session = rfc2866::lookup_acct_session_id(request).and_then(Result::ok);
+        let status = rfc2866::lookup_acct_status_type(request).and_then(Result::ok);
+
+        // Class attributes are echoed by the NAS and allow correlating the session with the granted groups
+        let classes = rfc2865::lookup_all_class(request).into_iter()
+            .map(|class| String::from_utf8_lossy(&class).into_owned())
+            .collect::<Vec<_>>();
+
+        info!("Accounting {:?} for session {:?} of user {:?} with classes {:?}", status, session, username, classes);
+
+        return Ok(request.make_response_packet(Code::AccountingResponse));
     }
 }
 
@@ -52,9 +295,35 @@ impl Handler {
 impl RequestHandler<(), Error> for Handler {
     async fn handle_radius_request(&self, conn: &UdpSocket, request: &Request) -> Result<(), Error> {
         let packet = request.get_packet();
+        let config = self.configs.borrow().clone();
+
+        if config.strict {
+            if let Err(malformed) = strict::validate(packet) {
+                warn!(reason = malformed.reason(), "Discarding malformed {:?} from {}: {}", packet.get_code(), request.get_remote_addr(), malformed);
+                self.control.counters().increment("radius_malformed_packets", Some(("reason", malformed.reason())));
+                return Ok(());
+            }
+        }
 
         let response = match packet.get_code() {
-            Code::AccessRequest => self.handle_auth_request(conn, packet).await?,
+            Code::AccessRequest if self.control.maintenance() => match config.maintenance {
+                MaintenanceBehavior::Reject => {
+                    let mut response = packet.make_response_packet(Code::AccessReject);
+                    let username = rfc2865::lookup_user_name(packet).and_then(Result::ok).unwrap_or_default();
+                    self.add_reply_message(&mut response, Message::Maintenance, &username);
+                    response
+                }
+                MaintenanceBehavior::Ignore => {
+                    debug!("Ignoring request in maintenance mode");
+                    return Ok(());
+                }
+            },
+
+            Code::AccessRequest => match self.handle_auth_request(conn, packet, request.get_remote_addr()).await? {
+                Some(response) => response,
+                None => return Ok(()),
+            },
+            Code::AccountingRequest => self.handle_acct_request(packet).await?,
 
             _ => {
                 warn!("Unhandled packet: {:?}", packet.get_code());
@@ -67,12 +336,25 @@ impl RequestHandler<(), Error> for Handler {
     }
 }
 
-pub async fn serve(config: Config,
-                   database: Arc<RwLock<Database>>,
+/// Serves requests until shutdown, the listener and the secret are taken from the config at startup
+pub async fn serve(configs: watch::Receiver<Arc<Config>>,
+                   database: Arc<SharedDatabase>,
+                   control: Arc<Control>,
+                   messages: Arc<Messages>,
                    shutdown: impl Future) -> Result<()> {
+    let config = Config::clone(&configs.borrow());
+
+    let handler = Handler {
+        configs,
+        database,
+        control,
+        messages,
+        conversations: eap::Conversations::default(),
+    };
+
     let mut server = Server::listen(&config.listen.ip().to_string(), // TODO: This is stupid
                                     config.listen.port(),
-                                    Handler { database },
+                                    handler,
                                     config.clone()).await // TODO: Get rid of the clone
         .with_context(|| format!("Failed to listen: {}", config.listen))?;
 
@@ -0,0 +1,209 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use radius::client::Client;
+use radius::core::avp::AVP;
+use radius::core::code::Code;
+use radius::core::packet::Packet;
+use radius::core::{rfc2865, rfc2868, rfc2869};
+use serde::{Deserialize, Deserializer};
+use tracing::warn;
+
+use super::strict::add_message_authenticator;
+
+/// Vendor-Specific attributes of Microsoft carrying the MS-MPPE keys hidden using the secret (RFC 2548)
+const VENDOR_MICROSOFT: u32 = 311;
+const MS_MPPE_SEND_KEY: u8 = 16;
+const MS_MPPE_RECV_KEY: u8 = 17;
+
+/// An upstream RADIUS server requests can be proxied to
+#[derive(Debug, Clone, Deserialize)]
+pub struct Upstream {
+    pub server: SocketAddr,
+
+    #[serde(deserialize_with = "deserialize_secret")]
+    pub secret: Vec<u8>,
+
+    /// Seconds to wait for the upstream server to respond
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    return 5;
+}
+
+fn deserialize_secret<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    return Ok(s.into_bytes());
+}
+
+/// Translates a value hidden by salt encryption (RFC 2868, section 3.5) from one secret and request authenticator to another
+fn reencrypt(value: &[u8], from: (&[u8], &[u8]), to: (&[u8], &[u8])) -> Option<Vec<u8>> {
+    if value.len() < 2 + 16 || (value.len() - 2) % 16 != 0 {
+        return None;
+    }
+
+    let (salt, cipher) = value.split_at(2);
+
+    // Each block is chained to the previous cipher block, the first one to the request authenticator and salt
+    let mut chain_from = [from.1, salt].concat();
+    let mut chain_to = [to.1, salt].concat();
+
+    let mut result = salt.to_vec();
+    for block in cipher.chunks(16) {
+        let key_from = md5::compute([from.0, &chain_from].concat());
+        let key_to = md5::compute([to.0, &chain_to].concat());
+
+        let encrypted = block.iter().zip(key_from.0).zip(key_to.0)
+            .map(|((b, f), t)| b ^ f ^ t)
+            .collect::<Vec<u8>>();
+
+        chain_from = block.to_vec();
+        chain_to = encrypted.clone();
+        result.extend(encrypted);
+    }
+
+    return Some(result);
+}
+
+/// Translates the MS-MPPE keys in Vendor-Specific attributes of Microsoft, other vendors are kept verbatim
+fn reencrypt_vendor_specific(value: &[u8], from: (&[u8], &[u8]), to: (&[u8], &[u8])) -> Option<Vec<u8>> {
+    if value.len() < 4 || u32::from_be_bytes([value[0], value[1], value[2], value[3]]) != VENDOR_MICROSOFT {
+        return Some(value.to_vec());
+    }
+
+    let mut result = value[..4].to_vec();
+    let mut rest = &value[4..];
+    while !rest.is_empty() {
+        let (typ, len) = (rest[0], *rest.get(1)? as usize);
+        if len < 2 || len > rest.len() {
+            return None;
+        }
+
+        let data = &rest[2..len];
+        let data = match typ {
+            MS_MPPE_SEND_KEY | MS_MPPE_RECV_KEY => reencrypt(data, from, to)?,
+            _ => data.to_vec(),
+        };
+
+        result.extend([typ, (data.len() + 2) as u8]);
+        result.extend(data);
+        rest = &rest[len..];
+    }
+
+    return Some(result);
+}
+
+/// Copies all attributes to a packet with another secret, those hidden using the secret and the request authenticator
+/// of the source packet are translated while Message-Authenticator and User-Password are left to the caller
+fn copy_attributes(from: &Packet, authenticator: &[u8], to: &mut Packet) {
+    let from_key = (from.get_secret().as_slice(), authenticator);
+    let to_key = (to.get_secret().as_slice(), to.get_authenticator().as_slice());
+
+    let mut attributes = Vec::new();
+    for typ in 1..=255 {
+        if typ == rfc2865::USER_PASSWORD_TYPE || typ == rfc2869::MESSAGE_AUTHENTICATOR_TYPE {
+            continue;
+        }
+
+        for attribute in from.lookup_all(typ) {
+            let value = attribute.encode_bytes();
+            let value = match typ {
+                // The value is prefixed by a tag preceding the salt
+                rfc2868::TUNNEL_PASSWORD_TYPE => value.split_first()
+                    .and_then(|(tag, value)| Some([&[*tag][..], &reencrypt(value, from_key, to_key)?].concat())),
+                rfc2865::VENDOR_SPECIFIC_TYPE => reencrypt_vendor_specific(&value, from_key, to_key),
+                _ => Some(value),
+            };
+
+            match value {
+                Some(value) => attributes.push(AVP::from_bytes(typ, &value)),
+                None => warn!("Dropping malformed attribute {} while proxying", typ),
+            }
+        }
+    }
+
+    to.extend(attributes);
+}
+
+impl Upstream {
+    /// Relays the request to the upstream server and translates the upstream response back
+    pub async fn forward(&self, request: &Packet) -> Result<Packet> {
+        let mut upstream = Packet::new(request.get_code(), &self.secret);
+        copy_attributes(request, request.get_authenticator(), &mut upstream);
+
+        // The password is hidden using the secret and must be encoded again for the upstream server
+        if let Some(password) = rfc2865::lookup_user_password(request) {
+            rfc2865::add_user_password(&mut upstream, &password?)?;
+        }
+
+        // Without an explicit challenge, CHAP uses the request authenticator which differs for the upstream request
+        if request.lookup(rfc2865::CHAP_PASSWORD_TYPE).is_some() && request.lookup(rfc2865::CHAP_CHALLENGE_TYPE).is_none() {
+            rfc2865::add_chap_challenge(&mut upstream, request.get_authenticator());
+        }
+
+        if request.lookup(rfc2869::MESSAGE_AUTHENTICATOR_TYPE).is_some() {
+            add_message_authenticator(&mut upstream);
+        }
+
+        let timeout = Some(Duration::from_secs(self.timeout));
+        let response = Client::new(timeout, timeout)
+            .send_packet(&self.server, &upstream).await
+            .map_err(|err| anyhow!("Proxying to {} failed: {}", self.server, err))?;
+
+        let code = match response.get_code() {
+            code @ (Code::AccessAccept | Code::AccessReject | Code::AccessChallenge) => code,
+            code => return Err(anyhow!("Unexpected response from {}: {:?}", self.server, code)),
+        };
+
+        let mut result = request.make_response_packet(code);
+        copy_attributes(&response, upstream.get_authenticator(), &mut result);
+
+        if response.lookup(rfc2869::MESSAGE_AUTHENTICATOR_TYPE).is_some() {
+            add_message_authenticator(&mut result);
+        }
+
+        return Ok(result);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use radius::core::tag::Tag;
+
+    use super::*;
+    use crate::radius::strict::validate_message_authenticator;
+
+    #[test]
+    fn test_copy_attributes() {
+        let mut request = Packet::new(Code::AccessRequest, b"client");
+        rfc2868::add_tunnel_password(&mut request, Some(&Tag::new(1)), b"narf").unwrap();
+
+        // The MS-MPPE keys are hidden like Tunnel-Password without the tag
+        let key = AVP::from_tunnel_password(rfc2868::TUNNEL_PASSWORD_TYPE, None, b"zort", b"client", request.get_authenticator()).unwrap()
+            .encode_bytes();
+        let vendor = [&VENDOR_MICROSOFT.to_be_bytes()[..], &[MS_MPPE_RECV_KEY, key.len() as u8 + 1], &key[1..]].concat();
+        request.add(AVP::from_bytes(rfc2865::VENDOR_SPECIFIC_TYPE, &vendor));
+
+        let mut upstream = Packet::new(Code::AccessRequest, b"upstream");
+        copy_attributes(&request, request.get_authenticator(), &mut upstream);
+
+        let (password, tag) = rfc2868::lookup_tunnel_password(&upstream).unwrap().unwrap();
+        assert_eq!(password, b"narf");
+        assert_eq!(tag, Tag::new(1));
+
+        let vendor = upstream.lookup(rfc2865::VENDOR_SPECIFIC_TYPE).unwrap().encode_bytes();
+        assert_eq!(vendor[..6], [0, 0, 1, 55, MS_MPPE_RECV_KEY, key.len() as u8 + 1]);
+        let key = AVP::from_bytes(rfc2868::TUNNEL_PASSWORD_TYPE, &[&[0][..], &vendor[6..]].concat())
+            .encode_tunnel_password(b"upstream", upstream.get_authenticator()).unwrap();
+        assert_eq!(key.0, b"zort");
+
+        add_message_authenticator(&mut upstream);
+        assert_eq!(validate_message_authenticator(&upstream), Ok(()));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,216 @@
+use std::fmt;
+
+use radius::core::avp::AVPType;
+use radius::core::code::Code;
+use radius::core::packet::Packet;
+use radius::core::{rfc2865, rfc2866, rfc2869, rfc3162};
+
+/// Reason for discarding a packet in strict parsing mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Malformed {
+    AttributeLength(AVPType),
+    AttributeText(AVPType),
+    AttributeRepeated(AVPType),
+    MissingUserName,
+    MissingCredentials,
+    AmbiguousCredentials,
+    MissingNasIdentification,
+    MessageAuthenticator,
+}
+
+impl Malformed {
+    /// Short reason used as label for the malformed packet counter
+    pub fn reason(&self) -> &'static str {
+        return match self {
+            Malformed::AttributeLength(_) => "attribute-length",
+            Malformed::AttributeText(_) => "attribute-text",
+            Malformed::AttributeRepeated(_) => "attribute-repeated",
+            Malformed::MissingUserName => "missing-user-name",
+            Malformed::MissingCredentials => "missing-credentials",
+            Malformed::AmbiguousCredentials => "ambiguous-credentials",
+            Malformed::MissingNasIdentification => "missing-nas-identification",
+            Malformed::MessageAuthenticator => "message-authenticator",
+        };
+    }
+}
+
+impl fmt::Display for Malformed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Malformed::AttributeLength(typ) => write!(f, "invalid length of attribute {}", typ),
+            Malformed::AttributeText(typ) => write!(f, "invalid UTF-8 in attribute {}", typ),
+            Malformed::AttributeRepeated(typ) => write!(f, "attribute {} occurs multiple times", typ),
+            Malformed::MissingUserName => write!(f, "missing User-Name"),
+            Malformed::MissingCredentials => write!(f, "missing User-Password, CHAP-Password or EAP-Message"),
+            Malformed::AmbiguousCredentials => write!(f, "multiple of User-Password, CHAP-Password and EAP-Message"),
+            Malformed::MissingNasIdentification => write!(f, "missing NAS-IP-Address, NAS-IPv6-Address and NAS-Identifier"),
+            Malformed::MessageAuthenticator => write!(f, "invalid Message-Authenticator"),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Text,
+    Integer,
+    Address,
+    Ipv6Address,
+    Bytes(usize, usize),
+}
+
+/// Value format of attributes processed by the handler or echoed back to the NAS
+const ATTRIBUTES: &[(AVPType, Kind, bool)] = &[
+    (rfc2865::USER_NAME_TYPE, Kind::Text, true),
+    (rfc2865::USER_PASSWORD_TYPE, Kind::Bytes(16, 128), true),
+    (rfc2865::CHAP_PASSWORD_TYPE, Kind::Bytes(17, 17), true),
+    (rfc2865::NAS_IP_ADDRESS_TYPE, Kind::Address, true),
+    (rfc2865::NAS_PORT_TYPE, Kind::Integer, true),
+    (rfc2865::SERVICE_TYPE_TYPE, Kind::Integer, true),
+    (rfc2865::FRAMED_PROTOCOL_TYPE, Kind::Integer, true),
+    (rfc2865::FRAMED_IP_ADDRESS_TYPE, Kind::Address, true),
+    (rfc2865::STATE_TYPE, Kind::Bytes(1, 253), true),
+    (rfc2865::CLASS_TYPE, Kind::Bytes(1, 253), false),
+    (rfc2865::CALLED_STATION_ID_TYPE, Kind::Text, true),
+    (rfc2865::CALLING_STATION_ID_TYPE, Kind::Text, true),
+    (rfc2865::NAS_IDENTIFIER_TYPE, Kind::Text, true),
+    (rfc2865::NAS_PORT_TYPE_TYPE, Kind::Integer, true),
+    (rfc2866::ACCT_STATUS_TYPE_TYPE, Kind::Integer, true),
+    (rfc2866::ACCT_SESSION_ID_TYPE, Kind::Text, true),
+    (rfc2869::EAP_MESSAGE_TYPE, Kind::Bytes(1, 253), false),
+    (rfc2869::MESSAGE_AUTHENTICATOR_TYPE, Kind::Bytes(16, 16), true),
+    (rfc3162::NAS_IPV6_ADDRESS_TYPE, Kind::Ipv6Address, true),
+];
+
+fn validate_attributes(packet: &Packet) -> Result<(), Malformed> {
+    for (typ, kind, single) in ATTRIBUTES.iter().copied() {
+        let values = packet.lookup_all(typ);
+
+        if single && values.len() > 1 {
+            return Err(Malformed::AttributeRepeated(typ));
+        }
+
+        for value in values {
+            let value = value.encode_bytes();
+
+            let valid = match kind {
+                Kind::Text => !value.is_empty(),
+                Kind::Integer => value.len() == 4,
+                Kind::Address => value.len() == 4,
+                Kind::Ipv6Address => value.len() == 16,
+                Kind::Bytes(min, max) => value.len() >= min && value.len() <= max,
+            };
+            if !valid {
+                return Err(Malformed::AttributeLength(typ));
+            }
+
+            if typ == rfc2865::USER_PASSWORD_TYPE && value.len() % 16 != 0 {
+                return Err(Malformed::AttributeLength(typ));
+            }
+
+            if matches!(kind, Kind::Text) && std::str::from_utf8(&value).is_err() {
+                return Err(Malformed::AttributeText(typ));
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+/// HMAC-MD5 as used for the Message-Authenticator (RFC 2104)
+pub fn hmac_md5(key: &[u8], data: &[u8]) -> [u8; 16] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..16].copy_from_slice(&md5::compute(key).0);
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner = md5::compute([&block.map(|b| b ^ 0x36)[..], data].concat());
+    let outer = md5::compute([&block.map(|b| b ^ 0x5c)[..], &inner.0[..]].concat());
+    return outer.0;
+}
+
+/// Verifies the Message-Authenticator of an Access-Request (RFC 3579) if present
+pub fn validate_message_authenticator(packet: &Packet) -> Result<(), Malformed> {
+    let expected = match packet.lookup(rfc2869::MESSAGE_AUTHENTICATOR_TYPE) {
+        Some(expected) => expected.encode_bytes(),
+        None => return Ok(()),
+    };
+
+    // Re-encoding an Access-Request keeps authenticator and attribute order as received
+    let mut data = packet.encode()
+        .map_err(|_| Malformed::MessageAuthenticator)?;
+
+    // Calculation is done with the value of the Message-Authenticator itself set to zero
+    let mut offset = 20;
+    while offset + 2 <= data.len() {
+        let (typ, len) = (data[offset], data[offset + 1] as usize);
+        if len < 2 || offset + len > data.len() {
+            return Err(Malformed::MessageAuthenticator);
+        }
+        if typ == rfc2869::MESSAGE_AUTHENTICATOR_TYPE {
+            data[offset + 2..offset + len].fill(0);
+        }
+        offset += len;
+    }
+
+    if hmac_md5(packet.get_secret(), &data)[..] != expected[..] {
+        return Err(Malformed::MessageAuthenticator);
+    }
+
+    return Ok(());
+}
+
+/// Adds a Message-Authenticator calculated with the secret of the packet (RFC 3579)
+pub fn add_message_authenticator(packet: &mut Packet) {
+    // The authenticator is calculated over the packet holding the request authenticator and a zeroed value of itself
+    rfc2869::delete_message_authenticator(packet);
+    rfc2869::add_message_authenticator(packet, &[0u8; 16]);
+    if let Ok(mut data) = packet.encode() {
+        data[4..20].copy_from_slice(packet.get_authenticator());
+
+        rfc2869::delete_message_authenticator(packet);
+        rfc2869::add_message_authenticator(packet, &hmac_md5(packet.get_secret(), &data));
+    }
+}
+
+/// Checks a received packet for well-formed attributes and the presence of mandatory attributes
+pub fn validate(packet: &Packet) -> Result<(), Malformed> {
+    validate_attributes(packet)?;
+
+    if packet.get_code() == Code::AccessRequest {
+        if packet.lookup(rfc2865::USER_NAME_TYPE).is_none() {
+            return Err(Malformed::MissingUserName);
+        }
+
+        let credentials = [rfc2865::USER_PASSWORD_TYPE, rfc2865::CHAP_PASSWORD_TYPE, rfc2869::EAP_MESSAGE_TYPE].iter()
+            .filter(|typ| packet.lookup(**typ).is_some())
+            .count();
+        match credentials {
+            0 => return Err(Malformed::MissingCredentials),
+            1 => {}
+            _ => return Err(Malformed::AmbiguousCredentials),
+        }
+
+        if [rfc2865::NAS_IP_ADDRESS_TYPE, rfc3162::NAS_IPV6_ADDRESS_TYPE, rfc2865::NAS_IDENTIFIER_TYPE].iter()
+            .all(|typ| packet.lookup(*typ).is_none()) {
+            return Err(Malformed::MissingNasIdentification);
+        }
+
+        validate_message_authenticator(packet)?;
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hmac_md5() {
+        // Test case 1 from RFC 2202
+        assert_eq!(hmac_md5(&[0x0b; 16], b"Hi There"),
+                   [0x92, 0x94, 0x72, 0x7a, 0x36, 0x38, 0xbb, 0x1c, 0x13, 0xf4, 0x8e, 0xf8, 0x15, 0x8b, 0xfc, 0x9d]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,43 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Secret shared by the primary and all secondaries to authenticate each other
+    pub secret: String,
+
+    /// Address accepting secondaries following this instance
+    pub listen: Option<SocketAddr>,
+
+    /// Address of the primary to follow, turning this instance into a read-only secondary
+    pub primary: Option<String>,
+
+    /// PEM encoded certificate chain presented to secondaries, required for listening
+    pub certificate: Option<PathBuf>,
+
+    /// PEM encoded private key of the certificate
+    pub key: Option<PathBuf>,
+
+    /// PEM encoded CA certificates used to verify the primary, required for following
+    pub ca: Option<PathBuf>,
+
+    /// Seconds without any message from the other side after which the connection is considered broken
+    #[serde(default = "Config::default_timeout")]
+    pub timeout: u64,
+
+    /// Seconds to wait before connecting to the primary again
+    #[serde(default = "Config::default_retry")]
+    pub retry: u64,
+}
+
+impl Config {
+    fn default_timeout() -> u64 {
+        return 60;
+    }
+
+    fn default_retry() -> u64 {
+        return 10;
+    }
+}
\ No newline at end of file
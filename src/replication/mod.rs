@@ -0,0 +1,440 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use data_encoding::HEXLOWER;
+use futures::{SinkExt, StreamExt};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tokio_rustls::rustls::ServerName;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::database::{Actor, hmac_sha1, Replica, SharedDatabase};
+use crate::ldap;
+
+pub use self::config::Config;
+
+mod config;
+
+/// Maximum size of a single message, which must hold the complete content of the data directory
+const MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Maximum size of a message before the other side has been authenticated
+const MAX_HANDSHAKE_SIZE: usize = 4 * 1024;
+
+/// Roles mixed into the responses to challenges and the hashes of messages, so neither can be reflected to the other side
+const PRIMARY: &[u8] = b"primary";
+const SECONDARY: &[u8] = b"secondary";
+
+/// Mixed into the key derived for the messages following the handshake
+const SESSION: &[u8] = b"session";
+
+/// Size of the keyed hash authenticating every message
+const MAC_SIZE: usize = 20;
+
+#[derive(Debug, Deserialize, Serialize)]
+enum Message {
+    /// Sent by the primary to a connected secondary
+    Hello {
+        challenge: String,
+    },
+
+    /// Sent by the secondary to authenticate and to announce the last received snapshot if any
+    Follow {
+        response: String,
+        challenge: String,
+        epoch: Option<Uuid>,
+        sequence: u64,
+    },
+
+    /// Sent by the primary to authenticate after the secondary has been authenticated
+    Welcome {
+        response: String,
+    },
+
+    /// Complete content of the primary, sent whenever it has changed
+    Snapshot {
+        epoch: Uuid,
+        sequence: u64,
+        replica: Box<Replica>,
+    },
+
+    /// Sent by the primary while nothing changes to keep the connection alive
+    Heartbeat,
+}
+
+/// Connection authenticating every message by a keyed hash prefixed to its frame
+///
+/// The hash covers the role of the sender and the number of messages it has sent before, so messages can neither be
+/// reflected nor replayed or reordered. The handshake is keyed by the secret while all later messages are keyed by a
+/// key derived from the challenges of both sides, so messages of former connections are not accepted either.
+struct Connection<S> {
+    framed: Framed<S, LengthDelimitedCodec>,
+
+    key: Vec<u8>,
+
+    /// Roles of this side and of the other side
+    role: &'static [u8],
+    peer: &'static [u8],
+
+    /// Number of messages sent and received so far
+    sent: u64,
+    received: u64,
+}
+
+impl<S> Connection<S>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn new(stream: S, config: &Config, role: &'static [u8], peer: &'static [u8]) -> Self {
+        return Self {
+            framed: Framed::new(stream, LengthDelimitedCodec::builder()
+                .max_frame_length(MAX_HANDSHAKE_SIZE + MAC_SIZE)
+                .new_codec()),
+            key: config.secret.as_bytes().to_vec(),
+            role,
+            peer,
+            sent: 0,
+            received: 0,
+        };
+    }
+
+    /// Switches to the key of the session after both sides have been authenticated, which may send snapshots from now
+    fn establish(&mut self, config: &Config, primary: &str, secondary: &str) {
+        self.key = hmac_sha1(config.secret.as_bytes(), &[SESSION, primary.as_bytes(), secondary.as_bytes()].concat()).to_vec();
+        self.framed.codec_mut().set_max_frame_length(MAX_MESSAGE_SIZE + MAC_SIZE);
+    }
+
+    fn mac(&self, role: &[u8], counter: u64, payload: &[u8]) -> [u8; MAC_SIZE] {
+        return hmac_sha1(&self.key, &[role, &counter.to_be_bytes(), payload].concat());
+    }
+
+    async fn send(&mut self, message: &Message) -> Result<()> {
+        let message = serde_yaml::to_string(message)
+            .context("Serializing message")?;
+
+        let mac = self.mac(self.role, self.sent, message.as_bytes());
+        self.sent += 1;
+
+        return self.framed.send(Bytes::from([&mac, message.as_bytes()].concat())).await
+            .context("Sending message");
+    }
+
+    async fn receive(&mut self, timeout: Duration) -> Result<Message> {
+        let frame = tokio::time::timeout(timeout, self.framed.next()).await
+            .context("Timeout while waiting for message")?
+            .context("Connection closed")?
+            .context("Receiving message")?;
+
+        if frame.len() < MAC_SIZE {
+            bail!("Truncated message");
+        }
+        let (mac, message) = frame.split_at(MAC_SIZE);
+
+        // Compared in constant time to not reveal how much of the hash matches
+        let expected = self.mac(self.peer, self.received, message);
+        if mac.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) != 0 {
+            bail!("Invalid message authentication");
+        }
+        self.received += 1;
+
+        return serde_yaml::from_slice(message)
+            .context("Parsing message");
+    }
+}
+
+fn challenge() -> String {
+    let mut challenge = [0u8; 16];
+    OsRng.fill_bytes(&mut challenge);
+    return HEXLOWER.encode(&challenge);
+}
+
+/// Proves knowledge of the secret by a keyed hash of the challenge made by the other side
+fn respond(config: &Config, role: &[u8], challenge: &str) -> String {
+    return HEXLOWER.encode(&hmac_sha1(config.secret.as_bytes(), &[role, challenge.as_bytes()].concat()));
+}
+
+/// Sends a snapshot to an authenticated secondary whenever the database changes
+async fn serve_secondary(socket: TcpStream, acceptor: TlsAcceptor, config: Arc<Config>, database: Arc<SharedDatabase>) -> Result<()> {
+    let timeout = Duration::from_secs(config.timeout);
+
+    let socket = tokio::time::timeout(timeout, acceptor.accept(socket)).await
+        .context("Timeout during TLS handshake")?
+        .context("TLS handshake")?;
+    let mut connection = Connection::new(socket, &config, PRIMARY, SECONDARY);
+
+    let challenge = challenge();
+    connection.send(&Message::Hello { challenge: challenge.clone() }).await?;
+
+    let (mut last, response, theirs) = match connection.receive(timeout).await? {
+        Message::Follow { response, challenge: theirs, epoch, sequence } if response == respond(&config, SECONDARY, &challenge) => {
+            ((epoch, sequence), respond(&config, PRIMARY, &theirs), theirs)
+        }
+        Message::Follow { .. } => bail!("Invalid secret"),
+        _ => bail!("Unexpected message"),
+    };
+
+    connection.send(&Message::Welcome { response }).await?;
+    connection.establish(&config, &challenge, &theirs);
+
+    // Subscribing before taking the snapshot ensures no change is missed in between
+    let mut changes = database.subscribe();
+
+    loop {
+        let snapshot = database.snapshot();
+        let current = (Some(database.epoch()), snapshot.sequence());
+
+        if current != last {
+            debug!("Sending snapshot {}", snapshot.sequence());
+            connection.send(&Message::Snapshot {
+                epoch: database.epoch(),
+                sequence: snapshot.sequence(),
+                replica: Box::new(snapshot.replica(false)),
+            }).await?;
+            last = current;
+        }
+
+        match tokio::time::timeout(timeout / 3, changes.changed()).await {
+            Ok(Ok(())) => continue,
+            Ok(Err(_)) => return Ok(()),
+            Err(_) => connection.send(&Message::Heartbeat).await?,
+        }
+    }
+}
+
+async fn primary(config: Arc<Config>, database: Arc<SharedDatabase>) -> Result<()> {
+    let listen = if let Some(listen) = config.listen { listen } else {
+        return Ok(());
+    };
+
+    let acceptor = ldap::acceptor(config.certificate.as_deref().context("Certificate required to serve secondaries")?,
+                                  config.key.as_deref().context("Key required to serve secondaries")?,
+                                  None).await?;
+
+    let listener = TcpListener::bind(listen).await
+        .with_context(|| format!("Listening on {}", listen))?;
+
+    loop {
+        match listener.accept().await {
+            Ok((socket, addr)) => {
+                info!("Secondary connected from {}", addr);
+
+                let acceptor = acceptor.clone();
+                let config = config.clone();
+                let database = database.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = serve_secondary(socket, acceptor, config, database).await {
+                        warn!("Replication to {} failed: {:#}", addr, err);
+                    }
+                });
+            }
+
+            Err(err) => {
+                error!("Failed to accept replication connection: {}", err);
+            }
+        }
+    }
+}
+
+/// Follows the primary until the connection breaks, remembering the last applied snapshot
+async fn follow(config: &Config,
+                address: &str,
+                connector: &TlsConnector,
+                database: &SharedDatabase,
+                last: &mut (Option<Uuid>, u64)) -> Result<()> {
+    let timeout = Duration::from_secs(config.timeout);
+
+    // Addresses of IPv6 literals are enclosed in brackets
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host)
+        .trim_start_matches('[').trim_end_matches(']');
+    let name = ServerName::try_from(host)
+        .map_err(|_| anyhow!("Invalid server name: {}", host))?;
+
+    let socket = tokio::time::timeout(timeout, TcpStream::connect(address)).await
+        .context("Timeout while connecting")?
+        .with_context(|| format!("Connecting to {}", address))?;
+    let socket = tokio::time::timeout(timeout, connector.connect(name, socket)).await
+        .context("Timeout during TLS handshake")?
+        .context("TLS handshake")?;
+    let mut connection = Connection::new(socket, config, SECONDARY, PRIMARY);
+
+    let (response, theirs) = match connection.receive(timeout).await? {
+        Message::Hello { challenge } => (respond(config, SECONDARY, &challenge), challenge),
+        _ => bail!("Unexpected message"),
+    };
+
+    let challenge = challenge();
+    connection.send(&Message::Follow {
+        response,
+        challenge: challenge.clone(),
+        epoch: last.0,
+        sequence: last.1,
+    }).await?;
+
+    match connection.receive(timeout).await? {
+        Message::Welcome { response } if response == respond(config, PRIMARY, &challenge) => {}
+        Message::Welcome { .. } => bail!("Invalid secret"),
+        _ => bail!("Unexpected message"),
+    }
+
+    connection.establish(config, &theirs, &challenge);
+
+    info!("Following primary {}", address);
+
+    let actor = Actor {
+        protocol: "replication",
+        identity: "primary".to_owned(),
+        source: address.to_owned(),
+    };
+
+    loop {
+        match connection.receive(timeout).await? {
+            Message::Snapshot { epoch, sequence, replica } => {
+                let changed = database.write().await.replicate(&actor, *replica).await?;
+                info!("Replicated snapshot {} from {}, changed {} entries", sequence, address, changed);
+
+                *last = (Some(epoch), sequence);
+            }
+
+            Message::Heartbeat => {}
+
+            _ => bail!("Unexpected message"),
+        }
+    }
+}
+
+async fn secondary(config: Arc<Config>, database: Arc<SharedDatabase>) -> Result<()> {
+    let address = if let Some(address) = &config.primary { address } else {
+        return Ok(());
+    };
+
+    let connector = ldap::connector(config.ca.as_deref()).await
+        .context("Verifying the primary")?;
+
+    let mut last = (None, 0);
+    loop {
+        if let Err(err) = follow(&config, address, &connector, &database, &mut last).await {
+            warn!("Lost primary {}: {:#}", address, err);
+        }
+
+        tokio::time::sleep(Duration::from_secs(config.retry)).await;
+    }
+}
+
+/// Replicates the database from a primary instance to secondary instances
+///
+/// An instance serves secondaries if listening and follows a primary if one is given, which makes its database
+/// read-only for clients. Instances can do both to be chained. The complete content is sent whenever it has changed,
+/// while the secondary only writes the entities which differ. Tenants are not replicated.
+pub async fn serve(config: Option<Config>,
+                   database: Arc<SharedDatabase>,
+                   shutdown: impl Future) -> Result<()> {
+    let config = if let Some(config) = config { Arc::new(config) } else {
+        return Ok(());
+    };
+
+    if config.primary.is_some() {
+        database.set_read_only();
+    }
+
+    let replicate = async {
+        return tokio::try_join!(primary(config.clone(), database.clone()), secondary(config.clone(), database.clone()));
+    };
+
+    tokio::select! {
+         _ = shutdown => {
+            info!("Replication is shutting down");
+            return Ok(());
+        }
+
+        res = replicate => {
+            return res.map(|_| ());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    fn config() -> Config {
+        return Config {
+            secret: "secret".to_owned(),
+            listen: None,
+            primary: None,
+            certificate: None,
+            key: None,
+            ca: None,
+            timeout: 1,
+            retry: 1,
+        };
+    }
+
+    /// Connection of the secondary and the raw frames it sends to the primary
+    fn secondary() -> (Connection<DuplexStream>, Framed<DuplexStream, LengthDelimitedCodec>) {
+        let (local, remote) = tokio::io::duplex(MAX_HANDSHAKE_SIZE);
+        return (Connection::new(local, &config(), SECONDARY, PRIMARY), Framed::new(remote, LengthDelimitedCodec::new()));
+    }
+
+    /// Connection of the primary receiving the given raw frames
+    async fn primary(frames: Vec<Bytes>) -> Connection<DuplexStream> {
+        let (local, remote) = tokio::io::duplex(MAX_HANDSHAKE_SIZE);
+        let mut remote = Framed::new(remote, LengthDelimitedCodec::new());
+        for frame in frames {
+            remote.send(frame).await.unwrap();
+        }
+        return Connection::new(local, &config(), PRIMARY, SECONDARY);
+    }
+
+    fn rejected(result: Result<Message>) -> bool {
+        return matches!(result, Err(err) if err.to_string() == "Invalid message authentication");
+    }
+
+    #[tokio::test]
+    async fn test_connection() {
+        let timeout = Duration::from_secs(1);
+
+        let (mut connection, mut frames) = secondary();
+        connection.send(&Message::Heartbeat).await.unwrap();
+        connection.send(&Message::Heartbeat).await.unwrap();
+        let first = frames.next().await.unwrap().unwrap().freeze();
+        let second = frames.next().await.unwrap().unwrap().freeze();
+
+        let mut connection = primary(vec![first.clone(), second.clone()]).await;
+        assert!(matches!(connection.receive(timeout).await, Ok(Message::Heartbeat)));
+        assert!(matches!(connection.receive(timeout).await, Ok(Message::Heartbeat)));
+
+        // Replayed and reordered messages do not match the number of received messages
+        let mut connection = primary(vec![first.clone(), first.clone()]).await;
+        assert!(connection.receive(timeout).await.is_ok());
+        assert!(rejected(connection.receive(timeout).await));
+
+        let mut connection = primary(vec![second.clone()]).await;
+        assert!(rejected(connection.receive(timeout).await));
+
+        // Messages are bound to the role of the sender
+        let (local, remote) = tokio::io::duplex(MAX_HANDSHAKE_SIZE);
+        let mut reflected = Connection::new(remote, &config(), PRIMARY, SECONDARY);
+        Connection::new(local, &config(), PRIMARY, SECONDARY).send(&Message::Heartbeat).await.unwrap();
+        assert!(rejected(reflected.receive(timeout).await));
+
+        let mut tampered = first.to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        let mut connection = primary(vec![Bytes::from(tampered)]).await;
+        assert!(rejected(connection.receive(timeout).await));
+
+        // Messages of the handshake are keyed by the secret, all later ones by the key of the session
+        let mut connection = primary(vec![first]).await;
+        connection.establish(&config(), "primary", "secondary");
+        assert!(rejected(connection.receive(timeout).await));
+    }
+}
\ No newline at end of file